@@ -1,6 +1,7 @@
 use std::{io, fmt, thread, time};
 use std::io::Read;
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
 use flate2::read::GzDecoder;
@@ -39,8 +40,13 @@ pub enum Error {
     OpenSslError(openssl::error::ErrorStack),
     GzipError(::std::io::Error),
     HyperError(hyper::Error),
+    IoError(::std::io::Error),
     JsonError(serde_json::Error),
     Unauthorized, // a generic "unauthorized" error
+    // a write lost to a concurrent change; the details are up to the service, e.g.
+    // `svc::datastore::VersionConflict`
+    Conflict(Box<::std::error::Error + Send + Sync>),
+    InvalidArgument(String), // a request that was rejected before being sent
 }
 
 impl fmt::Display for Error {
@@ -49,9 +55,24 @@ impl fmt::Display for Error {
             Error::ApiError(ref e) => write!(f, "ApiError {:?}", e),
             Error::GzipError(ref e) => write!(f, "GzipError {:?}", e),
             Error::HyperError(ref e) => write!(f, "HyperError {:?}", e),
+            Error::IoError(ref e) => write!(f, "IoError {:?}", e),
             Error::JsonError(ref e) => write!(f, "JsonError {:?}", e),
             Error::OpenSslError(ref e) => write!(f, "OpenSslError {:?}", e),
             Error::Unauthorized => write!(f, "Unauthorized"),
+            Error::Conflict(ref e) => write!(f, "Conflict {}", e),
+            Error::InvalidArgument(ref e) => write!(f, "InvalidArgument {}", e),
+        }
+    }
+}
+
+impl Error {
+    /// The canonical status of an api error, e.g. `ABORTED` or `NOT_FOUND`.
+    pub fn status(&self) -> Option<&str> {
+        match *self {
+            Error::ApiError(ApiError { error: Some(ref details), .. }) => {
+                details.status.as_ref().map(|s| s.as_str())
+            }
+            _ => None,
         }
     }
 }
@@ -70,11 +91,36 @@ pub struct ErrorDetails {
     pub status: Option<String>,
 }
 
+/// The credentials used to authorize a hub's requests.
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    /// The client's own credentials, with token caching; the default for every hub.
+    Client,
+    /// An end-user OAuth access token.
+    User(String),
+    /// A token obtained through `TokenInfoService::delegate`.
+    Delegated(auth::Token),
+}
+
+impl From<auth::Token> for Credentials {
+    fn from(token: auth::Token) -> Self {
+        Credentials::Delegated(token)
+    }
+}
+
+/// Handles requests in place of the network, e.g. an in-memory fake of a service
+/// such as `svc::datastore::fake::FakeDatastore`.
+pub trait Transport: Send + Sync {
+    // returns the status code and (json) body of the response
+    fn handle(&self, method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>);
+}
+
 #[derive(Clone)]
 pub struct GoogleCloudClient {
     project_id: String,
     remote: reactor::Remote,
     auth: auth::GoogleCloudAuth,
+    transport: Option<Arc<Transport>>,
 }
 
 impl GoogleCloudClient {
@@ -83,11 +129,23 @@ impl GoogleCloudClient {
             project_id: project_id.to_string(),
             remote: CORE_THREAD__REMOTE.1.clone(),
             auth: auth::default_credentials(),
+            transport: None,
         })
     }
+
+    /// A client whose requests are all handled by `transport`, without credentials.
+    pub fn with_transport(project_id: &str, transport: Arc<Transport>) -> Self {
+        GoogleCloudClient {
+            project_id: project_id.to_string(),
+            remote: CORE_THREAD__REMOTE.1.clone(),
+            auth: auth::anonymous_credentials(),
+            transport: Some(transport),
+        }
+    }
     pub fn hub<S>(&self) -> Hub<S> {
         Hub {
             client: self,
+            credentials: Credentials::Client,
             _service: PhantomData,
         }
     }
@@ -95,6 +153,7 @@ impl GoogleCloudClient {
 
 pub struct Hub<'a, S> {
     client: &'a GoogleCloudClient,
+    credentials: Credentials,
     _service: PhantomData<S>,
 }
 
@@ -102,6 +161,20 @@ impl<'a, S> Hub<'a, S> {
     pub fn project_id(&self) -> &str {
         &self.client.project_id
     }
+
+    /// Returns a hub for the same service whose requests use `credentials`, e.g.
+    /// `hub.with_credentials(Credentials::User(token)).list_tables(..)`.
+    pub fn with_credentials(&self, credentials: Credentials) -> Hub<'a, S> {
+        Hub {
+            client: self.client,
+            credentials: credentials,
+            _service: PhantomData,
+        }
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
 }
 
 impl<'a> Hub<'a, ::svc::tokeninfo::TokenInfoService> {
@@ -121,7 +194,11 @@ impl<'a> Hub<'a, ::svc::tokeninfo::TokenInfoService> {
 
 impl<'a, S> ApiClient for Hub<'a, S> {
     fn token(&self, scopes: &[String]) -> Result<auth::Token> {
-        self.client.auth.token(self, scopes)
+        match self.credentials {
+            Credentials::Client => self.client.auth.token(self, scopes),
+            Credentials::User(ref access_token) => Ok(auth::Token::bearer(access_token)),
+            Credentials::Delegated(ref token) => Ok(token.clone()),
+        }
     }
     fn request<D: 'static + Send>(
         &self,
@@ -139,6 +216,15 @@ impl<'a, S> ApiClient for Hub<'a, S> {
             "Sigma Computing, Inc. (gzip)",
         ));
 
+        if let Some(ref transport) = self.client.transport {
+            let method = r.method().clone();
+            let uri = r.uri().clone();
+            let body = r.body().concat2().wait().map_err(Error::HyperError)?;
+
+            let (status, body) = transport.handle(&method, &uri, &body);
+            return decode_response(status >= 200 && status < 300, hyper::Headers::new(), &body);
+        }
+
         trace!("send request: {:?}", r);
         let (tx, rx) = oneshot::channel();
 
@@ -173,17 +259,7 @@ impl<'a, S> ApiClient for Hub<'a, S> {
                         let as_str = unsafe { ::std::str::from_utf8_unchecked(&body) };
                         trace!("recv oneshot: {}", as_str);
 
-                        if status.is_success() {
-                            match serde_json::from_slice(&body) {
-                                Ok(res) => Ok((headers, res)),
-                                Err(e) => Err(Error::JsonError(e)),
-                            }
-                        } else {
-                            match serde_json::from_slice::<ApiError>(&body) {
-                                Ok(e) => Err(Error::ApiError(e)),
-                                Err(e) => Err(Error::JsonError(e)),
-                            }
-                        }
+                        decode_response(status.is_success(), headers, &body)
                     })
                     .map(|res| {
                         tx.send(res.map(|(headers, res)| (headers, res)))
@@ -195,6 +271,29 @@ impl<'a, S> ApiClient for Hub<'a, S> {
     }
 }
 
+fn decode_response<D>(
+    success: bool,
+    headers: hyper::Headers,
+    body: &[u8],
+) -> Result<(hyper::Headers, D)>
+where
+    for<'de> D: Deserialize<'de>,
+{
+    if success {
+        // deletes respond with an empty body
+        let body = if body.is_empty() { &b"{}"[..] } else { body };
+        match serde_json::from_slice(body) {
+            Ok(res) => Ok((headers, res)),
+            Err(e) => Err(Error::JsonError(e)),
+        }
+    } else {
+        match serde_json::from_slice::<ApiError>(body) {
+            Ok(e) => Err(Error::ApiError(e)),
+            Err(e) => Err(Error::JsonError(e)),
+        }
+    }
+}
+
 pub fn encode_query_params<'a, I>(i: I) -> String
 where
     I: IntoIterator<Item = (&'a str, String)>,
@@ -233,12 +332,37 @@ pub trait ApiClient {
         self.request(req).map(|(_, res)| res)
     }
 
+    // helper method for making a DELETE request
+    fn delete<D>(&self, uri: &hyper::Uri, scopes: &[String]) -> Result<D>
+    where
+        for<'de> D: 'static + Send + Deserialize<'de>,
+    {
+        let mut req = hyper::Request::new(hyper::Method::Delete, uri.clone());
+        req.headers_mut().set(self.token(scopes)?.into_header());
+
+        self.request(req).map(|(_, res)| res)
+    }
+
     // helper method for making a POST request with a JSON body
     fn post<B: Serialize, D>(&self, uri: &hyper::Uri, body: B, scopes: &[String]) -> Result<D>
     where
         for<'de> D: 'static + Send + Deserialize<'de>,
     {
-        let mut req = hyper::Request::new(hyper::Method::Post, uri.clone());
+        self.send(hyper::Method::Post, uri, body, scopes)
+    }
+
+    // helper method for making a request with a JSON body, e.g. a PUT or PATCH
+    fn send<B: Serialize, D>(
+        &self,
+        method: hyper::Method,
+        uri: &hyper::Uri,
+        body: B,
+        scopes: &[String],
+    ) -> Result<D>
+    where
+        for<'de> D: 'static + Send + Deserialize<'de>,
+    {
+        let mut req = hyper::Request::new(method, uri.clone());
         req.headers_mut().set(hyper::header::ContentType::json());
         req.headers_mut().set(self.token(scopes)?.into_header());
 
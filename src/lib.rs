@@ -11,6 +11,7 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 extern crate openssl;
+#[macro_use]
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -22,6 +23,6 @@ mod auth;
 mod client;
 pub mod svc;
 
-pub use client::{GoogleCloudClient, Hub};
+pub use client::{Credentials, GoogleCloudClient, Hub, Transport};
 pub use client::{Error, ApiError, ErrorDetails, Result};
 pub use auth::Token as BearerToken;
@@ -0,0 +1,613 @@
+// A minimal writer for the Arrow IPC streaming format:
+//
+// https://arrow.apache.org/docs/format/Columnar.html#ipc-streaming-format
+//
+// NOTE this only covers the handful of flat column types that the bigquery
+// exporter produces, and hand-encodes the flatbuffer metadata so that we don't
+// need to pull in the arrow crate (and its dependency tree) for it.
+use std::io::{self, Write};
+
+const CONTINUATION_MARKER: u32 = 0xFFFF_FFFF;
+const METADATA_VERSION_V5: i16 = 4;
+
+// MessageHeader union
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+// Type union
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_UTF8: u8 = 5;
+const TYPE_BOOL: u8 = 6;
+
+const PRECISION_DOUBLE: i16 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColumnType {
+    Int64,
+    Float64,
+    Bool,
+    Utf8,
+}
+
+#[derive(Debug)]
+pub enum Column {
+    Int64(Vec<Option<i64>>),
+    Float64(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+    Utf8(Vec<Option<String>>),
+}
+
+impl Column {
+    pub fn new(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::Int64 => Column::Int64(vec![]),
+            ColumnType::Float64 => Column::Float64(vec![]),
+            ColumnType::Bool => Column::Bool(vec![]),
+            ColumnType::Utf8 => Column::Utf8(vec![]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Column::Int64(ref v) => v.len(),
+            Column::Float64(ref v) => v.len(),
+            Column::Bool(ref v) => v.len(),
+            Column::Utf8(ref v) => v.len(),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        match *self {
+            Column::Int64(ref mut v) => v.truncate(len),
+            Column::Float64(ref mut v) => v.truncate(len),
+            Column::Bool(ref mut v) => v.truncate(len),
+            Column::Utf8(ref mut v) => v.truncate(len),
+        }
+    }
+
+    fn null_count(&self) -> usize {
+        match *self {
+            Column::Int64(ref v) => v.iter().filter(|v| v.is_none()).count(),
+            Column::Float64(ref v) => v.iter().filter(|v| v.is_none()).count(),
+            Column::Bool(ref v) => v.iter().filter(|v| v.is_none()).count(),
+            Column::Utf8(ref v) => v.iter().filter(|v| v.is_none()).count(),
+        }
+    }
+
+    // appends the validity bitmap followed by the value buffers of this column
+    fn write_buffers(&self, body: &mut Vec<u8>, buffers: &mut Vec<[i64; 2]>) {
+        let validity = match *self {
+            Column::Int64(ref v) => bitmap(v.iter().map(|v| v.is_some())),
+            Column::Float64(ref v) => bitmap(v.iter().map(|v| v.is_some())),
+            Column::Bool(ref v) => bitmap(v.iter().map(|v| v.is_some())),
+            Column::Utf8(ref v) => bitmap(v.iter().map(|v| v.is_some())),
+        };
+        push_buffer(body, buffers, &validity);
+
+        match *self {
+            Column::Int64(ref v) => {
+                let mut values = Vec::with_capacity(v.len() * 8);
+                for x in v {
+                    values.extend_from_slice(&le_u64(x.unwrap_or(0) as u64));
+                }
+                push_buffer(body, buffers, &values);
+            }
+            Column::Float64(ref v) => {
+                let mut values = Vec::with_capacity(v.len() * 8);
+                for x in v {
+                    values.extend_from_slice(&le_u64(x.unwrap_or(0.0).to_bits()));
+                }
+                push_buffer(body, buffers, &values);
+            }
+            Column::Bool(ref v) => {
+                let values = bitmap(v.iter().map(|v| v.unwrap_or(false)));
+                push_buffer(body, buffers, &values);
+            }
+            Column::Utf8(ref v) => {
+                let mut offsets = Vec::with_capacity((v.len() + 1) * 4);
+                let mut data = vec![];
+                offsets.extend_from_slice(&le_u32(0));
+                for x in v {
+                    if let Some(ref s) = *x {
+                        data.extend_from_slice(s.as_bytes());
+                    }
+                    offsets.extend_from_slice(&le_u32(data.len() as u32));
+                }
+                push_buffer(body, buffers, &offsets);
+                push_buffer(body, buffers, &data);
+            }
+        }
+    }
+}
+
+pub fn write_schema<W: Write>(w: &mut W, fields: &[(String, ColumnType)]) -> io::Result<()> {
+    let fields = fields
+        .iter()
+        .map(|&(ref name, ty)| {
+            let (type_type, type0) = match ty {
+                ColumnType::Int64 => (TYPE_INT, vec![(0, Fb::I32(64)), (1, Fb::Bool(true))]),
+                ColumnType::Float64 => (TYPE_FLOATING_POINT, vec![(0, Fb::I16(PRECISION_DOUBLE))]),
+                ColumnType::Bool => (TYPE_BOOL, vec![]),
+                ColumnType::Utf8 => (TYPE_UTF8, vec![]),
+            };
+            Fb::Table(vec![
+                (0, Fb::Str(name.clone())),
+                (1, Fb::Bool(true)),
+                (2, Fb::U8(type_type)),
+                (3, Fb::Table(type0)),
+                (5, Fb::Tables(vec![])),
+            ])
+        })
+        .collect();
+
+    let schema = Fb::Table(vec![(0, Fb::I16(0)), (1, Fb::Tables(fields))]);
+    write_message(w, HEADER_SCHEMA, schema, &[])
+}
+
+pub fn write_record_batch<W: Write>(w: &mut W, columns: &[Column]) -> io::Result<()> {
+    let length = columns.first().map_or(0, |c| c.len());
+
+    let mut body = vec![];
+    let mut nodes = vec![];
+    let mut buffers = vec![];
+    for column in columns {
+        debug_assert_eq!(column.len(), length);
+        nodes.push([column.len() as i64, column.null_count() as i64]);
+        column.write_buffers(&mut body, &mut buffers);
+    }
+
+    let batch = Fb::Table(vec![
+        (0, Fb::I64(length as i64)),
+        (1, Fb::Structs(nodes)),
+        (2, Fb::Structs(buffers)),
+    ]);
+    write_message(w, HEADER_RECORD_BATCH, batch, &body)
+}
+
+pub fn write_end_of_stream<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(&le_u32(CONTINUATION_MARKER))?;
+    w.write_all(&le_u32(0))
+}
+
+fn write_message<W: Write>(w: &mut W, header_type: u8, header: Fb, body: &[u8]) -> io::Result<()> {
+    let message = Fb::Table(vec![
+        (0, Fb::I16(METADATA_VERSION_V5)),
+        (1, Fb::U8(header_type)),
+        (2, header),
+        (3, Fb::I64(body.len() as i64)),
+    ]);
+
+    let mut metadata = FbBuilder::finish(&message);
+    pad_to_8(&mut metadata);
+
+    w.write_all(&le_u32(CONTINUATION_MARKER))?;
+    w.write_all(&le_u32(metadata.len() as u32))?;
+    w.write_all(&metadata)?;
+    w.write_all(body)
+}
+
+fn push_buffer(body: &mut Vec<u8>, buffers: &mut Vec<[i64; 2]>, bytes: &[u8]) {
+    buffers.push([body.len() as i64, bytes.len() as i64]);
+    body.extend_from_slice(bytes);
+    pad_to_8(body);
+}
+
+fn bitmap<I: Iterator<Item = bool>>(bits: I) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().unwrap() |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+fn pad_to_8(buf: &mut Vec<u8>) {
+    while buf.len() % 8 != 0 {
+        buf.push(0);
+    }
+}
+
+fn le_u32(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+fn le_u64(v: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (v >> (8 * i)) as u8;
+    }
+    bytes
+}
+
+//
+// flatbuffer encoding
+
+// A flatbuffer value; tables are a list of (field index, value) pairs.
+enum Fb {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Str(String),
+    Table(Vec<(u16, Fb)>),
+    Tables(Vec<Fb>),
+    // a vector of structs made up of two longs, e.g. FieldNode and Buffer
+    Structs(Vec<[i64; 2]>),
+}
+
+impl Fb {
+    // the inline size of this value within a table
+    fn inline_size(&self) -> usize {
+        match *self {
+            Fb::Bool(_) | Fb::U8(_) => 1,
+            Fb::I16(_) => 2,
+            Fb::I32(_) => 4,
+            Fb::I64(_) => 8,
+            Fb::Str(_) | Fb::Table(_) | Fb::Tables(_) | Fb::Structs(_) => 4,
+        }
+    }
+}
+
+// NOTE flatbuffers are normally built back-to-front; since all of our
+// metadata is known up front we instead lay it out front-to-back, writing each
+// table before its children so that every uoffset points forwards.
+struct FbBuilder {
+    buf: Vec<u8>,
+}
+
+impl FbBuilder {
+    fn finish(root: &Fb) -> Vec<u8> {
+        let mut builder = FbBuilder { buf: vec![0; 4] };
+        let pos = builder.write_child(root);
+        builder.patch_offset(0, pos);
+        builder.buf
+    }
+
+    fn align(&mut self, n: usize) {
+        while self.buf.len() % n != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) {
+        self.buf[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn patch_offset(&mut self, at: usize, target: usize) {
+        let offset = le_u32((target - at) as u32);
+        self.patch(at, &offset);
+    }
+
+    // writes a non-inline value and returns its position
+    fn write_child(&mut self, value: &Fb) -> usize {
+        match *value {
+            Fb::Table(ref fields) => self.write_table(fields),
+            Fb::Str(ref s) => {
+                self.align(4);
+                let pos = self.buf.len();
+                self.buf.extend_from_slice(&le_u32(s.len() as u32));
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+                pos
+            }
+            Fb::Tables(ref tables) => {
+                self.align(4);
+                let pos = self.buf.len();
+                self.buf.extend_from_slice(&le_u32(tables.len() as u32));
+                let slots = self.buf.len();
+                self.buf.resize(slots + 4 * tables.len(), 0);
+                for (i, table) in tables.iter().enumerate() {
+                    let child = self.write_child(table);
+                    self.patch_offset(slots + 4 * i, child);
+                }
+                pos
+            }
+            Fb::Structs(ref structs) => {
+                // the elements (not the length prefix) must be 8-byte aligned
+                self.align(4);
+                if self.buf.len() % 8 == 0 {
+                    self.buf.extend_from_slice(&[0; 4]);
+                }
+                let pos = self.buf.len();
+                self.buf.extend_from_slice(&le_u32(structs.len() as u32));
+                for s in structs {
+                    self.buf.extend_from_slice(&le_u64(s[0] as u64));
+                    self.buf.extend_from_slice(&le_u64(s[1] as u64));
+                }
+                pos
+            }
+            _ => unreachable!("scalars are always written inline"),
+        }
+    }
+
+    fn write_table(&mut self, fields: &[(u16, Fb)]) -> usize {
+        // lay out the inline fields largest first, after the vtable soffset
+        let mut layout: Vec<&(u16, Fb)> = fields.iter().collect();
+        layout.sort_by(|a, b| b.1.inline_size().cmp(&a.1.inline_size()));
+
+        let mut field_offsets = vec![];
+        let mut size = 4;
+        for &&(idx, ref value) in &layout {
+            let n = value.inline_size();
+            size = (size + n - 1) / n * n;
+            field_offsets.push((idx, size));
+            size += n;
+        }
+
+        // the vtable precedes the table, which is always 8-byte aligned
+        let num_slots = fields.iter().map(|f| f.0 as usize + 1).max().unwrap_or(0);
+        let mut slots = vec![0u16; num_slots];
+        for &(idx, offset) in &field_offsets {
+            slots[idx as usize] = offset as u16;
+        }
+
+        self.align(2);
+        let vtable = self.buf.len();
+        self.buf.extend_from_slice(&le_u32(4 + 2 * num_slots as u32)[..2]);
+        self.buf.extend_from_slice(&le_u32(size as u32)[..2]);
+        for slot in slots {
+            self.buf.extend_from_slice(&le_u32(slot as u32)[..2]);
+        }
+
+        self.align(8);
+        let table = self.buf.len();
+        self.buf.resize(table + size, 0);
+        self.patch(table, &le_u32((table - vtable) as u32));
+
+        let mut children = vec![];
+        for (&&(_, ref value), &(_, offset)) in layout.iter().zip(field_offsets.iter()) {
+            let at = table + offset;
+            match *value {
+                Fb::Bool(v) => self.patch(at, &[v as u8]),
+                Fb::U8(v) => self.patch(at, &[v]),
+                Fb::I16(v) => self.patch(at, &le_u32(v as u16 as u32)[..2]),
+                Fb::I32(v) => self.patch(at, &le_u32(v as u32)),
+                Fb::I64(v) => self.patch(at, &le_u64(v as u64)),
+                _ => children.push((at, value)),
+            }
+        }
+        for (at, value) in children {
+            let child = self.write_child(value);
+            self.patch_offset(at, child);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use super::*;
+
+    fn u16_at(buf: &[u8], pos: usize) -> u16 {
+        u16::from(buf[pos]) | u16::from(buf[pos + 1]) << 8
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        (0..4).fold(0, |v, i| v | u32::from(buf[pos + i]) << (8 * i))
+    }
+
+    fn u64_at(buf: &[u8], pos: usize) -> u64 {
+        (0..8).fold(0, |v, i| v | u64::from(buf[pos + i]) << (8 * i))
+    }
+
+    // a flatbuffer table, read the usual way (rather than relying on how FbBuilder
+    // lays tables out)
+    #[derive(Copy, Clone)]
+    struct Table<'b> {
+        buf: &'b [u8],
+        pos: usize,
+    }
+
+    impl<'b> Table<'b> {
+        fn root(buf: &'b [u8]) -> Table<'b> {
+            Table {
+                buf: buf,
+                pos: u32_at(buf, 0) as usize,
+            }
+        }
+
+        // the position of field `i`, if it's set
+        fn field(&self, i: usize) -> Option<usize> {
+            let vtable = (self.pos as i64 - i64::from(u32_at(self.buf, self.pos) as i32)) as usize;
+            if 4 + 2 * i >= u16_at(self.buf, vtable) as usize {
+                return None;
+            }
+            match u16_at(self.buf, vtable + 4 + 2 * i) {
+                0 => None,
+                offset => Some(self.pos + offset as usize),
+            }
+        }
+
+        fn u8(&self, i: usize) -> u8 {
+            self.field(i).map_or(0, |p| self.buf[p])
+        }
+
+        fn i16(&self, i: usize) -> i16 {
+            self.field(i).map_or(0, |p| u16_at(self.buf, p) as i16)
+        }
+
+        fn i32(&self, i: usize) -> i32 {
+            self.field(i).map_or(0, |p| u32_at(self.buf, p) as i32)
+        }
+
+        fn i64(&self, i: usize) -> i64 {
+            self.field(i).map_or(0, |p| u64_at(self.buf, p) as i64)
+        }
+
+        fn deref(&self, i: usize) -> usize {
+            let p = self.field(i).expect("field to be set");
+            p + u32_at(self.buf, p) as usize
+        }
+
+        fn table(&self, i: usize) -> Table<'b> {
+            Table {
+                buf: self.buf,
+                pos: self.deref(i),
+            }
+        }
+
+        fn string(&self, i: usize) -> &'b str {
+            let p = self.deref(i);
+            let len = u32_at(self.buf, p) as usize;
+            assert_eq!(self.buf[p + 4 + len], 0);
+            str::from_utf8(&self.buf[p + 4..p + 4 + len]).unwrap()
+        }
+
+        fn tables(&self, i: usize) -> Vec<Table<'b>> {
+            let p = self.deref(i);
+            (0..u32_at(self.buf, p) as usize)
+                .map(|j| {
+                    let slot = p + 4 + 4 * j;
+                    Table {
+                        buf: self.buf,
+                        pos: slot + u32_at(self.buf, slot) as usize,
+                    }
+                })
+                .collect()
+        }
+
+        // a vector of structs of two longs
+        fn structs(&self, i: usize) -> Vec<[i64; 2]> {
+            let p = self.deref(i);
+            assert_eq!((p + 4) % 8, 0);
+            (0..u32_at(self.buf, p) as usize)
+                .map(|j| {
+                    let s = p + 4 + 16 * j;
+                    [u64_at(self.buf, s) as i64, u64_at(self.buf, s + 8) as i64]
+                })
+                .collect()
+        }
+    }
+
+    // splits a stream into its messages' metadata and bodies
+    fn read_messages(stream: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut messages = vec![];
+        let mut pos = 0;
+        loop {
+            assert_eq!(u32_at(stream, pos), CONTINUATION_MARKER);
+            let len = u32_at(stream, pos + 4) as usize;
+            pos += 8;
+            if len == 0 {
+                assert_eq!(pos, stream.len());
+                return messages;
+            }
+            assert_eq!(len % 8, 0);
+            let metadata = &stream[pos..pos + len];
+            let message = Table::root(metadata);
+            assert_eq!(message.i16(0), METADATA_VERSION_V5);
+            let body_len = message.i64(3) as usize;
+            messages.push((metadata, &stream[pos + len..pos + len + body_len]));
+            pos += len + body_len;
+        }
+    }
+
+    fn bit(bytes: &[u8], i: usize) -> bool {
+        bytes[i / 8] & (1 << (i % 8)) != 0
+    }
+
+    #[test]
+    fn stream() {
+        let fields = vec![
+            ("id".to_string(), ColumnType::Int64),
+            ("score".to_string(), ColumnType::Float64),
+            ("ok".to_string(), ColumnType::Bool),
+            ("name".to_string(), ColumnType::Utf8),
+        ];
+        let columns = vec![
+            Column::Int64(vec![Some(1), None, Some(-3)]),
+            Column::Float64(vec![Some(0.5), Some(-2.0), None]),
+            Column::Bool(vec![None, Some(true), Some(false)]),
+            Column::Utf8(vec![Some("a".to_string()), None, Some("".to_string())]),
+        ];
+
+        let mut stream = vec![];
+        write_schema(&mut stream, &fields).unwrap();
+        write_record_batch(&mut stream, &columns).unwrap();
+        write_end_of_stream(&mut stream).unwrap();
+        let messages = read_messages(&stream);
+        assert_eq!(messages.len(), 2);
+
+        let (metadata, body) = messages[0];
+        assert!(body.is_empty());
+        let message = Table::root(metadata);
+        assert_eq!(message.u8(1), HEADER_SCHEMA);
+        let schema = message.table(2);
+        let fields: Vec<_> = schema
+            .tables(1)
+            .iter()
+            .map(|f| (f.string(0), f.u8(1), f.u8(2)))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("id", 1, TYPE_INT),
+                ("score", 1, TYPE_FLOATING_POINT),
+                ("ok", 1, TYPE_BOOL),
+                ("name", 1, TYPE_UTF8),
+            ]
+        );
+        let int = schema.tables(1)[0].table(3);
+        assert_eq!((int.i32(0), int.u8(1)), (64, 1));
+        assert_eq!(schema.tables(1)[1].table(3).i16(0), PRECISION_DOUBLE);
+
+        let (metadata, body) = messages[1];
+        let message = Table::root(metadata);
+        assert_eq!(message.u8(1), HEADER_RECORD_BATCH);
+        let batch = message.table(2);
+        assert_eq!(batch.i64(0), 3);
+        assert_eq!(batch.structs(1), vec![[3, 1], [3, 1], [3, 1], [3, 1]]);
+
+        // validity and values buffers per column, plus offsets for utf8
+        let buffers: Vec<&[u8]> = batch
+            .structs(2)
+            .iter()
+            .map(|b| {
+                assert_eq!(b[0] % 8, 0);
+                &body[b[0] as usize..(b[0] + b[1]) as usize]
+            })
+            .collect();
+        assert_eq!(buffers.len(), 9);
+        let validity: Vec<Vec<bool>> = [0, 2, 4, 6]
+            .iter()
+            .map(|&b| (0..3).map(|i| bit(buffers[b], i)).collect())
+            .collect();
+        assert_eq!(
+            validity,
+            vec![
+                vec![true, false, true],
+                vec![true, true, false],
+                vec![false, true, true],
+                vec![true, false, true],
+            ]
+        );
+
+        let ids: Vec<i64> = (0..3).map(|i| u64_at(buffers[1], 8 * i) as i64).collect();
+        assert_eq!(ids, vec![1, 0, -3]);
+        let scores: Vec<f64> = (0..3)
+            .map(|i| f64::from_bits(u64_at(buffers[3], 8 * i)))
+            .collect();
+        assert_eq!(scores, vec![0.5, -2.0, 0.0]);
+        let oks: Vec<bool> = (0..3).map(|i| bit(buffers[5], i)).collect();
+        assert_eq!(oks, vec![false, true, false]);
+        let offsets: Vec<u32> = (0..4).map(|i| u32_at(buffers[7], 4 * i)).collect();
+        assert_eq!(offsets, vec![0, 1, 1, 1]);
+        assert_eq!(buffers[8], b"a");
+    }
+
+    #[test]
+    fn empty_stream() {
+        let mut stream = vec![];
+        write_schema(&mut stream, &[]).unwrap();
+        write_end_of_stream(&mut stream).unwrap();
+        assert_eq!(read_messages(&stream).len(), 1);
+        assert_eq!(&stream[stream.len() - 8..], &[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    }
+}
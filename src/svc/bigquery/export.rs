@@ -0,0 +1,634 @@
+//! Writers that convert bigquery query results into common file formats.
+//!
+//! Every writer is built from the result schema and consumes `TableRow`s, e.g. the
+//! ones produced by `Hub::query_rows`. Scalars are converted the same way by every
+//! writer:
+//!
+//! - `INTEGER`, `FLOAT` and `BOOLEAN` values are typed (JSON numbers/booleans, and
+//!   Int64/Float64/Bool arrow columns)
+//! - `TIMESTAMP` values are rendered as RFC 3339 strings in UTC
+//! - everything else (`NUMERIC`, `DATE`, `BYTES` etc) is passed through as the string
+//!   bigquery returned, so that no precision is lost
+//!
+//! Nested and repeated fields are handled as follows:
+//!
+//! - NDJSON writes `RECORD` fields as objects and `REPEATED` fields as arrays
+//! - CSV and Arrow have no nested representation, so `RECORD` and `REPEATED` columns
+//!   contain the JSON encoding of the value (the same JSON NDJSON would write)
+use std::cmp;
+use std::io::{self, Write};
+
+use chrono::{TimeZone, Utc};
+use serde_json::{self, Map, Number};
+
+use client;
+use super::{Cell, TableCell, TableField, TableFieldSchema, TableRow};
+use super::arrow::{self, Column, ColumnType};
+
+const DEFAULT_ARROW_BATCH_SIZE: usize = 1024;
+
+pub trait RowWriter {
+    fn write_row(&mut self, row: &TableRow) -> io::Result<()>;
+
+    // flushes any buffered rows and writes trailers
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Writes every row to `writer` and finishes it, returning the number of rows written.
+pub fn export<I, R>(rows: I, writer: &mut R) -> client::Result<u64>
+where
+    I: IntoIterator<Item = client::Result<TableRow>>,
+    R: RowWriter,
+{
+    let mut count = 0;
+    for row in rows {
+        writer.write_row(&row?).map_err(client::Error::IoError)?;
+        count += 1;
+    }
+    writer.finish().map_err(client::Error::IoError)?;
+    Ok(count)
+}
+
+/// RFC 4180 CSV, with a header row of the top-level field names.
+///
+/// NULL is written as an empty field and the empty string as `""`, so the two can be
+/// told apart.
+pub struct CsvWriter<W: Write> {
+    inner: W,
+    fields: Vec<TableField>,
+    header: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(inner: W, schema: &TableFieldSchema) -> Self {
+        CsvWriter {
+            inner: inner,
+            fields: schema.fields.clone(),
+            header: true,
+        }
+    }
+
+    pub fn without_header(mut self) -> Self {
+        self.header = false;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header {
+            self.header = false;
+            let names: Vec<_> = self.fields.iter().map(|f| Some(f.name.clone())).collect();
+            self.write_record(names)?;
+        }
+        Ok(())
+    }
+
+    fn write_record<I>(&mut self, values: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Option<String>>,
+    {
+        for (i, value) in values.into_iter().enumerate() {
+            if i != 0 {
+                self.inner.write_all(b",")?;
+            }
+            if let Some(value) = value {
+                self.inner.write_all(csv_escape(&value).as_bytes())?;
+            }
+        }
+        self.inner.write_all(b"\r\n")
+    }
+}
+
+impl<W: Write> RowWriter for CsvWriter<W> {
+    fn write_row(&mut self, row: &TableRow) -> io::Result<()> {
+        self.write_header()?;
+        let values = row_cells(&self.fields, row)?
+            .map(|(field, cell)| cell_to_text(field, cell))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.write_record(values)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // an empty result set still gets a header
+        self.write_header()?;
+        self.inner.flush()
+    }
+}
+
+/// Newline-delimited JSON, one object per row keyed by field name.
+pub struct NdjsonWriter<W: Write> {
+    inner: W,
+    fields: Vec<TableField>,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(inner: W, schema: &TableFieldSchema) -> Self {
+        NdjsonWriter {
+            inner: inner,
+            fields: schema.fields.clone(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> RowWriter for NdjsonWriter<W> {
+    fn write_row(&mut self, row: &TableRow) -> io::Result<()> {
+        let object = row_to_json(&self.fields, row)?;
+        serde_json::to_writer(&mut self.inner, &object)?;
+        self.inner.write_all(b"\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Arrow IPC stream of record batches, with one column per top-level field.
+pub struct ArrowWriter<W: Write> {
+    inner: W,
+    fields: Vec<TableField>,
+    columns: Vec<Column>,
+    batch_size: usize,
+    wrote_schema: bool,
+}
+
+impl<W: Write> ArrowWriter<W> {
+    pub fn new(inner: W, schema: &TableFieldSchema) -> Self {
+        let columns = schema
+            .fields
+            .iter()
+            .map(|f| Column::new(arrow_type(f)))
+            .collect();
+        ArrowWriter {
+            inner: inner,
+            fields: schema.fields.clone(),
+            columns: columns,
+            batch_size: DEFAULT_ARROW_BATCH_SIZE,
+            wrote_schema: false,
+        }
+    }
+
+    /// Sets the maximum number of rows per record batch.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = ::std::cmp::max(batch_size, 1);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_schema(&mut self) -> io::Result<()> {
+        if !self.wrote_schema {
+            self.wrote_schema = true;
+            let fields: Vec<_> = self.fields
+                .iter()
+                .map(|f| (f.name.clone(), arrow_type(f)))
+                .collect();
+            arrow::write_schema(&mut self.inner, &fields)?;
+        }
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> io::Result<()> {
+        self.write_schema()?;
+        if self.columns.first().map_or(false, |c| c.len() > 0) {
+            arrow::write_record_batch(&mut self.inner, &self.columns)?;
+            for column in &mut self.columns {
+                column.truncate(0);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for ArrowWriter<W> {
+    fn write_row(&mut self, row: &TableRow) -> io::Result<()> {
+        let len = self.columns.first().map_or(0, |c| c.len());
+        // don't leave a partially written row behind
+        if let Err(e) = push_arrow_row(&self.fields, &mut self.columns, row) {
+            for column in &mut self.columns {
+                column.truncate(len);
+            }
+            return Err(e);
+        }
+
+        if len + 1 >= self.batch_size {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_batch()?;
+        arrow::write_end_of_stream(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+//
+// value conversion
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn row_cells<'r>(
+    fields: &'r [TableField],
+    row: &'r TableRow,
+) -> io::Result<::std::iter::Zip<::std::slice::Iter<'r, TableField>, ::std::slice::Iter<'r, TableCell>>> {
+    if fields.len() != row.f.len() {
+        return Err(invalid_data(format!(
+            "row has {} cells but the schema has {} fields",
+            row.f.len(),
+            fields.len()
+        )));
+    }
+    Ok(fields.iter().zip(row.f.iter()))
+}
+
+fn row_to_json(fields: &[TableField], row: &TableRow) -> io::Result<serde_json::Value> {
+    let mut object = Map::new();
+    for (field, cell) in row_cells(fields, row)? {
+        object.insert(field.name.clone(), cell_to_json(field, &cell.v)?);
+    }
+    Ok(serde_json::Value::Object(object))
+}
+
+fn cell_to_json(field: &TableField, cell: &Cell) -> io::Result<serde_json::Value> {
+    match *cell {
+        Cell::Repeat(ref cells) if field.is_repeated() => {
+            let values = cells
+                .iter()
+                .map(|c| element_to_json(field, &c.v))
+                .collect::<io::Result<Vec<_>>>()?;
+            Ok(serde_json::Value::Array(values))
+        }
+        // bigquery returns an empty repeated field as null in some cases
+        Cell::Value(None) if field.is_repeated() => Ok(serde_json::Value::Array(vec![])),
+        _ => element_to_json(field, cell),
+    }
+}
+
+// converts a single (non-repeated) value of the given field
+fn element_to_json(field: &TableField, cell: &Cell) -> io::Result<serde_json::Value> {
+    match *cell {
+        Cell::Value(None) => Ok(serde_json::Value::Null),
+        Cell::Value(Some(ref s)) => scalar_to_json(field, s),
+        Cell::Row(ref row) if field.is_record() => {
+            let fields = field.fields.as_ref().map(|f| f.as_slice()).unwrap_or(&[]);
+            row_to_json(fields, row)
+        }
+        _ => Err(invalid_data(format!(
+            "unexpected value for field '{}' ({} {})",
+            field.name,
+            field.mode,
+            field.type0
+        ))),
+    }
+}
+
+fn scalar_to_json(field: &TableField, s: &str) -> io::Result<serde_json::Value> {
+    let value = match field.type0.as_str() {
+        "INTEGER" | "INT64" => serde_json::Value::Number(parse_int(field, s)?.into()),
+        "FLOAT" | "FLOAT64" => {
+            // NaN and +/-Infinity have no JSON representation
+            match Number::from_f64(parse_float(field, s)?) {
+                Some(n) => serde_json::Value::Number(n),
+                None => serde_json::Value::String(s.to_string()),
+            }
+        }
+        "BOOLEAN" | "BOOL" => serde_json::Value::Bool(parse_bool(field, s)?),
+        "TIMESTAMP" => serde_json::Value::String(format_timestamp(field, s)?),
+        _ => serde_json::Value::String(s.to_string()),
+    };
+    Ok(value)
+}
+
+// the textual form of a cell, as used by csv and arrow utf8 columns
+fn cell_to_text(field: &TableField, cell: &TableCell) -> io::Result<Option<String>> {
+    if field.is_repeated() || field.is_record() {
+        return match cell_to_json(field, &cell.v)? {
+            serde_json::Value::Null => Ok(None),
+            value => Ok(Some(value.to_string())),
+        };
+    }
+    match cell.v {
+        Cell::Value(None) => Ok(None),
+        Cell::Value(Some(ref s)) if field.type0 == "TIMESTAMP" => {
+            format_timestamp(field, s).map(Some)
+        }
+        Cell::Value(Some(ref s)) => Ok(Some(s.clone())),
+        _ => element_to_json(field, &cell.v).map(|v| Some(v.to_string())),
+    }
+}
+
+fn arrow_type(field: &TableField) -> ColumnType {
+    if field.is_repeated() {
+        return ColumnType::Utf8;
+    }
+    match field.type0.as_str() {
+        "INTEGER" | "INT64" => ColumnType::Int64,
+        "FLOAT" | "FLOAT64" => ColumnType::Float64,
+        "BOOLEAN" | "BOOL" => ColumnType::Bool,
+        _ => ColumnType::Utf8,
+    }
+}
+
+fn push_arrow_row(fields: &[TableField], columns: &mut [Column], row: &TableRow) -> io::Result<()> {
+    for ((field, cell), column) in row_cells(fields, row)?.zip(columns.iter_mut()) {
+        push_arrow_value(column, field, cell)?;
+    }
+    Ok(())
+}
+
+fn push_arrow_value(column: &mut Column, field: &TableField, cell: &TableCell) -> io::Result<()> {
+    let raw = match cell.v {
+        Cell::Value(ref v) => v.as_ref(),
+        _ => None,
+    };
+    match *column {
+        Column::Int64(ref mut v) => v.push(match raw {
+            Some(s) => Some(parse_int(field, s)?),
+            None => None,
+        }),
+        Column::Float64(ref mut v) => v.push(match raw {
+            Some(s) => Some(parse_float(field, s)?),
+            None => None,
+        }),
+        Column::Bool(ref mut v) => v.push(match raw {
+            Some(s) => Some(parse_bool(field, s)?),
+            None => None,
+        }),
+        Column::Utf8(ref mut v) => v.push(cell_to_text(field, cell)?),
+    }
+    Ok(())
+}
+
+fn parse_int(field: &TableField, s: &str) -> io::Result<i64> {
+    s.parse().map_err(|_| {
+        invalid_data(format!("invalid integer for field '{}': {}", field.name, s))
+    })
+}
+
+fn parse_float(field: &TableField, s: &str) -> io::Result<f64> {
+    s.parse().map_err(|_| {
+        invalid_data(format!("invalid float for field '{}': {}", field.name, s))
+    })
+}
+
+fn parse_bool(field: &TableField, s: &str) -> io::Result<bool> {
+    match s {
+        "true" | "TRUE" => Ok(true),
+        "false" | "FALSE" => Ok(false),
+        _ => Err(invalid_data(
+            format!("invalid boolean for field '{}': {}", field.name, s),
+        )),
+    }
+}
+
+// bigquery returns timestamps as seconds since the epoch, as a decimal string that may
+// use exponent notation, e.g. "1.5E9"; it's parsed exactly (to the microsecond) rather
+// than as a float, which can't hold microseconds for dates far from the epoch
+fn format_timestamp(field: &TableField, s: &str) -> io::Result<String> {
+    let micros = parse_micros(field, s)?;
+    let mut secs = micros / 1_000_000;
+    let mut sub_micros = micros % 1_000_000;
+    if sub_micros < 0 {
+        secs -= 1;
+        sub_micros += 1_000_000;
+    }
+    Utc.timestamp_opt(secs, sub_micros as u32 * 1000)
+        .single()
+        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string())
+        .ok_or_else(|| invalid_timestamp(field, s))
+}
+
+// a decimal number of seconds as microseconds, rounded half away from zero
+fn parse_micros(field: &TableField, s: &str) -> io::Result<i64> {
+    let invalid = || invalid_timestamp(field, s);
+
+    let (negative, unsigned) = if s.starts_with('-') || s.starts_with('+') {
+        (s.starts_with('-'), &s[1..])
+    } else {
+        (false, s)
+    };
+    let (mantissa, exponent) = match unsigned.find(|c| c == 'e' || c == 'E') {
+        Some(i) => {
+            let exponent = unsigned[i + 1..].parse::<i64>().map_err(|_| invalid())?;
+            (&unsigned[..i], exponent)
+        }
+        None => (unsigned, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if int.len() + frac.len() == 0 || !int.chars().chain(frac.chars()).all(|c| c.is_digit(10)) {
+        return Err(invalid());
+    }
+
+    let digits: Vec<u8> = int.bytes().chain(frac.bytes()).map(|b| b - b'0').collect();
+    if digits.iter().all(|&d| d == 0) {
+        return Ok(0);
+    }
+    // how many of the digits (padded with zeros) are whole microseconds
+    let whole = (int.len() as i64)
+        .checked_add(exponent)
+        .and_then(|n| n.checked_add(6))
+        .ok_or_else(&invalid)?;
+
+    let mut micros: i64 = 0;
+    for i in 0..cmp::max(whole, 0) {
+        let digit = digits.get(i as usize).map_or(0, |&d| i64::from(d));
+        micros = micros
+            .checked_mul(10)
+            .and_then(|n| n.checked_add(digit))
+            .ok_or_else(&invalid)?;
+    }
+    let next = if whole >= 0 { digits.get(whole as usize) } else { None };
+    if next.map_or(false, |&d| d >= 5) {
+        micros = micros.checked_add(1).ok_or_else(&invalid)?;
+    }
+    Ok(if negative { -micros } else { micros })
+}
+
+fn invalid_timestamp(field: &TableField, s: &str) -> io::Error {
+    invalid_data(format!("invalid timestamp for field '{}': {}", field.name, s))
+}
+
+// an empty string is quoted, to tell it apart from NULL
+fn csv_escape<'v>(value: &'v str) -> ::std::borrow::Cow<'v, str> {
+    if value.is_empty() || value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn schema() -> TableFieldSchema {
+        serde_json::from_str(
+            r#"{"fields": [
+                {"name": "id", "type": "INTEGER"},
+                {"name": "name", "type": "STRING"},
+                {"name": "tags", "type": "STRING", "mode": "REPEATED"}
+            ]}"#,
+        ).unwrap()
+    }
+
+    fn rows() -> Vec<TableRow> {
+        serde_json::from_str(
+            r#"[
+                {"f": [{"v": "1"}, {"v": "a, \"b\""}, {"v": [{"v": "x"}]}]},
+                {"f": [{"v": "2"}, {"v": ""}, {"v": []}]},
+                {"f": [{"v": null}, {"v": null}, {"v": null}]},
+                {"f": [{"v": "4"}, {"v": "two\nlines"}, {"v": [{"v": ""}]}]}
+            ]"#,
+        ).unwrap()
+    }
+
+    fn csv(writer: CsvWriter<Vec<u8>>, rows: Vec<TableRow>) -> String {
+        let mut writer = writer;
+        export(rows.into_iter().map(Ok), &mut writer).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn csv_rows() {
+        assert_eq!(
+            csv(CsvWriter::new(vec![], &schema()), rows()),
+            concat!(
+                "id,name,tags\r\n",
+                "1,\"a, \"\"b\"\"\",\"[\"\"x\"\"]\"\r\n",
+                "2,\"\",[]\r\n",
+                ",,[]\r\n",
+                "4,\"two\nlines\",\"[\"\"\"\"]\"\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn csv_without_header() {
+        let writer = CsvWriter::new(vec![], &schema()).without_header();
+        let rows = rows().into_iter().skip(1).take(2).collect();
+        assert_eq!(csv(writer, rows), "2,\"\",[]\r\n,,[]\r\n");
+    }
+
+    #[test]
+    fn csv_empty() {
+        assert_eq!(csv(CsvWriter::new(vec![], &schema()), vec![]), "id,name,tags\r\n");
+    }
+
+    fn ndjson(schema: &str, rows: &str) -> Vec<serde_json::Value> {
+        let schema: TableFieldSchema = serde_json::from_str(schema).unwrap();
+        let rows: Vec<TableRow> = serde_json::from_str(rows).unwrap();
+        let mut writer = NdjsonWriter::new(vec![], &schema);
+        export(rows.into_iter().map(Ok), &mut writer).unwrap();
+        String::from_utf8(writer.into_inner())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn json(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn ndjson_rows() {
+        let schema = r#"{"fields": [
+            {"name": "id", "type": "INTEGER"},
+            {"name": "name", "type": "STRING"},
+            {"name": "score", "type": "FLOAT"},
+            {"name": "ok", "type": "BOOLEAN"},
+            {"name": "at", "type": "TIMESTAMP"},
+            {"name": "amount", "type": "NUMERIC"},
+            {"name": "tags", "type": "STRING", "mode": "REPEATED"}
+        ]}"#;
+        let rows = r#"[
+            {"f": [{"v": "9007199254740993"}, {"v": ""}, {"v": "1.5"}, {"v": "true"},
+                   {"v": "1.5E9"}, {"v": "0.1"}, {"v": [{"v": "a"}, {"v": ""}]}]},
+            {"f": [{"v": null}, {"v": null}, {"v": "NaN"}, {"v": null}, {"v": null},
+                   {"v": null}, {"v": null}]}
+        ]"#;
+        assert_eq!(
+            ndjson(schema, rows),
+            vec![
+                // integers beyond 2^53 are written exactly
+                json(r#"{"id": 9007199254740993, "name": "", "score": 1.5, "ok": true,
+                         "at": "2017-07-14T02:40:00Z", "amount": "0.1", "tags": ["a", ""]}"#),
+                json(r#"{"id": null, "name": null, "score": "NaN", "ok": null, "at": null,
+                         "amount": null, "tags": []}"#),
+            ]
+        );
+    }
+
+    #[test]
+    fn ndjson_nested() {
+        let schema = r#"{"fields": [
+            {"name": "address", "type": "RECORD", "fields": [
+                {"name": "city", "type": "STRING"},
+                {"name": "zips", "type": "INTEGER", "mode": "REPEATED"}
+            ]},
+            {"name": "items", "type": "STRUCT", "mode": "REPEATED", "fields": [
+                {"name": "sku", "type": "STRING"},
+                {"name": "count", "type": "INTEGER"}
+            ]}
+        ]}"#;
+        let rows = r#"[
+            {"f": [
+                {"v": {"f": [{"v": "Paris"}, {"v": [{"v": "75001"}, {"v": "75002"}]}]}},
+                {"v": [{"v": {"f": [{"v": "a"}, {"v": "1"}]}},
+                       {"v": {"f": [{"v": null}, {"v": "2"}]}}]}
+            ]},
+            {"f": [{"v": null}, {"v": []}]}
+        ]"#;
+        assert_eq!(
+            ndjson(schema, rows),
+            vec![
+                json(r#"{"address": {"city": "Paris", "zips": [75001, 75002]},
+                         "items": [{"sku": "a", "count": 1}, {"sku": null, "count": 2}]}"#),
+                json(r#"{"address": null, "items": []}"#),
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        let field: TableField =
+            serde_json::from_str(r#"{"name": "at", "type": "TIMESTAMP"}"#).unwrap();
+        let format = |s: &str| format_timestamp(&field, s).unwrap();
+        assert_eq!(format("0"), "1970-01-01T00:00:00Z");
+        assert_eq!(format("1.5E9"), "2017-07-14T02:40:00Z");
+        assert_eq!(format("1.500000000123456E9"), "2017-07-14T02:40:00.123456Z");
+        assert_eq!(format("1234567890.123456"), "2009-02-13T23:31:30.123456Z");
+        assert_eq!(format("253402300799.999999"), "9999-12-31T23:59:59.999999Z");
+        assert_eq!(format("1.2345678901234567e9"), "2009-02-13T23:31:30.123457Z");
+        assert_eq!(format("+12.5e-1"), "1970-01-01T00:00:01.250Z");
+        assert_eq!(format("0.0000005"), "1970-01-01T00:00:00.000001Z");
+        assert_eq!(format("5E-8"), "1970-01-01T00:00:00Z");
+
+        // before the epoch
+        assert_eq!(format("-1.5"), "1969-12-31T23:59:58.500Z");
+        assert_eq!(format("-0.000001"), "1969-12-31T23:59:59.999999Z");
+        assert_eq!(format("-1.0E6"), "1969-12-20T10:13:20Z");
+        assert_eq!(format("-62135596800"), "0001-01-01T00:00:00Z");
+
+        for s in &["", "-", ".", "E5", "1e", "1.2.3", "--1", "1,5", "abc", "1e999", "9e18"] {
+            assert!(format_timestamp(&field, s).is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,997 @@
+//! BigQuery datasets, tables, routines and jobs.
+//!
+//! Requests are authorized as the client itself (e.g. its service account) unless the
+//! hub is given other credentials, e.g. to run a call as an end user:
+//!
+//! ```ignore
+//! let hub: bigquery::Hub = client.hub();
+//! let tables = hub.with_credentials(Credentials::User(token)).list_tables(..)?;
+//! ```
+#![allow(unused_variables)]
+use std::str::FromStr;
+
+use hyper::{self, Uri};
+
+use client::{self, ApiClient};
+use svc::common;
+
+pub use svc::common::{Binding, Expr, Policy};
+
+mod arrow;
+pub mod export;
+pub mod schema;
+
+static BIGQUERY_ROOT: &str = "https://www.googleapis.com/bigquery/v2/projects";
+
+// the newest policy version, which supports conditional role bindings
+const IAM_POLICY_VERSION: i32 = 3;
+
+pub struct BigQueryService {}
+pub type Hub<'a> = client::Hub<'a, BigQueryService>;
+
+#[derive(Default, Debug)]
+pub struct ListDatasetsRequest {
+    pub all: bool,
+    pub filter: Option<String>,
+    pub max_results: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+impl ListDatasetsRequest {
+    fn to_query(&self) -> String {
+        let mut params = vec![("all", self.all.to_string())];
+        if let Some(ref filter) = self.filter {
+            params.push(("filter", filter.clone()));
+        }
+        if let Some(ref max_results) = self.max_results {
+            params.push(("maxResults", max_results.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListDatasetsResponse {
+    pub next_page_token: Option<String>,
+
+    pub datasets: Vec<DatasetMeta>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetMeta {
+    pub id: String,
+    pub dataset_reference: DatasetReference,
+    pub friendly_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetReference {
+    pub project_id: String,
+    pub dataset_id: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Dataset {
+    pub id: String,
+    pub dataset_reference: DatasetReference,
+    pub friendly_name: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub etag: Option<String>,
+
+    #[serde(default)]
+    pub access: Vec<AccessEntry>,
+}
+
+// NOTE the access list in a patch replaces the existing one entirely, so callers
+// should read-modify-write the entries from get_dataset
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetPatchRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<Vec<AccessEntry>>,
+}
+
+/// A dataset access control entry; exactly one grantee should be set.
+///
+/// https://cloud.google.com/bigquery/docs/reference/rest/v2/datasets#Dataset.FIELDS.access
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessEntry {
+    // READER, WRITER or OWNER (or an IAM role); unset for authorized views,
+    // routines and datasets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_by_email: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_by_email: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+
+    // projectOwners, projectReaders, projectWriters or allAuthenticatedUsers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub special_group: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iam_member: Option<String>,
+
+    // an authorized view
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view: Option<TableReference>,
+
+    // an authorized routine
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routine: Option<RoutineReference>,
+
+    // an authorized dataset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<DatasetAccessEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetAccessEntry {
+    pub dataset: DatasetReference,
+
+    // currently only VIEWS is supported
+    pub target_types: Vec<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct ListTablesRequest {
+    pub max_results: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+impl ListTablesRequest {
+    fn to_query(&self) -> String {
+        let mut params = vec![];
+        if let Some(ref max_results) = self.max_results {
+            params.push(("maxResults", max_results.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListTablesResponse {
+    pub next_page_token: Option<String>,
+    pub tables: Option<Vec<TableMeta>>,
+    pub total_items: usize,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TableMeta {
+    pub id: String,
+    pub table_reference: TableReference,
+    pub friendly_name: Option<String>,
+    pub view: Option<ViewMeta>,
+
+    #[serde(rename = "type")]
+    pub type0: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+
+    #[serde(default = "default_view_use_legacy_sql")]
+    pub use_legacy_sql: bool,
+}
+
+fn default_view_use_legacy_sql() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TableReference {
+    pub project_id: String,
+    pub dataset_id: String,
+    pub table_id: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DescribeTableResponse {
+    pub id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<TableFieldSchema>,
+
+    #[serde(rename = "type")]
+    pub type0: String,
+
+    pub table_reference: TableReference,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_partitioning: Option<TimePartitioning>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_data_configuration: Option<ExtDataConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view: Option<ViewMeta>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtDataConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<TableFieldSchema>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TableFieldSchema {
+    pub fields: Vec<TableField>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimePartitioning {
+    #[serde(rename = "type")]
+    // Always "DAY"
+    pub type0: String,
+    // Partitions older than this will be deleted automatically
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration_ms: Option<i64>,
+    // If unset, the table is partitioned by the pseudo column "_PARTITIONTIME"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TableField {
+    pub name: String,
+
+    #[serde(default = "default_table_field_mode")]
+    pub mode: String,
+
+    #[serde(rename = "type")]
+    pub type0: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<TableField>>,
+}
+
+fn default_table_field_mode() -> String {
+    "NULLABLE".into()
+}
+
+impl TableField {
+    /// Whether the field holds nested fields; `STRUCT` is the standard sql name.
+    pub fn is_record(&self) -> bool {
+        self.type0 == "RECORD" || self.type0 == "STRUCT"
+    }
+
+    pub fn is_repeated(&self) -> bool {
+        self.mode == "REPEATED"
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TablePatchRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<TableFieldSchema>,
+}
+
+#[derive(Default, Debug)]
+pub struct ListRowAccessPoliciesRequest {
+    pub page_size: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+impl ListRowAccessPoliciesRequest {
+    fn to_query(&self) -> String {
+        let mut params = vec![];
+        if let Some(ref page_size) = self.page_size {
+            params.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRowAccessPoliciesResponse {
+    pub next_page_token: Option<String>,
+
+    #[serde(default)]
+    pub row_access_policies: Vec<RowAccessPolicy>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RowAccessPolicy {
+    pub row_access_policy_reference: RowAccessPolicyReference,
+
+    // a SQL boolean expression, e.g. region = "us"
+    pub filter_predicate: String,
+
+    pub etag: Option<String>,
+    pub creation_time: Option<String>,
+    pub last_modified_time: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RowAccessPolicyReference {
+    pub project_id: String,
+    pub dataset_id: String,
+    pub table_id: String,
+    pub policy_id: String,
+}
+
+#[derive(Default, Debug)]
+pub struct ListRoutinesRequest {
+    pub filter: Option<String>,
+    pub max_results: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+impl ListRoutinesRequest {
+    fn to_query(&self) -> String {
+        let mut params = vec![];
+        if let Some(ref filter) = self.filter {
+            params.push(("filter", filter.clone()));
+        }
+        if let Some(ref max_results) = self.max_results {
+            params.push(("maxResults", max_results.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRoutinesResponse {
+    pub next_page_token: Option<String>,
+
+    // NOTE the list response only includes a subset of each routine's fields
+    pub routines: Option<Vec<Routine>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutineReference {
+    pub project_id: String,
+    pub dataset_id: String,
+    pub routine_id: String,
+}
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/routines#Routine
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Routine {
+    pub routine_reference: RoutineReference,
+
+    // SCALAR_FUNCTION, TABLE_VALUED_FUNCTION or PROCEDURE
+    pub routine_type: String,
+
+    // SQL or JAVASCRIPT; defaults to SQL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<RoutineArgument>>,
+
+    // If unset for a SQL function, the return type is inferred from the definition body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_type: Option<StandardSqlDataType>,
+
+    // Google Cloud Storage URIs of JAVASCRIPT libraries, e.g. gs://bucket/lib.js
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imported_libraries: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub definition_body: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    // DETERMINISTIC or NOT_DETERMINISTIC; only applies to JAVASCRIPT functions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub determinism_level: Option<String>,
+
+    // output only
+    #[serde(skip_serializing)]
+    pub etag: Option<String>,
+
+    #[serde(skip_serializing)]
+    pub creation_time: Option<String>,
+
+    #[serde(skip_serializing)]
+    pub last_modified_time: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutineArgument {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    // FIXED_TYPE or ANY_TYPE; defaults to FIXED_TYPE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argument_kind: Option<String>,
+
+    // IN, OUT or INOUT; only applies to procedures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+
+    // unset if argument_kind is ANY_TYPE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_type: Option<StandardSqlDataType>,
+}
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/StandardSqlDataType
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardSqlDataType {
+    // e.g. INT64, STRING, ARRAY or STRUCT
+    pub type_kind: String,
+
+    // set if type_kind is ARRAY
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub array_element_type: Option<Box<StandardSqlDataType>>,
+
+    // set if type_kind is STRUCT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub struct_type: Option<StandardSqlStructType>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardSqlStructType {
+    pub fields: Vec<StandardSqlField>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardSqlField {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type0: Option<StandardSqlDataType>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobResource {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_reference: Option<JobReference>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<JobStatus>,
+
+    pub configuration: JobConfiguration,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobConfiguration {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<QueryResource>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResource {
+    pub query: String,
+    pub use_legacy_sql: bool,
+    pub use_query_cache: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_table: Option<TableReference>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub state: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_result: Option<QueryError>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<QueryError>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobReference {
+    pub project_id: String,
+
+    pub job_id: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryError {
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    pub message: Option<String>,
+    pub debug_info: Option<String>,
+}
+
+#[derive(Serialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetQueryResultsRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_token: Option<String>,
+}
+
+impl GetQueryResultsRequest {
+    fn to_query(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(ref timeout_ms) = self.timeout_ms {
+            params.push(("timeoutMs", timeout_ms.to_string()));
+        }
+        if let Some(ref max_results) = self.max_results {
+            params.push(("maxResults", max_results.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        if let Some(ref start_index) = self.start_index {
+            params.push(("startIndex", start_index.to_string()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetQueryResultsResponse {
+    pub schema: Option<TableFieldSchema>,
+    pub job_reference: Option<JobReference>,
+    pub total_rows: Option<String>,
+    pub rows: Option<Vec<TableRow>>,
+    pub job_complete: bool,
+    pub page_token: Option<String>,
+    pub errors: Option<Vec<QueryError>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TableRow {
+    pub f: Vec<TableCell>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TableCell {
+    pub v: Cell,
+}
+
+#[serde(untagged)]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Cell {
+    Value(Option<String>),
+    Repeat(Vec<TableCell>),
+    Row(TableRow),
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell::Value(None)
+    }
+}
+
+/// An iterator over every row of a query's results, fetching pages as needed.
+///
+/// Pages are requested with `get_query_results`, waiting for the job to complete
+/// first. The schema is available once the first page has been fetched.
+pub struct QueryRows<'h, 'a: 'h> {
+    hub: &'h Hub<'a>,
+    project_id: String,
+    job_id: String,
+    req: GetQueryResultsRequest,
+    schema: Option<TableFieldSchema>,
+    rows: ::std::vec::IntoIter<TableRow>,
+    done: bool,
+}
+
+impl<'h, 'a> QueryRows<'h, 'a> {
+    /// Returns the result schema, fetching the first page if needed.
+    pub fn schema(&mut self) -> client::Result<&TableFieldSchema> {
+        while self.schema.is_none() && !self.done {
+            self.fetch_page()?;
+        }
+        Ok(self.schema.get_or_insert_with(Default::default))
+    }
+
+    fn fetch_page(&mut self) -> client::Result<()> {
+        let res = self.hub
+            .get_query_results(&self.project_id, &self.job_id, &self.req)?;
+        if !res.job_complete {
+            // the request timed out before the job finished, so ask again
+            return Ok(());
+        }
+
+        if let Some(schema) = res.schema {
+            self.schema = Some(schema);
+        }
+        self.rows = res.rows.unwrap_or_default().into_iter();
+        self.req.start_index = None;
+        self.req.page_token = res.page_token;
+        self.done = self.req.page_token.is_none();
+        Ok(())
+    }
+}
+
+impl<'h, 'a> Iterator for QueryRows<'h, 'a> {
+    type Item = client::Result<TableRow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fetch_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a> Hub<'a> {
+    pub fn list_datasets(
+        &self,
+        project_id: &str,
+        req: &ListDatasetsRequest,
+    ) -> client::Result<ListDatasetsResponse> {
+        let path = format!(
+            "{}/{}/datasets?{}",
+            BIGQUERY_ROOT,
+            project_id,
+            req.to_query()
+        );
+
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<ListDatasetsResponse>(&uri, &[])
+    }
+
+    pub fn get_dataset(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+    ) -> client::Result<Dataset> {
+        let path = format!("{}/{}/datasets/{}", BIGQUERY_ROOT, project_id, dataset_id);
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<Dataset>(&uri, &[])
+    }
+
+    pub fn patch_dataset(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        req: &DatasetPatchRequest,
+    ) -> client::Result<Dataset> {
+        let path = format!("{}/{}/datasets/{}", BIGQUERY_ROOT, project_id, dataset_id);
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.send::<_, Dataset>(hyper::Method::Patch, &uri, req, &[])
+    }
+
+    pub fn list_tables(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        req: &ListTablesRequest,
+    ) -> client::Result<ListTablesResponse> {
+        let path = format!(
+            "{}/{}/datasets/{}/tables?{}",
+            BIGQUERY_ROOT,
+            project_id,
+            dataset_id,
+            req.to_query()
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<ListTablesResponse>(&uri, &[])
+    }
+
+    pub fn describe_table(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+    ) -> client::Result<DescribeTableResponse> {
+        let path = format!(
+            "{}/{}/datasets/{}/tables/{}",
+            BIGQUERY_ROOT,
+            project_id,
+            dataset_id,
+            table_id
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<DescribeTableResponse>(&uri, &[])
+    }
+
+    pub fn get_table_iam_policy(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+    ) -> client::Result<Policy> {
+        let uri = mk_table_iam_uri(project_id, dataset_id, table_id, "getIamPolicy");
+        let req = common::GetIamPolicyRequest {
+            options: common::GetPolicyOptions { requested_policy_version: IAM_POLICY_VERSION },
+        };
+        self.post::<_, Policy>(&uri, req, &[])
+    }
+
+    // NOTE the policy's etag should be the one returned by get_table_iam_policy
+    pub fn set_table_iam_policy(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+        policy: Policy,
+    ) -> client::Result<Policy> {
+        let uri = mk_table_iam_uri(project_id, dataset_id, table_id, "setIamPolicy");
+        let req = common::SetIamPolicyRequest { policy: policy };
+        self.post::<_, Policy>(&uri, req, &[])
+    }
+
+    // returns the subset of `permissions` that the caller has on the table
+    pub fn test_table_iam_permissions(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+        permissions: &[String],
+    ) -> client::Result<Vec<String>> {
+        let uri = mk_table_iam_uri(project_id, dataset_id, table_id, "testIamPermissions");
+        let req = common::TestIamPermissionsRequest { permissions: permissions.to_vec() };
+        self.post::<_, common::TestIamPermissionsResponse>(&uri, req, &[])
+            .map(|r| r.permissions)
+    }
+
+    pub fn list_row_access_policies(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+        req: &ListRowAccessPoliciesRequest,
+    ) -> client::Result<ListRowAccessPoliciesResponse> {
+        let path = format!(
+            "{}/{}/datasets/{}/tables/{}/rowAccessPolicies?{}",
+            BIGQUERY_ROOT,
+            project_id,
+            dataset_id,
+            table_id,
+            req.to_query()
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<ListRowAccessPoliciesResponse>(&uri, &[])
+    }
+
+    pub fn list_routines(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        req: &ListRoutinesRequest,
+    ) -> client::Result<ListRoutinesResponse> {
+        let path = format!(
+            "{}/{}/datasets/{}/routines?{}",
+            BIGQUERY_ROOT,
+            project_id,
+            dataset_id,
+            req.to_query()
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<ListRoutinesResponse>(&uri, &[])
+    }
+
+    pub fn get_routine(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        routine_id: &str,
+    ) -> client::Result<Routine> {
+        let uri = mk_routine_uri(project_id, dataset_id, Some(routine_id));
+        self.get::<Routine>(&uri, &[])
+    }
+
+    // NOTE the routine is created in the dataset named by its routine_reference
+    pub fn insert_routine(&self, routine: &Routine) -> client::Result<Routine> {
+        let r = &routine.routine_reference;
+        let uri = mk_routine_uri(&r.project_id, &r.dataset_id, None);
+        self.post::<_, Routine>(&uri, routine, &[])
+    }
+
+    // replaces the entire routine, so unset fields are cleared
+    pub fn update_routine(&self, routine: &Routine) -> client::Result<Routine> {
+        let r = &routine.routine_reference;
+        let uri = mk_routine_uri(&r.project_id, &r.dataset_id, Some(&r.routine_id));
+        self.send::<_, Routine>(hyper::Method::Put, &uri, routine, &[])
+    }
+
+    pub fn delete_routine(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        routine_id: &str,
+    ) -> client::Result<()> {
+        let uri = mk_routine_uri(project_id, dataset_id, Some(routine_id));
+        self.delete::<common::Empty>(&uri, &[]).map(|_| ())
+    }
+
+    // NOTE schema patches must contain the full schema; see schema::diff
+    pub fn patch_table(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+        req: &TablePatchRequest,
+    ) -> client::Result<DescribeTableResponse> {
+        let path = format!(
+            "{}/{}/datasets/{}/tables/{}",
+            BIGQUERY_ROOT,
+            project_id,
+            dataset_id,
+            table_id
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.send::<_, _>(hyper::Method::Patch, &uri, req, &[])
+    }
+
+    pub fn create_job(
+        &self,
+        project_id: &str,
+        req: &JobResource,
+    ) -> client::Result<JobResource> {
+        let path = format!("{}/{}/jobs", BIGQUERY_ROOT, project_id);
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.post::<_, _>(&uri, req, &[])
+    }
+
+    pub fn cancel_job(
+        &self,
+        project_id: &str,
+        job_id: &str,
+    ) -> client::Result<JobResource> {
+        let path = format!("{}/{}/jobs/{}/cancel", BIGQUERY_ROOT, project_id, job_id);
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "camelCase")]
+        struct Response {
+            pub job: JobResource,
+        }
+
+        self.post::<_, Response>(&uri, common::Empty {}, &[])
+            .map(|r| r.job)
+    }
+
+    pub fn get_job(
+        &self,
+        project_id: &str,
+        job_id: &str,
+    ) -> client::Result<JobResource> {
+        let path = format!("{}/{}/jobs/{}", BIGQUERY_ROOT, project_id, job_id);
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<_>(&uri, &[])
+    }
+
+    pub fn get_query_results(
+        &self,
+        project_id: &str,
+        job_id: &str,
+        req: &GetQueryResultsRequest,
+    ) -> client::Result<GetQueryResultsResponse> {
+        let path = format!(
+            "{}/{}/queries/{}?{}",
+            BIGQUERY_ROOT,
+            project_id,
+            job_id,
+            req.to_query()
+        );
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        self.get::<_>(&uri, &[])
+    }
+
+    /// Streams every result row of a query job, starting from `req`.
+    pub fn query_rows<'h>(
+        &'h self,
+        project_id: &str,
+        job_id: &str,
+        req: GetQueryResultsRequest,
+    ) -> QueryRows<'h, 'a> {
+        QueryRows {
+            hub: self,
+            project_id: project_id.to_string(),
+            job_id: job_id.to_string(),
+            req: req,
+            schema: None,
+            rows: vec![].into_iter(),
+            done: false,
+        }
+    }
+}
+
+fn mk_routine_uri(project_id: &str, dataset_id: &str, routine_id: Option<&str>) -> Uri {
+    let mut path = format!(
+        "{}/{}/datasets/{}/routines",
+        BIGQUERY_ROOT,
+        project_id,
+        dataset_id
+    );
+    if let Some(routine_id) = routine_id {
+        path.push('/');
+        path.push_str(routine_id);
+    }
+    Uri::from_str(&path).expect("uri to be valid")
+}
+
+fn mk_table_iam_uri(project_id: &str, dataset_id: &str, table_id: &str, action: &str) -> Uri {
+    let path = format!(
+        "{}/{}/datasets/{}/tables/{}:{}",
+        BIGQUERY_ROOT,
+        project_id,
+        dataset_id,
+        table_id,
+        action
+    );
+    Uri::from_str(&path).expect("uri to be valid")
+}
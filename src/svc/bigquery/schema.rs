@@ -0,0 +1,414 @@
+//! Schema diffing for declaratively managed tables.
+//!
+//! `diff` compares an existing table schema against a desired one, recursing into
+//! `RECORD` fields, and classifies every change the same way bigquery does when
+//! patching a table: only new `NULLABLE`/`REPEATED` columns and relaxing `REQUIRED`
+//! to `NULLABLE` can be applied in place, anything else requires recreating the table.
+//!
+//! https://cloud.google.com/bigquery/docs/managing-table-schemas
+use std::fmt;
+
+use super::{TableField, TableFieldSchema, TablePatchRequest};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    // can be applied through a table patch
+    Additive,
+    // requires the table to be recreated (or the data to be migrated)
+    Breaking,
+}
+
+#[derive(Clone, Debug)]
+pub enum SchemaChange {
+    FieldAdded { path: String, field: TableField },
+    FieldRemoved { path: String },
+    ModeChanged { path: String, from: String, to: String },
+    TypeChanged { path: String, from: String, to: String },
+}
+
+impl SchemaChange {
+    /// The dotted path of the changed field, e.g. `address.city`.
+    pub fn path(&self) -> &str {
+        match *self {
+            SchemaChange::FieldAdded { ref path, .. } |
+            SchemaChange::FieldRemoved { ref path } |
+            SchemaChange::ModeChanged { ref path, .. } |
+            SchemaChange::TypeChanged { ref path, .. } => path,
+        }
+    }
+
+    pub fn kind(&self) -> ChangeKind {
+        match *self {
+            // new columns can't be required, since existing rows have no value for them
+            SchemaChange::FieldAdded { ref field, .. } if field.mode != "REQUIRED" => {
+                ChangeKind::Additive
+            }
+            SchemaChange::ModeChanged { ref from, ref to, .. }
+                if from == "REQUIRED" && to == "NULLABLE" => ChangeKind::Additive,
+            _ => ChangeKind::Breaking,
+        }
+    }
+
+    pub fn is_additive(&self) -> bool {
+        self.kind() == ChangeKind::Additive
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaChange::FieldAdded { ref path, ref field } => {
+                write!(f, "add {} {} {}", path, field.mode, field.type0)
+            }
+            SchemaChange::FieldRemoved { ref path } => write!(f, "remove {}", path),
+            SchemaChange::ModeChanged { ref path, ref from, ref to } => {
+                write!(f, "change mode of {} from {} to {}", path, from, to)
+            }
+            SchemaChange::TypeChanged { ref path, ref from, ref to } => {
+                write!(f, "change type of {} from {} to {}", path, from, to)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SchemaDiff {
+    pub changes: Vec<SchemaChange>,
+
+    // the existing schema with every additive change applied
+    patched: TableFieldSchema,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// True if every change can be applied through a table patch.
+    pub fn is_compatible(&self) -> bool {
+        self.changes.iter().all(|c| c.is_additive())
+    }
+
+    pub fn additive(&self) -> Vec<&SchemaChange> {
+        self.changes.iter().filter(|c| c.is_additive()).collect()
+    }
+
+    pub fn breaking(&self) -> Vec<&SchemaChange> {
+        self.changes.iter().filter(|c| !c.is_additive()).collect()
+    }
+
+    /// The existing schema with the additive changes applied; breaking changes are left out.
+    pub fn patched_schema(&self) -> &TableFieldSchema {
+        &self.patched
+    }
+
+    /// The `tables.patch` body that applies the additive changes, if there are any.
+    ///
+    /// bigquery requires the full schema in the patch, so this contains every existing
+    /// field (in its existing position) followed by the new ones.
+    pub fn patch_request(&self) -> Option<TablePatchRequest> {
+        if self.changes.iter().any(|c| c.is_additive()) {
+            Some(TablePatchRequest {
+                schema: Some(self.patched.clone()),
+                ..Default::default()
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Compares `existing` against `desired` field by field.
+///
+/// Field names are compared case insensitively (as bigquery does), and type aliases
+/// such as `INTEGER`/`INT64` are considered equal.
+pub fn diff(existing: &TableFieldSchema, desired: &TableFieldSchema) -> SchemaDiff {
+    let mut changes = vec![];
+    let fields = diff_fields("", &existing.fields, &desired.fields, &mut changes);
+    SchemaDiff {
+        changes: changes,
+        patched: TableFieldSchema { fields: fields },
+    }
+}
+
+// returns the patched version of `existing`
+fn diff_fields(
+    prefix: &str,
+    existing: &[TableField],
+    desired: &[TableField],
+    changes: &mut Vec<SchemaChange>,
+) -> Vec<TableField> {
+    let mut patched = vec![];
+
+    for field in existing {
+        let path = field_path(prefix, &field.name);
+        let wanted = desired.iter().find(|d| same_name(d, field));
+        let wanted = match wanted {
+            Some(wanted) => wanted,
+            None => {
+                changes.push(SchemaChange::FieldRemoved { path: path });
+                patched.push(field.clone());
+                continue;
+            }
+        };
+
+        let mut field = field.clone();
+        if normalize_type(&field.type0) != normalize_type(&wanted.type0) {
+            changes.push(SchemaChange::TypeChanged {
+                path: path.clone(),
+                from: field.type0.clone(),
+                to: wanted.type0.clone(),
+            });
+        } else if field.is_record() {
+            let empty = vec![];
+            let fields = diff_fields(
+                &path,
+                field.fields.as_ref().unwrap_or(&empty),
+                wanted.fields.as_ref().unwrap_or(&empty),
+                changes,
+            );
+            field.fields = Some(fields);
+        }
+
+        let (from, to) = (normalize_mode(&field.mode), normalize_mode(&wanted.mode));
+        if from != to {
+            let change = SchemaChange::ModeChanged {
+                path: path,
+                from: from.to_string(),
+                to: to.to_string(),
+            };
+            if change.is_additive() {
+                field.mode = to.to_string();
+            }
+            changes.push(change);
+        }
+        patched.push(field);
+    }
+
+    for field in desired {
+        if !existing.iter().any(|e| same_name(e, field)) {
+            let field = TableField {
+                mode: normalize_mode(&field.mode).to_string(),
+                ..field.clone()
+            };
+            let change = SchemaChange::FieldAdded {
+                path: field_path(prefix, &field.name),
+                field: field.clone(),
+            };
+            if change.is_additive() {
+                patched.push(field);
+            }
+            changes.push(change);
+        }
+    }
+
+    patched
+}
+
+fn field_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn same_name(a: &TableField, b: &TableField) -> bool {
+    a.name.to_lowercase() == b.name.to_lowercase()
+}
+
+// a field without a mode (e.g. a defaulted `TableField`) is nullable
+fn normalize_mode(mode: &str) -> &str {
+    if mode.is_empty() {
+        "NULLABLE"
+    } else {
+        mode
+    }
+}
+
+// maps the standard sql type names onto their legacy equivalents
+fn normalize_type(ty: &str) -> &str {
+    match ty {
+        "INT64" => "INTEGER",
+        "FLOAT64" => "FLOAT",
+        "BOOL" => "BOOLEAN",
+        "STRUCT" => "RECORD",
+        ty => ty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn field(name: &str, type0: &str) -> TableField {
+        TableField {
+            name: name.to_string(),
+            type0: type0.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn existing() -> TableFieldSchema {
+        serde_json::from_str(
+            r#"{"fields": [
+                {"name": "id", "type": "INTEGER", "mode": "REQUIRED"},
+                {"name": "name", "type": "STRING"}
+            ]}"#,
+        ).unwrap()
+    }
+
+    fn schema(json: &str) -> TableFieldSchema {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // each change as a string, with whether it's additive
+    fn changes(diff: &SchemaDiff) -> Vec<(String, bool)> {
+        diff.changes.iter().map(|c| (c.to_string(), c.is_additive())).collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        let desired = schema(
+            r#"{"fields": [
+                {"name": "ID", "type": "INT64", "mode": "REQUIRED"},
+                {"name": "name", "type": "STRING", "mode": "NULLABLE"}
+            ]}"#,
+        );
+        let diff = diff(&existing(), &desired);
+        assert!(diff.is_empty());
+        assert!(diff.patch_request().is_none());
+    }
+
+    #[test]
+    fn breaking_changes() {
+        let desired = schema(
+            r#"{"fields": [
+                {"name": "id", "type": "STRING", "mode": "REQUIRED"},
+                {"name": "name", "type": "STRING", "mode": "REQUIRED"},
+                {"name": "email", "type": "STRING", "mode": "REQUIRED"}
+            ]}"#,
+        );
+        let changed = diff(&existing(), &desired);
+        assert_eq!(
+            changes(&changed),
+            vec![
+                ("change type of id from INTEGER to STRING".into(), false),
+                ("change mode of name from NULLABLE to REQUIRED".into(), false),
+                ("add email REQUIRED STRING".into(), false),
+            ]
+        );
+        assert!(!changed.is_compatible());
+        assert_eq!(changed.breaking().len(), 3);
+        assert!(changed.patch_request().is_none());
+
+        let desired = schema(r#"{"fields": [{"name": "id", "type": "INTEGER"}]}"#);
+        let removed = diff(&existing(), &desired);
+        assert_eq!(
+            changes(&removed),
+            vec![
+                ("change mode of id from REQUIRED to NULLABLE".into(), true),
+                ("remove name".into(), false),
+            ]
+        );
+        assert!(!removed.is_compatible());
+
+        let existing = schema(r#"{"fields": [{"name": "name", "type": "STRING"}]}"#);
+        let desired =
+            schema(r#"{"fields": [{"name": "name", "type": "STRING", "mode": "REPEATED"}]}"#);
+        assert_eq!(
+            changes(&diff(&existing, &desired)),
+            vec![("change mode of name from NULLABLE to REPEATED".into(), false)]
+        );
+    }
+
+    #[test]
+    fn nested_records() {
+        let existing = schema(
+            r#"{"fields": [
+                {"name": "id", "type": "INTEGER", "mode": "REQUIRED"},
+                {"name": "address", "type": "RECORD", "fields": [
+                    {"name": "city", "type": "STRING", "mode": "REQUIRED"}
+                ]}
+            ]}"#,
+        );
+        let desired = schema(
+            r#"{"fields": [
+                {"name": "id", "type": "INTEGER", "mode": "REQUIRED"},
+                {"name": "address", "type": "STRUCT", "fields": [
+                    {"name": "city", "type": "STRING"},
+                    {"name": "zip", "type": "STRING"},
+                    {"name": "geo", "type": "GEOGRAPHY", "mode": "REQUIRED"}
+                ]},
+                {"name": "tags", "type": "STRING", "mode": "REPEATED"}
+            ]}"#,
+        );
+        let diff = diff(&existing, &desired);
+        assert_eq!(
+            changes(&diff),
+            vec![
+                ("change mode of address.city from REQUIRED to NULLABLE".into(), true),
+                ("add address.zip NULLABLE STRING".into(), true),
+                ("add address.geo REQUIRED GEOGRAPHY".into(), false),
+                ("add tags REPEATED STRING".into(), true),
+            ]
+        );
+        assert_eq!(diff.additive().len(), 3);
+
+        // the full existing schema, with only the additive changes applied
+        let patch = diff.patch_request().unwrap();
+        assert_eq!(
+            serde_json::to_value(patch.schema.unwrap()).unwrap(),
+            serde_json::to_value(schema(
+                r#"{"fields": [
+                    {"name": "id", "type": "INTEGER", "mode": "REQUIRED"},
+                    {"name": "address", "type": "RECORD", "fields": [
+                        {"name": "city", "type": "STRING", "mode": "NULLABLE"},
+                        {"name": "zip", "type": "STRING"}
+                    ]},
+                    {"name": "tags", "type": "STRING", "mode": "REPEATED"}
+                ]}"#,
+            )).unwrap()
+        );
+        assert!(patch.friendly_name.is_none());
+    }
+
+    #[test]
+    fn default_mode_is_nullable() {
+        let desired = TableFieldSchema {
+            fields: vec![
+                TableField {
+                    mode: "REQUIRED".to_string(),
+                    ..field("id", "INT64")
+                },
+                field("name", "STRING"),
+            ],
+        };
+        let diff = diff(&existing(), &desired);
+        assert!(diff.changes.is_empty(), "{:?}", diff.changes);
+    }
+
+    #[test]
+    fn relax_to_default_mode() {
+        let desired = TableFieldSchema {
+            fields: vec![field("id", "INTEGER"), field("name", "STRING")],
+        };
+        let diff = diff(&existing(), &desired);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].to_string(), "change mode of id from REQUIRED to NULLABLE");
+        assert!(diff.changes[0].is_additive());
+        assert_eq!(diff.patched.fields[0].mode, "NULLABLE");
+    }
+
+    #[test]
+    fn add_with_default_mode() {
+        let mut desired = existing();
+        desired.fields.push(field("email", "STRING"));
+        let diff = diff(&existing(), &desired);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].to_string(), "add email NULLABLE STRING");
+        assert_eq!(diff.patched.fields[2].mode, "NULLABLE");
+    }
+}
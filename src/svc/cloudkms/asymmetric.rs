@@ -0,0 +1,389 @@
+//! Signing with ASYMMETRIC_SIGN keys and decrypting with ASYMMETRIC_DECRYPT keys,
+//! with the matching verification and encryption done locally.
+//!
+//! KMS signs a digest computed by the caller, with the digest algorithm named in the
+//! key version's algorithm. Verifying and encrypting need only the version's public
+//! key, which `PublicKeyCache` fetches once per version:
+//!
+//! ```ignore
+//! let version = hub.cryptokey_version_name("global", "app", "signer", "1");
+//! let signature = hub.asymmetric_sign(&version, Digest::sha256(data)?)?;
+//!
+//! // e.g. in a service that can read the public key but can't sign
+//! let valid = cache.get(&hub, &version)?.verify(data, &signature)?;
+//!
+//! // and similarly, for a decryption key
+//! let ciphertext = cache.encrypt(&hub, &version, secret)?;
+//! let secret = hub.asymmetric_decrypt(&version, &ciphertext)?;
+//! ```
+//!
+//! https://cloud.google.com/kms/docs/create-validate-signatures
+//! https://cloud.google.com/kms/docs/encrypt-decrypt-rsa
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use base64;
+use openssl::hash::{hash2, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+
+use client::{self, ApiClient};
+use super::{mk_uri, pkcs1, Hub};
+
+// one of the fields is set, to the base64 of the digest
+#[derive(Clone, Serialize, Default, Debug)]
+pub struct Digest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha384: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct AsymmetricSignRequest {
+    pub digest: Digest,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct AsymmetricSignResponse {
+    pub signature: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct AsymmetricDecryptRequest {
+    pub ciphertext: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct AsymmetricDecryptResponse {
+    pub plaintext: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+pub struct PublicKey {
+    pub pem: String,
+
+    // the key version's algorithm, e.g. EC_SIGN_P256_SHA256 or RSA_DECRYPT_OAEP_2048_SHA256
+    pub algorithm: String,
+
+    pub name: Option<String>,
+}
+
+// the ways KMS signs a digest
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Scheme {
+    RsaPss,
+    RsaPkcs1,
+    Ecdsa,
+}
+
+impl Digest {
+    pub fn sha256(data: &[u8]) -> client::Result<Digest> {
+        Ok(Digest {
+            sha256: Some(base64_hash(MessageDigest::sha256(), data)?),
+            ..Default::default()
+        })
+    }
+
+    pub fn sha384(data: &[u8]) -> client::Result<Digest> {
+        Ok(Digest {
+            sha384: Some(base64_hash(MessageDigest::sha384(), data)?),
+            ..Default::default()
+        })
+    }
+
+    pub fn sha512(data: &[u8]) -> client::Result<Digest> {
+        Ok(Digest {
+            sha512: Some(base64_hash(MessageDigest::sha512(), data)?),
+            ..Default::default()
+        })
+    }
+}
+
+impl PublicKey {
+    /// Whether `signature` was made by `asymmetric_sign` with this key version, over
+    /// the digest of `data`.
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> client::Result<bool> {
+        let (scheme, md) = match signature_algorithm(&self.algorithm) {
+            Some(algorithm) => algorithm,
+            None => {
+                return Err(client::Error::InvalidArgument(
+                    format!("{} isn't a signing algorithm", self.algorithm),
+                ))
+            }
+        };
+        let pkey = PKey::public_key_from_pem(self.pem.as_bytes())
+            .map_err(client::Error::OpenSslError)?;
+
+        match scheme {
+            Scheme::RsaPss => {
+                let rsa = pkey.rsa().map_err(client::Error::OpenSslError)?;
+                let digest = hash2(md, data).map_err(client::Error::OpenSslError)?;
+                pkcs1::verify_pss(&rsa, md, &digest, signature)
+            }
+            // ec signatures are DER encoded, as openssl expects
+            Scheme::RsaPkcs1 | Scheme::Ecdsa => {
+                let mut verifier = Verifier::new(md, &pkey).map_err(client::Error::OpenSslError)?;
+                verifier.update(data).map_err(client::Error::OpenSslError)?;
+                verifier.verify(signature).map_err(client::Error::OpenSslError)
+            }
+        }
+    }
+
+    /// Encrypts `plaintext` for `asymmetric_decrypt` with this key version. Its size is
+    /// limited by the key's, e.g. to 190 bytes for RSA_DECRYPT_OAEP_2048_SHA256.
+    pub fn encrypt(&self, plaintext: &[u8]) -> client::Result<Vec<u8>> {
+        let md = match oaep_digest(&self.algorithm) {
+            Some(md) => md,
+            None => {
+                return Err(client::Error::InvalidArgument(
+                    format!("{} isn't an encryption algorithm", self.algorithm),
+                ))
+            }
+        };
+        let rsa = Rsa::public_key_from_pem(self.pem.as_bytes())
+            .map_err(client::Error::OpenSslError)?;
+        pkcs1::encrypt_oaep(&rsa, md, plaintext)
+    }
+}
+
+/// Public keys by key version name, shared between clones.
+///
+/// A version's key pair never changes, so keys are kept for the life of the cache.
+#[derive(Clone, Default, Debug)]
+pub struct PublicKeyCache {
+    keys: Arc<RwLock<HashMap<String, PublicKey>>>,
+}
+
+impl PublicKeyCache {
+    pub fn get(&self, hub: &Hub, version: &str) -> client::Result<PublicKey> {
+        {
+            let ref keys = *self.keys.read().expect("lock to not be poisoned");
+            if let Some(key) = keys.get(version) {
+                return Ok(key.clone());
+            }
+        }
+
+        // fetched without holding the lock, so that a slow fetch doesn't block lookups
+        // of other versions; concurrent fetches of the same version keep the first key
+        let key = hub.get_public_key(version)?;
+        let ref mut keys = *self.keys.write().expect("lock to not be poisoned");
+        Ok(keys.entry(version.to_string()).or_insert(key).clone())
+    }
+
+    /// Encrypts `plaintext` locally with the key version's public key, so that only
+    /// `asymmetric_decrypt` can decrypt it; see `PublicKey::encrypt`.
+    pub fn encrypt(&self, hub: &Hub, version: &str, plaintext: &[u8]) -> client::Result<Vec<u8>> {
+        self.get(hub, version)?.encrypt(plaintext)
+    }
+}
+
+impl<'a> Hub<'a> {
+    /// `version` is a key version's resource name, see `cryptokey_version_name`.
+    pub fn get_public_key(&self, version: &str) -> client::Result<PublicKey> {
+        self.get(&mk_uri(&format!("{}/publicKey", version)), &[])
+    }
+
+    /// Signs `digest`, which must use the key version's digest algorithm.
+    pub fn asymmetric_sign(&self, version: &str, digest: Digest) -> client::Result<Vec<u8>> {
+        let uri = mk_uri(&format!("{}:asymmetricSign", version));
+        let req = AsymmetricSignRequest { digest: digest };
+        let res = self.post::<_, AsymmetricSignResponse>(&uri, req, &[])?;
+
+        let signature = res.signature.expect("signature to be set");
+        Ok(base64::decode(&signature.as_bytes()).expect(
+            "signature to be base64",
+        ))
+    }
+
+    /// Decrypts `ciphertext`, e.g. from `PublicKeyCache::encrypt`.
+    pub fn asymmetric_decrypt(&self, version: &str, ciphertext: &[u8]) -> client::Result<Vec<u8>> {
+        let uri = mk_uri(&format!("{}:asymmetricDecrypt", version));
+        let req = AsymmetricDecryptRequest { ciphertext: base64::encode(ciphertext) };
+        let res = self.post::<_, AsymmetricDecryptResponse>(&uri, req, &[])?;
+
+        let plaintext = res.plaintext.expect("plaintext to be set");
+        Ok(base64::decode(&plaintext.as_bytes()).expect(
+            "plaintext to be base64",
+        ))
+    }
+}
+
+fn base64_hash(md: MessageDigest, data: &[u8]) -> client::Result<String> {
+    let digest = hash2(md, data).map_err(client::Error::OpenSslError)?;
+    Ok(base64::encode(&*digest))
+}
+
+// e.g. RSA_SIGN_PSS_2048_SHA256 or EC_SIGN_P384_SHA384; raw PKCS1 keys sign data
+// without a digest, and so aren't supported
+fn signature_algorithm(algorithm: &str) -> Option<(Scheme, MessageDigest)> {
+    let scheme = if algorithm.starts_with("RSA_SIGN_PSS_") {
+        Scheme::RsaPss
+    } else if algorithm.starts_with("RSA_SIGN_PKCS1_") {
+        Scheme::RsaPkcs1
+    } else if algorithm.starts_with("EC_SIGN_") {
+        Scheme::Ecdsa
+    } else {
+        return None;
+    };
+
+    if algorithm.ends_with("_SHA256") {
+        Some((scheme, MessageDigest::sha256()))
+    } else if algorithm.ends_with("_SHA384") {
+        Some((scheme, MessageDigest::sha384()))
+    } else if algorithm.ends_with("_SHA512") {
+        Some((scheme, MessageDigest::sha512()))
+    } else {
+        None
+    }
+}
+
+// e.g. RSA_DECRYPT_OAEP_3072_SHA256
+fn oaep_digest(algorithm: &str) -> Option<MessageDigest> {
+    if !algorithm.starts_with("RSA_DECRYPT_OAEP_") {
+        None
+    } else if algorithm.ends_with("_SHA1") {
+        Some(MessageDigest::sha1())
+    } else if algorithm.ends_with("_SHA256") {
+        Some(MessageDigest::sha256())
+    } else if algorithm.ends_with("_SHA512") {
+        Some(MessageDigest::sha512())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid;
+    use openssl::sign::Signer;
+
+    use client::{GoogleCloudClient, Transport};
+    use super::*;
+
+    const DATA: &[u8] = b"hello kms";
+
+    fn sign(pkey: &PKey, md: MessageDigest) -> Vec<u8> {
+        let mut signer = Signer::new(md, pkey).unwrap();
+        signer.update(DATA).unwrap();
+        signer.sign_to_vec().unwrap()
+    }
+
+    fn public_key(pkey: &PKey, algorithm: &str) -> PublicKey {
+        PublicKey {
+            pem: String::from_utf8(pkey.public_key_to_pem().unwrap()).unwrap(),
+            algorithm: algorithm.to_string(),
+            name: None,
+        }
+    }
+
+    #[test]
+    fn verify_ec() {
+        let group = EcGroup::from_curve_name(nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let signature = sign(&pkey, MessageDigest::sha256());
+
+        let key = public_key(&pkey, "EC_SIGN_P256_SHA256");
+        assert!(key.verify(DATA, &signature).unwrap());
+        assert!(!key.verify(b"hello kms!", &signature).unwrap());
+        assert!(!public_key(&pkey, "EC_SIGN_P256_SHA384").verify(DATA, &signature).unwrap());
+    }
+
+    #[test]
+    fn verify_rsa_pkcs1() {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let signature = sign(&pkey, MessageDigest::sha512());
+
+        let key = public_key(&pkey, "RSA_SIGN_PKCS1_2048_SHA512");
+        assert!(key.verify(DATA, &signature).unwrap());
+        assert!(!key.verify(b"hello kms!", &signature).unwrap());
+        let sha256 = public_key(&pkey, "RSA_SIGN_PKCS1_2048_SHA256");
+        assert!(!sha256.verify(DATA, &signature).unwrap());
+    }
+
+    #[test]
+    fn unsupported_algorithms() {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let signature = sign(&pkey, MessageDigest::sha256());
+
+        for algorithm in &[
+            "RSA_SIGN_RAW_PKCS1_2048",
+            "RSA_SIGN_PKCS1_2048_SHA1",
+            "RSA_DECRYPT_OAEP_2048_SHA256",
+            "GOOGLE_SYMMETRIC_ENCRYPTION",
+            "",
+        ] {
+            assert!(signature_algorithm(algorithm).is_none(), "{}", algorithm);
+            match public_key(&pkey, algorithm).verify(DATA, &signature) {
+                Err(client::Error::InvalidArgument(_)) => {}
+                res => panic!("expected InvalidArgument for {}, got {:?}", algorithm, res),
+            }
+        }
+        for algorithm in &[
+            "RSA_DECRYPT_OAEP_2048_SHA384",
+            "RSA_DECRYPT_OAEP_2048",
+            "RSA_SIGN_PKCS1_2048_SHA256",
+            "EC_SIGN_P256_SHA256",
+        ] {
+            assert!(oaep_digest(algorithm).is_none(), "{}", algorithm);
+            match public_key(&pkey, algorithm).encrypt(b"secret") {
+                Err(client::Error::InvalidArgument(_)) => {}
+                res => panic!("expected InvalidArgument for {}, got {:?}", algorithm, res),
+            }
+        }
+
+        assert!(signature_algorithm("RSA_SIGN_PSS_4096_SHA512").is_some());
+        assert!(signature_algorithm("EC_SIGN_P384_SHA384").is_some());
+        assert!(oaep_digest("RSA_DECRYPT_OAEP_3072_SHA1").is_some());
+        assert!(oaep_digest("RSA_DECRYPT_OAEP_4096_SHA512").is_some());
+    }
+
+    // serves one public key, counting the calls
+    struct FakeKms {
+        pem: String,
+        calls: Mutex<usize>,
+    }
+
+    impl Transport for FakeKms {
+        fn handle(&self, _: &hyper::Method, uri: &hyper::Uri, _: &[u8]) -> (u16, Vec<u8>) {
+            *self.calls.lock().unwrap() += 1;
+            assert!(uri.path().ends_with("/publicKey"));
+            let res = format!(
+                "{{\"pem\":{:?},\"algorithm\":\"RSA_DECRYPT_OAEP_2048_SHA256\"}}",
+                self.pem
+            );
+            (200, res.into_bytes())
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let kms = Arc::new(FakeKms {
+            pem: String::from_utf8(pkey.public_key_to_pem().unwrap()).unwrap(),
+            calls: Mutex::new(0),
+        });
+        let client = GoogleCloudClient::with_transport("p", kms.clone());
+        let hub: Hub = client.hub();
+        let version = "projects/p/locations/global/keyRings/r/cryptoKeys/k/cryptoKeyVersions/1";
+
+        let cache = PublicKeyCache::default();
+        assert_eq!(cache.get(&hub, version).unwrap().pem, kms.pem);
+        assert_eq!(cache.encrypt(&hub, version, b"secret").unwrap().len(), 256);
+        assert_eq!(cache.clone().get(&hub, version).unwrap().pem, kms.pem);
+        assert_eq!(*kms.calls.lock().unwrap(), 1);
+
+        cache.get(&hub, &version.replace("/1", "/2")).unwrap();
+        assert_eq!(*kms.calls.lock().unwrap(), 2);
+    }
+}
@@ -0,0 +1,547 @@
+//! Envelope encryption: payloads are encrypted locally with AES-256-GCM under a data
+//! key (DEK), and only the data key is encrypted ("wrapped") with a KMS crypto key.
+//!
+//! Payloads aren't limited in size by KMS, and a data key is reused for a while, so
+//! most payloads don't need a KMS call at all:
+//!
+//! ```ignore
+//! let cipher = EnvelopeCipher::new(&hub.cryptokey_name("global", "app", "dek"));
+//! let envelope = cipher.encrypt(&hub, record, b"table=users")?;
+//! store(envelope.to_bytes());
+//!
+//! let envelope = Envelope::from_bytes(&load())?;
+//! let record = cipher.decrypt(&hub, &envelope)?;
+//! ```
+//!
+//! https://cloud.google.com/kms/docs/envelope-encryption
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use base64;
+use openssl::rand::rand_bytes;
+use openssl::symm::{self, Cipher};
+use serde::{de, Deserialize, Deserializer, Serializer};
+use serde_json;
+
+use client;
+use super::Hub;
+
+// the format of serialized envelopes
+pub const ENVELOPE_VERSION: u32 = 1;
+
+const DATA_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+const DEFAULT_TTL_SECS: u64 = 5 * 60; // 5 minutes
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// An encrypted payload, with everything but the KMS key needed to decrypt it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub version: u32,
+
+    // the resource name of the crypto key that wrapped the data key
+    pub key_name: String,
+
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub wrapped_key: Vec<u8>,
+
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+
+    // authenticated along with the payload, but not encrypted
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub aad: Vec<u8>,
+
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub ciphertext: Vec<u8>,
+
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub tag: Vec<u8>,
+}
+
+impl Envelope {
+    /// Serializes the envelope as JSON.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("envelope to serialize")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> client::Result<Envelope> {
+        let envelope: Envelope = serde_json::from_slice(bytes).map_err(client::Error::JsonError)?;
+        envelope.check()?;
+        Ok(envelope)
+    }
+
+    // rejects envelopes that `EnvelopeCipher::encrypt` couldn't have made; in particular,
+    // openssl accepts GCM tags as short as a byte, which are easily forged
+    fn check(&self) -> client::Result<()> {
+        if self.version != ENVELOPE_VERSION {
+            return Err(client::Error::InvalidArgument(
+                format!("unsupported envelope version {}", self.version),
+            ));
+        }
+        if self.nonce.len() != NONCE_LEN {
+            return Err(client::Error::InvalidArgument(
+                format!("envelope nonce of {} bytes isn't {} bytes", self.nonce.len(), NONCE_LEN),
+            ));
+        }
+        if self.tag.len() != TAG_LEN {
+            return Err(client::Error::InvalidArgument(
+                format!("envelope tag of {} bytes isn't {} bytes", self.tag.len(), TAG_LEN),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Encrypts and decrypts envelopes, caching data keys; clones share the caches.
+///
+/// New envelopes reuse a data key until its ttl passes, and unwrapped data keys are
+/// kept for the ttl, up to `capacity` of them, so that decrypting envelopes with the
+/// same data key only calls KMS once.
+#[derive(Clone, Debug)]
+pub struct EnvelopeCipher {
+    key_name: String,
+
+    // other crypto keys whose envelopes may be decrypted, e.g. after a key change
+    allowed_keys: Vec<String>,
+
+    ttl: time::Duration,
+    capacity: usize,
+
+    // the data key that new envelopes are encrypted with
+    current: Arc<Mutex<Option<DataKey>>>,
+
+    // unwrapped data keys by their wrapped form
+    keys: Arc<Mutex<HashMap<Vec<u8>, DataKey>>>,
+}
+
+#[derive(Clone)]
+struct DataKey {
+    key: Vec<u8>,
+    wrapped: Vec<u8>,
+    expires_at: time::Instant,
+}
+
+// NOTE keep data keys out of logs
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DataKey {{ expires_at: {:?} }}", self.expires_at)
+    }
+}
+
+impl DataKey {
+    fn is_expired(&self) -> bool {
+        time::Instant::now() > self.expires_at
+    }
+}
+
+impl EnvelopeCipher {
+    /// `key_name` is the resource name of an ENCRYPT_DECRYPT crypto key, see
+    /// `Hub::cryptokey_name`.
+    pub fn new(key_name: &str) -> EnvelopeCipher {
+        EnvelopeCipher::with_cache(
+            key_name,
+            time::Duration::from_secs(DEFAULT_TTL_SECS),
+            DEFAULT_CAPACITY,
+        )
+    }
+
+    pub fn with_cache(key_name: &str, ttl: time::Duration, capacity: usize) -> EnvelopeCipher {
+        EnvelopeCipher {
+            key_name: key_name.to_string(),
+            allowed_keys: vec![],
+            ttl: ttl,
+            capacity: capacity,
+            current: Arc::new(Mutex::new(None)),
+            keys: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Also decrypts envelopes wrapped with `key_name`, e.g. while moving to a new key.
+    pub fn allow_key(mut self, key_name: &str) -> EnvelopeCipher {
+        self.allowed_keys.push(key_name.to_string());
+        self
+    }
+
+    pub fn encrypt(&self, hub: &Hub, plaintext: &[u8], aad: &[u8]) -> client::Result<Envelope> {
+        let data_key = self.current_key(hub)?;
+
+        // random nonces are safe for far more envelopes than a data key lives for
+        let mut nonce = vec![0; NONCE_LEN];
+        rand_bytes(&mut nonce).map_err(client::Error::OpenSslError)?;
+        let mut tag = vec![0; TAG_LEN];
+        let ciphertext = symm::encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &data_key.key,
+            Some(&nonce),
+            &authenticated_data(ENVELOPE_VERSION, &self.key_name, &data_key.wrapped, aad),
+            plaintext,
+            &mut tag,
+        ).map_err(client::Error::OpenSslError)?;
+
+        Ok(Envelope {
+            version: ENVELOPE_VERSION,
+            key_name: self.key_name.clone(),
+            wrapped_key: data_key.wrapped,
+            nonce: nonce,
+            aad: aad.to_vec(),
+            ciphertext: ciphertext,
+            tag: tag,
+        })
+    }
+
+    /// Decrypts an envelope wrapped with our crypto key or an allowed one, failing if the
+    /// envelope was tampered with.
+    pub fn decrypt(&self, hub: &Hub, envelope: &Envelope) -> client::Result<Vec<u8>> {
+        envelope.check()?;
+        // the key name comes from the envelope, so is checked before asking KMS to unwrap
+        if envelope.key_name != self.key_name && !self.allowed_keys.contains(&envelope.key_name) {
+            return Err(client::Error::InvalidArgument(
+                format!("envelope key {} isn't allowed", envelope.key_name),
+            ));
+        }
+
+        let key = self.unwrap_key(hub, &envelope.key_name, &envelope.wrapped_key)?;
+        symm::decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(&envelope.nonce),
+            &authenticated_data(
+                envelope.version,
+                &envelope.key_name,
+                &envelope.wrapped_key,
+                &envelope.aad,
+            ),
+            &envelope.ciphertext,
+            &envelope.tag,
+        ).map_err(client::Error::OpenSslError)
+    }
+
+    // the unexpired data key for new envelopes, generating and wrapping one if needed
+    fn current_key(&self, hub: &Hub) -> client::Result<DataKey> {
+        let mut current = self.current.lock().expect("lock to not be poisoned");
+        if let Some(ref data_key) = *current {
+            if !data_key.is_expired() {
+                return Ok(data_key.clone());
+            }
+        }
+
+        let mut key = vec![0; DATA_KEY_LEN];
+        rand_bytes(&mut key).map_err(client::Error::OpenSslError)?;
+        let data_key = DataKey {
+            wrapped: hub.encrypt(&self.key_name, &key, None)?,
+            key: key,
+            expires_at: time::Instant::now() + self.ttl,
+        };
+
+        // so that decrypting our own envelopes doesn't call KMS
+        self.cache(data_key.clone());
+        *current = Some(data_key.clone());
+        Ok(data_key)
+    }
+
+    fn unwrap_key(&self, hub: &Hub, key_name: &str, wrapped: &[u8]) -> client::Result<Vec<u8>> {
+        {
+            let keys = self.keys.lock().expect("lock to not be poisoned");
+            if let Some(data_key) = keys.get(wrapped) {
+                if !data_key.is_expired() {
+                    return Ok(data_key.key.clone());
+                }
+            }
+        }
+
+        // NOTE the lock isn't held while calling KMS, so concurrent misses on the same
+        // data key may each unwrap it
+        let key = hub.decrypt(key_name, wrapped, None)?;
+        self.cache(DataKey {
+            key: key.clone(),
+            wrapped: wrapped.to_vec(),
+            expires_at: time::Instant::now() + self.ttl,
+        });
+        Ok(key)
+    }
+
+    fn cache(&self, data_key: DataKey) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut keys = self.keys.lock().expect("lock to not be poisoned");
+        if keys.len() >= self.capacity && !keys.contains_key(&data_key.wrapped) {
+            keys.retain(|_, k| !k.is_expired());
+        }
+        // otherwise evict the key closest to expiring, i.e. the oldest
+        if keys.len() >= self.capacity && !keys.contains_key(&data_key.wrapped) {
+            let oldest = keys.iter()
+                .min_by_key(|&(_, k)| k.expires_at)
+                .map(|(wrapped, _)| wrapped.clone());
+            if let Some(wrapped) = oldest {
+                keys.remove(&wrapped);
+            }
+        }
+        keys.insert(data_key.wrapped.clone(), data_key);
+    }
+}
+
+// the envelope's header and the caller's aad, each length prefixed so that bytes can't
+// move between fields
+fn authenticated_data(version: u32, key_name: &str, wrapped_key: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 + key_name.len() + wrapped_key.len() + aad.len());
+    data.extend_from_slice(&u32_bytes(version));
+    for field in &[key_name.as_bytes(), wrapped_key, aad] {
+        data.extend_from_slice(&u32_bytes(field.len() as u32));
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+fn u32_bytes(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn serialize_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(bytes))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    base64::decode(&s).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use base64;
+    use hyper;
+    use serde_json;
+
+    use client::{GoogleCloudClient, Transport};
+    use super::*;
+
+    const KEY_NAME: &str = "projects/p/locations/global/keyRings/r/cryptoKeys/k";
+
+    // "wraps" data keys by flipping their bits, counting the calls
+    #[derive(Default)]
+    struct FakeKms {
+        calls: Mutex<usize>,
+    }
+
+    impl Transport for FakeKms {
+        fn handle(&self, _: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            *self.calls.lock().unwrap() += 1;
+            let req: HashMap<String, String> = serde_json::from_slice(body).unwrap();
+            let (field, input) = if uri.path().ends_with(":encrypt") {
+                ("ciphertext", &req["plaintext"])
+            } else {
+                ("plaintext", &req["ciphertext"])
+            };
+            let output: Vec<u8> = base64::decode(input).unwrap().iter().map(|b| !b).collect();
+            let res = format!("{{\"{}\":\"{}\"}}", field, base64::encode(&output));
+            (200, res.into_bytes())
+        }
+    }
+
+    fn client() -> (Arc<FakeKms>, GoogleCloudClient) {
+        let kms = Arc::new(FakeKms::default());
+        let client = GoogleCloudClient::with_transport("p", kms.clone());
+        (kms, client)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let cipher = EnvelopeCipher::new(KEY_NAME);
+
+        let first = cipher.encrypt(&hub, b"first", b"aad").unwrap();
+        let second = cipher.encrypt(&hub, b"second", b"").unwrap();
+        assert_eq!(first.wrapped_key, second.wrapped_key);
+        assert!(first.nonce != second.nonce);
+
+        let envelope = Envelope::from_bytes(&first.to_bytes()).unwrap();
+        assert_eq!(envelope, first);
+        assert_eq!(cipher.decrypt(&hub, &envelope).unwrap(), b"first");
+        assert_eq!(cipher.decrypt(&hub, &second).unwrap(), b"second");
+        // one call to wrap the data key, none to unwrap it
+        assert_eq!(*kms.calls.lock().unwrap(), 1);
+
+        let other = EnvelopeCipher::new(KEY_NAME);
+        assert_eq!(other.decrypt(&hub, &first).unwrap(), b"first");
+        assert_eq!(other.decrypt(&hub, &second).unwrap(), b"second");
+        assert_eq!(*kms.calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn truncated() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let cipher = EnvelopeCipher::new(KEY_NAME);
+        let envelope = cipher.encrypt(&hub, b"secret", b"").unwrap();
+
+        let mut short_tag = envelope.clone();
+        short_tag.tag.truncate(1);
+        assert!(cipher.decrypt(&hub, &short_tag).is_err());
+        assert!(Envelope::from_bytes(&short_tag.to_bytes()).is_err());
+
+        let mut short_nonce = envelope.clone();
+        short_nonce.nonce.truncate(8);
+        assert!(cipher.decrypt(&hub, &short_nonce).is_err());
+        assert!(Envelope::from_bytes(&short_nonce.to_bytes()).is_err());
+
+        let mut long_nonce = envelope.clone();
+        long_nonce.nonce.push(0);
+        assert!(cipher.decrypt(&hub, &long_nonce).is_err());
+
+        // rejected before unwrapping the data key
+        let other = EnvelopeCipher::new(KEY_NAME);
+        assert!(other.decrypt(&hub, &short_tag).is_err());
+        assert_eq!(*kms.calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn tampered() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let cipher = EnvelopeCipher::new(KEY_NAME);
+        let envelope = cipher.encrypt(&hub, b"secret", b"aad").unwrap();
+
+        let mut ciphertext = envelope.clone();
+        ciphertext.ciphertext[0] ^= 1;
+        assert!(cipher.decrypt(&hub, &ciphertext).is_err());
+
+        let mut tag = envelope.clone();
+        tag.tag[15] ^= 1;
+        assert!(cipher.decrypt(&hub, &tag).is_err());
+
+        let mut nonce = envelope.clone();
+        nonce.nonce[0] ^= 1;
+        assert!(cipher.decrypt(&hub, &nonce).is_err());
+
+        let mut aad = envelope.clone();
+        aad.aad = b"other".to_vec();
+        assert!(cipher.decrypt(&hub, &aad).is_err());
+
+        let mut wrapped_key = envelope.clone();
+        wrapped_key.wrapped_key[0] ^= 1;
+        assert!(cipher.decrypt(&hub, &wrapped_key).is_err());
+
+        let mut version = envelope.clone();
+        version.version += 1;
+        assert!(cipher.decrypt(&hub, &version).is_err());
+
+        // bytes moved between the aad and the wrapped key
+        let mut moved = envelope.clone();
+        let last = moved.wrapped_key.pop().unwrap();
+        moved.aad.insert(0, last);
+        assert!(cipher.decrypt(&hub, &moved).is_err());
+    }
+
+    #[test]
+    fn key_names() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let old = EnvelopeCipher::new("projects/p/locations/global/keyRings/r/cryptoKeys/old");
+        let envelope = old.encrypt(&hub, b"secret", b"").unwrap();
+
+        let cipher = EnvelopeCipher::new(KEY_NAME);
+        match cipher.decrypt(&hub, &envelope) {
+            Err(client::Error::InvalidArgument(_)) => {}
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+        // rejected without calling KMS
+        assert_eq!(*kms.calls.lock().unwrap(), 1);
+
+        let cipher = cipher.allow_key(&envelope.key_name);
+        assert_eq!(cipher.decrypt(&hub, &envelope).unwrap(), b"secret");
+
+        // the fake KMS unwraps with any key, but the key name is authenticated
+        let mut renamed = envelope.clone();
+        renamed.key_name = KEY_NAME.to_string();
+        assert!(cipher.decrypt(&hub, &renamed).is_err());
+    }
+
+    #[test]
+    fn expired_keys() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let cipher = EnvelopeCipher::with_cache(KEY_NAME, time::Duration::from_millis(50), 2);
+        let calls = || *kms.calls.lock().unwrap();
+
+        let first = cipher.encrypt(&hub, b"first", b"").unwrap();
+        assert_eq!(cipher.decrypt(&hub, &first).unwrap(), b"first");
+        assert_eq!(calls(), 1);
+
+        thread::sleep(time::Duration::from_millis(100));
+        // the unwrapped key expired, so it's unwrapped again and cached anew
+        assert_eq!(cipher.decrypt(&hub, &first).unwrap(), b"first");
+        assert_eq!(calls(), 2);
+        assert_eq!(cipher.decrypt(&hub, &first).unwrap(), b"first");
+        assert_eq!(calls(), 2);
+
+        // and new envelopes get a new data key
+        let second = cipher.encrypt(&hub, b"second", b"").unwrap();
+        assert!(second.wrapped_key != first.wrapped_key);
+        assert_eq!(calls(), 3);
+    }
+
+    #[test]
+    fn capacity() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let calls = || *kms.calls.lock().unwrap();
+
+        // each from a different data key
+        let envelopes: Vec<Envelope> = (0..3)
+            .map(|i| {
+                thread::sleep(time::Duration::from_millis(1));
+                EnvelopeCipher::new(KEY_NAME).encrypt(&hub, &[i], b"").unwrap()
+            })
+            .collect();
+        assert_eq!(calls(), 3);
+
+        let cipher = EnvelopeCipher::with_cache(KEY_NAME, time::Duration::from_secs(60), 2);
+        for (i, envelope) in envelopes.iter().enumerate() {
+            thread::sleep(time::Duration::from_millis(1));
+            assert_eq!(cipher.decrypt(&hub, envelope).unwrap(), vec![i as u8]);
+        }
+        assert_eq!(calls(), 6);
+
+        // the oldest key was evicted to make room for the third
+        cipher.decrypt(&hub, &envelopes[1]).unwrap();
+        cipher.decrypt(&hub, &envelopes[2]).unwrap();
+        assert_eq!(calls(), 6);
+        cipher.decrypt(&hub, &envelopes[0]).unwrap();
+        assert_eq!(calls(), 7);
+        // which evicted the next oldest
+        cipher.decrypt(&hub, &envelopes[2]).unwrap();
+        assert_eq!(calls(), 7);
+        cipher.decrypt(&hub, &envelopes[1]).unwrap();
+        assert_eq!(calls(), 8);
+    }
+
+    #[test]
+    fn no_cache() {
+        let (kms, client) = client();
+        let hub: Hub = client.hub();
+        let cipher = EnvelopeCipher::with_cache(KEY_NAME, time::Duration::from_secs(60), 0);
+        let calls = || *kms.calls.lock().unwrap();
+
+        let envelope = cipher.encrypt(&hub, b"secret", b"").unwrap();
+        assert_eq!(cipher.decrypt(&hub, &envelope).unwrap(), b"secret");
+        assert_eq!(cipher.decrypt(&hub, &envelope).unwrap(), b"secret");
+        assert_eq!(calls(), 3);
+
+        // the current data key is still reused until it expires
+        let other = cipher.encrypt(&hub, b"other", b"").unwrap();
+        assert_eq!(other.wrapped_key, envelope.wrapped_key);
+        assert_eq!(calls(), 3);
+    }
+}
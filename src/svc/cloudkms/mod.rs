@@ -0,0 +1,530 @@
+//! Cloud KMS key rings, crypto keys and crypto key versions.
+//!
+//! Keys live in a key ring in a location, e.g. `global` or `us-east1`, and every
+//! method takes the location along with the key ring and key ids:
+//!
+//! ```ignore
+//! hub.create_keyring("us-east1", "app")?;
+//! let key = CryptoKey::new(ENCRYPT_DECRYPT).rotation(Duration::days(90), next);
+//! hub.create_cryptokey("us-east1", "app", "secrets", &key)?;
+//! ```
+//!
+//! https://cloud.google.com/kms/docs/reference/rest
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use base64;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use hyper::{self, Uri};
+
+use client::{self, ApiClient};
+use svc::common::Empty;
+
+pub mod asymmetric;
+pub mod envelope;
+mod pkcs1;
+
+pub use self::asymmetric::{Digest, PublicKey, PublicKeyCache};
+pub use self::envelope::{Envelope, EnvelopeCipher};
+
+static CLOUDKMS_ROOT: &str = "https://cloudkms.googleapis.com/v1";
+
+// crypto key purposes
+pub const ENCRYPT_DECRYPT: &str = "ENCRYPT_DECRYPT";
+pub const ASYMMETRIC_SIGN: &str = "ASYMMETRIC_SIGN";
+pub const ASYMMETRIC_DECRYPT: &str = "ASYMMETRIC_DECRYPT";
+
+// protection levels
+pub const SOFTWARE: &str = "SOFTWARE";
+pub const HSM: &str = "HSM";
+
+// crypto key version states that can be set through a patch
+pub const ENABLED: &str = "ENABLED";
+pub const DISABLED: &str = "DISABLED";
+
+pub struct CloudKeyMgmtService {}
+pub type Hub<'a> = client::Hub<'a, CloudKeyMgmtService>;
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRing {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_time: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CryptoKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    // only ENCRYPT_DECRYPT keys are rotated automatically
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_rotation_time: Option<String>,
+
+    // a duration in seconds, e.g. "7776000s"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_period: Option<String>,
+
+    // only set for ENCRYPT_DECRYPT keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<CryptoKeyVersion>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_time: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<String>,
+
+    // used when creating new versions, including through rotation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_template: Option<CryptoKeyVersionTemplate>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CryptoKeyVersionTemplate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protection_level: Option<String>,
+
+    // e.g. GOOGLE_SYMMETRIC_ENCRYPTION or EC_SIGN_P256_SHA256
+    pub algorithm: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CryptoKeyVersion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protection_level: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destroy_time: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_time: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generate_time: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destroy_event_time: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct EncryptRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plaintext: Option<String>,
+
+    #[serde(rename = "additionalAuthenticatedData")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additional_authenticated_data: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct EncryptResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ciphertext: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DecryptRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ciphertext: Option<String>,
+
+    #[serde(rename = "additionalAuthenticatedData")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additional_authenticated_data: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DecryptResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plaintext: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdatePrimaryVersionRequest {
+    crypto_key_version_id: String,
+}
+
+#[derive(Default, Debug)]
+pub struct ListRequest {
+    // e.g. `labels.env=prod`
+    pub filter: Option<String>,
+    // e.g. `name desc`
+    pub order_by: Option<String>,
+    pub page_size: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+impl ListRequest {
+    fn to_query(&self) -> String {
+        let mut params = vec![];
+        if let Some(ref filter) = self.filter {
+            params.push(("filter", filter.clone()));
+        }
+        if let Some(ref order_by) = self.order_by {
+            params.push(("orderBy", order_by.clone()));
+        }
+        if let Some(ref page_size) = self.page_size {
+            params.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListKeyRingsResponse {
+    #[serde(default)]
+    pub key_rings: Vec<KeyRing>,
+    pub next_page_token: Option<String>,
+    pub total_size: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListCryptoKeysResponse {
+    #[serde(default)]
+    pub crypto_keys: Vec<CryptoKey>,
+    pub next_page_token: Option<String>,
+    pub total_size: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListCryptoKeyVersionsResponse {
+    #[serde(default)]
+    pub crypto_key_versions: Vec<CryptoKeyVersion>,
+    pub next_page_token: Option<String>,
+    pub total_size: Option<usize>,
+}
+
+impl CryptoKey {
+    pub fn new(purpose: &str) -> CryptoKey {
+        CryptoKey {
+            purpose: Some(purpose.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the protection level and algorithm of the key's versions, e.g.
+    /// `(HSM, "RSA_SIGN_PSS_2048_SHA256")`.
+    pub fn template(mut self, protection_level: &str, algorithm: &str) -> CryptoKey {
+        self.version_template = Some(CryptoKeyVersionTemplate {
+            protection_level: Some(protection_level.to_string()),
+            algorithm: algorithm.to_string(),
+        });
+        self
+    }
+
+    /// Rotates the key every `period` (at least a day), starting at `next`.
+    pub fn rotation(mut self, period: Duration, next: DateTime<Utc>) -> CryptoKey {
+        self.rotation_period = Some(format!("{}s", period.num_seconds()));
+        self.next_rotation_time = Some(next.to_rfc3339_opts(SecondsFormat::Secs, true));
+        self
+    }
+
+    pub fn label(mut self, key: &str, value: &str) -> CryptoKey {
+        self.labels
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+impl<'a> Hub<'a> {
+    pub fn keyring_name(&self, location: &str, keyring: &str) -> String {
+        format!(
+            "projects/{}/locations/{}/keyRings/{}",
+            self.project_id(),
+            location,
+            keyring
+        )
+    }
+
+    /// The resource name that `encrypt` and `decrypt` take.
+    pub fn cryptokey_name(&self, location: &str, keyring: &str, keyid: &str) -> String {
+        format!("{}/cryptoKeys/{}", self.keyring_name(location, keyring), keyid)
+    }
+
+    pub fn cryptokey_version_name(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> String {
+        format!(
+            "{}/cryptoKeyVersions/{}",
+            self.cryptokey_name(location, keyring, keyid),
+            version
+        )
+    }
+
+    pub fn create_keyring(&self, location: &str, keyring: &str) -> client::Result<KeyRing> {
+        let path = format!(
+            "projects/{}/locations/{}/keyRings?keyRingId={}",
+            self.project_id(),
+            location,
+            keyring
+        );
+        self.post(&mk_uri(&path), KeyRing::default(), &[])
+    }
+
+    pub fn get_keyring(&self, location: &str, keyring: &str) -> client::Result<KeyRing> {
+        self.get(&mk_uri(&self.keyring_name(location, keyring)), &[])
+    }
+
+    pub fn list_keyrings(
+        &self,
+        location: &str,
+        req: &ListRequest,
+    ) -> client::Result<ListKeyRingsResponse> {
+        let path = format!(
+            "projects/{}/locations/{}/keyRings?{}",
+            self.project_id(),
+            location,
+            req.to_query()
+        );
+        self.get(&mk_uri(&path), &[])
+    }
+
+    /// Creates a key along with its first version, e.g. from `CryptoKey::new(ENCRYPT_DECRYPT)`.
+    pub fn create_cryptokey(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        key: &CryptoKey,
+    ) -> client::Result<CryptoKey> {
+        let path = format!(
+            "{}/cryptoKeys?cryptoKeyId={}",
+            self.keyring_name(location, keyring),
+            keyid
+        );
+        self.post(&mk_uri(&path), key, &[])
+    }
+
+    pub fn get_cryptokey(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+    ) -> client::Result<CryptoKey> {
+        self.get(&mk_uri(&self.cryptokey_name(location, keyring, keyid)), &[])
+    }
+
+    pub fn list_cryptokeys(
+        &self,
+        location: &str,
+        keyring: &str,
+        req: &ListRequest,
+    ) -> client::Result<ListCryptoKeysResponse> {
+        let path = format!(
+            "{}/cryptoKeys?{}",
+            self.keyring_name(location, keyring),
+            req.to_query()
+        );
+        self.get(&mk_uri(&path), &[])
+    }
+
+    /// Updates the fields of the key named in `update_mask`, e.g. `["labels", "rotationPeriod"]`.
+    pub fn patch_cryptokey(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        key: &CryptoKey,
+        update_mask: &[&str],
+    ) -> client::Result<CryptoKey> {
+        let path = format!(
+            "{}?{}",
+            self.cryptokey_name(location, keyring, keyid),
+            client::encode_query_params(vec![("updateMask", update_mask.join(","))])
+        );
+        self.send(hyper::Method::Patch, &mk_uri(&path), key, &[])
+    }
+
+    /// Makes `version` the one that `encrypt` uses (ENCRYPT_DECRYPT keys only).
+    pub fn update_primary_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKey> {
+        let path = format!(
+            "{}:updatePrimaryVersion",
+            self.cryptokey_name(location, keyring, keyid)
+        );
+        let req = UpdatePrimaryVersionRequest { crypto_key_version_id: version.to_string() };
+        self.post(&mk_uri(&path), req, &[])
+    }
+
+    /// Creates a version from the key's template; it doesn't become the primary.
+    pub fn create_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let path = format!(
+            "{}/cryptoKeyVersions",
+            self.cryptokey_name(location, keyring, keyid)
+        );
+        self.post(&mk_uri(&path), CryptoKeyVersion::default(), &[])
+    }
+
+    pub fn get_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let name = self.cryptokey_version_name(location, keyring, keyid, version);
+        self.get(&mk_uri(&name), &[])
+    }
+
+    pub fn list_cryptokey_versions(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        req: &ListRequest,
+    ) -> client::Result<ListCryptoKeyVersionsResponse> {
+        let path = format!(
+            "{}/cryptoKeyVersions?{}",
+            self.cryptokey_name(location, keyring, keyid),
+            req.to_query()
+        );
+        self.get(&mk_uri(&path), &[])
+    }
+
+    pub fn enable_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let name = self.cryptokey_version_name(location, keyring, keyid, version);
+        self.set_version_state(&name, ENABLED)
+    }
+
+    pub fn disable_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let name = self.cryptokey_version_name(location, keyring, keyid, version);
+        self.set_version_state(&name, DISABLED)
+    }
+
+    /// Schedules the version's key material to be destroyed (after 24 hours, by default).
+    pub fn destroy_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let name = self.cryptokey_version_name(location, keyring, keyid, version);
+        self.post(&mk_uri(&format!("{}:destroy", name)), Empty {}, &[])
+    }
+
+    /// Cancels a scheduled destruction, leaving the version disabled.
+    pub fn restore_cryptokey_version(
+        &self,
+        location: &str,
+        keyring: &str,
+        keyid: &str,
+        version: &str,
+    ) -> client::Result<CryptoKeyVersion> {
+        let name = self.cryptokey_version_name(location, keyring, keyid, version);
+        self.post(&mk_uri(&format!("{}:restore", name)), Empty {}, &[])
+    }
+
+    fn set_version_state(&self, name: &str, state: &str) -> client::Result<CryptoKeyVersion> {
+        let uri = mk_uri(&format!("{}?updateMask=state", name));
+        let req = CryptoKeyVersion {
+            state: Some(state.to_string()),
+            ..Default::default()
+        };
+        self.send(hyper::Method::Patch, &uri, req, &[])
+    }
+
+    pub fn encrypt(
+        &self,
+        cryptokey: &str,
+        plaintext: &[u8],
+        nonce: Option<&str>,
+    ) -> client::Result<Vec<u8>> {
+        let path = format!("{}/{}:encrypt", CLOUDKMS_ROOT, cryptokey);
+
+        let req = EncryptRequest {
+            plaintext: Some(base64::encode(plaintext)),
+            additional_authenticated_data: nonce.map(|d| d.to_string()),
+        };
+
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        let res = self.post::<_, EncryptResponse>(&uri, req, &[])?;
+
+        let ciphertext = res.ciphertext.expect("ciphertext to be set");
+        Ok(base64::decode(&ciphertext.as_bytes()).expect(
+            "ciphertext to be base64",
+        ))
+    }
+
+    pub fn decrypt(
+        &self,
+        cryptokey: &str,
+        ciphertext: &[u8],
+        nonce: Option<&str>,
+    ) -> client::Result<Vec<u8>> {
+        let path = format!("{}/{}:decrypt", CLOUDKMS_ROOT, cryptokey);
+
+        let req = DecryptRequest {
+            ciphertext: Some(base64::encode(ciphertext)),
+            additional_authenticated_data: nonce.map(|d| d.to_string()),
+        };
+
+        let uri = Uri::from_str(&path).expect("uri to be valid");
+        let res = self.post::<_, DecryptResponse>(&uri, req, &[])?;
+
+        let plaintext = res.plaintext.expect("plaintext to be set");
+        Ok(base64::decode(&plaintext.as_bytes()).expect(
+            "plaintext to be base64",
+        ))
+    }
+}
+
+// `path` is a resource name, optionally followed by a method and query
+fn mk_uri(path: &str) -> Uri {
+    Uri::from_str(&format!("{}/{}", CLOUDKMS_ROOT, path)).expect("uri to be valid")
+}
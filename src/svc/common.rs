@@ -1,3 +1,297 @@
+use std::{cmp, io, thread, time};
+use std::str::FromStr;
+
+use hyper::Uri;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use client::{self, ApiClient};
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Empty {}
+
+// https://cloud.google.com/iam/docs/reference/rest/v1/Policy
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Policy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bindings: Option<Vec<Binding>>,
+
+    // must be sent back unchanged when setting a policy, to detect concurrent updates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Binding {
+    pub role: String,
+
+    // e.g. user:alice@example.com, group:admins@example.com or serviceAccount:...
+    pub members: Vec<String>,
+
+    // conditional bindings require policy version 3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Expr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Expr {
+    pub expression: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetIamPolicyRequest {
+    pub options: GetPolicyOptions,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetPolicyOptions {
+    pub requested_policy_version: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetIamPolicyRequest {
+    pub policy: Policy,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TestIamPermissionsRequest {
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+pub type TestIamPermissionsResponse = TestIamPermissionsRequest;
+
+// https://cloud.google.com/datastore/docs/reference/admin/rest/Shared.Types/Operation
+//
+// the long-running operation type shared by google apis; `metadata` and `response`
+// depend on the kind of operation, see `Operation::metadata` and `Operation::response`
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation {
+    // e.g. projects/my-project/operations/ASA1MTAwNDQxNjM
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+
+    #[serde(default)]
+    pub done: bool,
+
+    // set if the operation failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Status>,
+
+    // set if the operation succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+}
+
+// https://cloud.google.com/datastore/docs/reference/admin/rest/Shared.Types/Status
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    // a google.rpc.Code, e.g. 1 for CANCELLED
+    #[serde(default)]
+    pub code: i32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Vec<serde_json::Value>>,
+}
+
+impl Operation {
+    /// The operation's metadata as the service-specific type `T`.
+    pub fn metadata<T: DeserializeOwned>(&self) -> client::Result<Option<T>> {
+        decode_any(&self.metadata)
+    }
+
+    /// The response of a successful operation as the service-specific type `T`.
+    pub fn response<T: DeserializeOwned>(&self) -> client::Result<Option<T>> {
+        decode_any(&self.response)
+    }
+
+    /// The response of a finished operation, or its error as an api error.
+    pub fn into_result<T: DeserializeOwned>(self) -> client::Result<Option<T>> {
+        match self.error {
+            Some(status) => Err(client::Error::ApiError(client::ApiError {
+                error: Some(client::ErrorDetails {
+                    code: Some(status.code as usize),
+                    message: status.message,
+                    status: Some(rpc_code_name(status.code).to_string()),
+                }),
+                error_description: None,
+            })),
+            None => decode_any(&self.response),
+        }
+    }
+}
+
+// metadata and responses carry an `@type` field, which is ignored
+fn decode_any<T: DeserializeOwned>(value: &Option<serde_json::Value>) -> client::Result<Option<T>> {
+    match *value {
+        Some(ref value) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(client::Error::JsonError),
+        None => Ok(None),
+    }
+}
+
+// https://github.com/googleapis/googleapis/blob/master/google/rpc/code.proto
+fn rpc_code_name(code: i32) -> &'static str {
+    match code {
+        0 => "OK",
+        1 => "CANCELLED",
+        3 => "INVALID_ARGUMENT",
+        4 => "DEADLINE_EXCEEDED",
+        5 => "NOT_FOUND",
+        6 => "ALREADY_EXISTS",
+        7 => "PERMISSION_DENIED",
+        8 => "RESOURCE_EXHAUSTED",
+        9 => "FAILED_PRECONDITION",
+        10 => "ABORTED",
+        11 => "OUT_OF_RANGE",
+        12 => "UNIMPLEMENTED",
+        13 => "INTERNAL",
+        14 => "UNAVAILABLE",
+        15 => "DATA_LOSS",
+        16 => "UNAUTHENTICATED",
+        _ => "UNKNOWN",
+    }
+}
+
+// the filter and paging parameters shared by list methods, e.g. of operations
+#[derive(Default, Debug)]
+pub struct ListRequest {
+    pub filter: Option<String>,
+    pub page_size: Option<usize>,
+    pub page_token: Option<String>,
+}
+
+pub type ListOperationsRequest = ListRequest;
+
+impl ListRequest {
+    pub fn to_query(&self) -> String {
+        let mut params = vec![];
+        if let Some(ref filter) = self.filter {
+            params.push(("filter", filter.clone()));
+        }
+        if let Some(ref page_size) = self.page_size {
+            params.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(ref page_token) = self.page_token {
+            params.push(("pageToken", page_token.clone()));
+        }
+        client::encode_query_params(params)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListOperationsResponse {
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+
+    pub next_page_token: Option<String>,
+}
+
+/// Gets, lists, cancels and waits for the long-running operations of a service.
+///
+/// Operation names are relative to the service's root, e.g.
+/// `Operations::new(hub, "https://datastore.googleapis.com/v1")`.
+pub struct Operations<'c, C: 'c> {
+    client: &'c C,
+    root: &'static str,
+}
+
+// the interval between polls starts here and doubles up to the maximum
+const OPERATION_INITIAL_POLL_MS: u64 = 1000;
+const OPERATION_MAX_POLL_MS: u64 = 30 * 1000;
+
+impl<'c, C: ApiClient> Operations<'c, C> {
+    pub fn new(client: &'c C, root: &'static str) -> Self {
+        Operations {
+            client: client,
+            root: root,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> client::Result<Operation> {
+        let uri = self.mk_uri(name, "");
+        self.client.get(&uri, &[])
+    }
+
+    /// Lists the operations under `parent`, e.g. `projects/my-project`.
+    pub fn list(
+        &self,
+        parent: &str,
+        req: &ListOperationsRequest,
+    ) -> client::Result<ListOperationsResponse> {
+        let uri = self.mk_uri(&format!("{}/operations", parent), &format!("?{}", req.to_query()));
+        self.client.get(&uri, &[])
+    }
+
+    /// Asks the service to cancel the operation; it may still finish, so use `wait` or
+    /// `get` to see how it ended.
+    pub fn cancel(&self, name: &str) -> client::Result<()> {
+        let uri = self.mk_uri(name, ":cancel");
+        self.client.post::<_, Empty>(&uri, Empty {}, &[]).map(|_| ())
+    }
+
+    /// Deletes a finished operation's record (it doesn't cancel the operation).
+    pub fn delete(&self, name: &str) -> client::Result<()> {
+        let uri = self.mk_uri(name, "");
+        self.client.delete::<Empty>(&uri, &[]).map(|_| ())
+    }
+
+    /// Polls the operation with exponential backoff until it's done, or fails with a
+    /// `TimedOut` io error once `timeout` has elapsed.
+    pub fn wait(
+        &self,
+        op: Operation,
+        timeout: Option<time::Duration>,
+    ) -> client::Result<Operation> {
+        let started = time::Instant::now();
+        let mut interval = OPERATION_INITIAL_POLL_MS;
+        let mut op = op;
+        while !op.done {
+            let delay = time::Duration::from_millis(interval);
+            if let Some(timeout) = timeout {
+                if started.elapsed() + delay > timeout {
+                    return Err(client::Error::IoError(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("operation {} didn't finish in time", op.name),
+                    )));
+                }
+            }
+
+            thread::sleep(delay);
+            interval = cmp::min(interval * 2, OPERATION_MAX_POLL_MS);
+            op = self.get(&op.name)?;
+        }
+        Ok(op)
+    }
+
+    fn mk_uri(&self, name: &str, suffix: &str) -> Uri {
+        let path = format!("{}/{}{}", self.root, name, suffix);
+        Uri::from_str(&path).expect("uri to be valid")
+    }
+}
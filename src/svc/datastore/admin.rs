@@ -0,0 +1,303 @@
+//! Datastore managed export and import.
+//!
+//! Exports and imports run as long-running operations, which can be waited on with
+//! `Hub::operations`:
+//!
+//! ```ignore
+//! let filter = EntityFilter::kinds(&["Task"]);
+//! let op = hub.export_entities("gs://my-bucket/nightly", &filter, HashMap::new())?;
+//! let op = hub.operations().wait(op, None)?;
+//! let export = op.into_result::<ExportEntitiesResponse>()?.expect("a response");
+//! // later, e.g. into another project
+//! hub.import_entities(&export.output_url, &filter, HashMap::new())?;
+//! ```
+//!
+//! https://cloud.google.com/datastore/docs/export-import-entities
+use std::collections::HashMap;
+
+use client::{self, ApiClient};
+use super::{Hub, DATASTORE_ROOT};
+use svc::common::{Operation, Operations};
+
+/// Which entities to export or import; empty lists mean every kind or namespace.
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<String>,
+
+    // "" is the default namespace
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespace_ids: Vec<String>,
+}
+
+impl EntityFilter {
+    pub fn all() -> EntityFilter {
+        EntityFilter::default()
+    }
+
+    pub fn kinds(kinds: &[&str]) -> EntityFilter {
+        EntityFilter {
+            kinds: kinds.iter().map(|k| k.to_string()).collect(),
+            namespace_ids: vec![],
+        }
+    }
+
+    pub fn in_namespaces(mut self, namespace_ids: &[&str]) -> EntityFilter {
+        self.namespace_ids = namespace_ids.iter().map(|ns| ns.to_string()).collect();
+        self
+    }
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportEntitiesRequest {
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+
+    pub entity_filter: EntityFilter,
+
+    // gs://bucket[/namespace-path]; the export is written under a generated prefix
+    pub output_url_prefix: String,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEntitiesRequest {
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+
+    // the export's overall_export_metadata file, see `ExportEntitiesResponse::output_url`
+    pub input_url: String,
+
+    pub entity_filter: EntityFilter,
+}
+
+// the response of a finished export operation
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportEntitiesResponse {
+    pub output_url: String,
+}
+
+// the metadata of export operations
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportEntitiesMetadata {
+    #[serde(default)]
+    pub common: CommonMetadata,
+
+    pub progress_entities: Option<Progress>,
+    pub progress_bytes: Option<Progress>,
+    pub entity_filter: Option<EntityFilter>,
+    pub output_url_prefix: Option<String>,
+}
+
+// the metadata of import operations
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEntitiesMetadata {
+    #[serde(default)]
+    pub common: CommonMetadata,
+
+    pub progress_entities: Option<Progress>,
+    pub progress_bytes: Option<Progress>,
+    pub entity_filter: Option<EntityFilter>,
+    pub input_url: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonMetadata {
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+
+    // e.g. EXPORT_ENTITIES or IMPORT_ENTITIES
+    pub operation_type: Option<String>,
+
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+
+    // e.g. PROCESSING, CANCELLING, SUCCESSFUL or CANCELLED
+    pub state: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Progress {
+    // int64s serialized as strings
+    pub work_completed: Option<String>,
+    pub work_estimated: Option<String>,
+}
+
+impl<'a> Hub<'a> {
+    /// Starts exporting the entities matching `filter` to cloud storage.
+    pub fn export_entities(
+        &self,
+        output_url_prefix: &str,
+        filter: &EntityFilter,
+        labels: HashMap<String, String>,
+    ) -> client::Result<Operation> {
+        let req = ExportEntitiesRequest {
+            labels: labels,
+            entity_filter: filter.clone(),
+            output_url_prefix: output_url_prefix.to_string(),
+        };
+        let uri = self.mk_uri("export");
+        self.post(&uri, req, &[])
+    }
+
+    /// Starts importing the entities matching `filter` from a previous export.
+    pub fn import_entities(
+        &self,
+        input_url: &str,
+        filter: &EntityFilter,
+        labels: HashMap<String, String>,
+    ) -> client::Result<Operation> {
+        let req = ImportEntitiesRequest {
+            labels: labels,
+            input_url: input_url.to_string(),
+            entity_filter: filter.clone(),
+        };
+        let uri = self.mk_uri("import");
+        self.post(&uri, req, &[])
+    }
+
+    /// The datastore operations of the project, e.g. to wait for an export; list them
+    /// with `hub.operations().list(&format!("projects/{}", hub.project_id()), ..)`.
+    pub fn operations(&self) -> Operations<Self> {
+        Operations::new(self, DATASTORE_ROOT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use hyper;
+    use serde_json;
+
+    use client::{self, GoogleCloudClient, Transport};
+    use super::*;
+
+    const OP: &str = "projects/test/operations/op1";
+
+    // answers each request with the next scripted operation, keeping the requests
+    #[derive(Default)]
+    struct Script {
+        operations: Mutex<VecDeque<String>>,
+        requests: Mutex<Vec<(hyper::Method, String, String)>>,
+    }
+
+    impl Transport for Script {
+        fn handle(&self, method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let request = (method.clone(), uri.path().to_string(), body);
+            self.requests.lock().unwrap().push(request);
+            let op = self.operations.lock().unwrap().pop_front().expect("a scripted operation");
+            (200, op.into_bytes())
+        }
+    }
+
+    fn scripted(operations: &[&str]) -> (Arc<Script>, GoogleCloudClient) {
+        let script = Arc::new(Script::default());
+        script.operations.lock().unwrap().extend(operations.iter().map(|op| op.to_string()));
+        let client = GoogleCloudClient::with_transport("test", script.clone());
+        (script, client)
+    }
+
+    fn running(state: &str) -> String {
+        format!(
+            r#"{{"name": "{}", "metadata": {{
+                "@type": "type.googleapis.com/google.datastore.admin.v1.ExportEntitiesMetadata",
+                "common": {{"operationType": "EXPORT_ENTITIES", "state": "{}"}},
+                "progressEntities": {{"workCompleted": "10", "workEstimated": "100"}},
+                "outputUrlPrefix": "gs://bucket/nightly"
+            }}}}"#,
+            OP,
+            state
+        )
+    }
+
+    #[test]
+    fn export_and_wait() {
+        let done = format!(
+            r#"{{"name": "{}", "done": true, "response": {{
+                "@type": "type.googleapis.com/google.datastore.admin.v1.ExportEntitiesResponse",
+                "outputUrl": "gs://bucket/nightly/2024_01_02/2024_01_02.overall_export_metadata"
+            }}}}"#,
+            OP
+        );
+        let (script, client) = scripted(&[&running("PROCESSING"), &done]);
+        let hub: Hub = client.hub();
+
+        let filter = EntityFilter::kinds(&["Task"]).in_namespaces(&[""]);
+        let op = hub.export_entities("gs://bucket/nightly", &filter, HashMap::new()).unwrap();
+        assert!(!op.done);
+        let metadata: ExportEntitiesMetadata = op.metadata().unwrap().unwrap();
+        assert_eq!(metadata.common.state, Some("PROCESSING".to_string()));
+        assert_eq!(metadata.progress_entities.unwrap().work_completed, Some("10".to_string()));
+
+        let op = hub.operations().wait(op, None).unwrap();
+        assert!(op.done);
+        let export = op.into_result::<ExportEntitiesResponse>().unwrap().unwrap();
+        assert!(export.output_url.ends_with(".overall_export_metadata"));
+
+        let requests = script.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].0, hyper::Method::Post);
+        assert!(requests[0].1.ends_with("/v1/projects/test:export"));
+        let body: serde_json::Value = serde_json::from_str(&requests[0].2).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(
+            r#"{"entityFilter": {"kinds": ["Task"], "namespaceIds": [""]},
+                "outputUrlPrefix": "gs://bucket/nightly"}"#,
+        ).unwrap();
+        assert_eq!(body, expected);
+        assert_eq!(requests[1].0, hyper::Method::Get);
+        assert!(requests[1].1.ends_with(&format!("/v1/{}", OP)));
+    }
+
+    #[test]
+    fn failed() {
+        let failed = format!(
+            r#"{{"name": "{}", "done": true, "error": {{
+                "code": 7, "message": "the service account can't write to the bucket"
+            }}}}"#,
+            OP
+        );
+        let (_, client) = scripted(&[&failed]);
+        let hub: Hub = client.hub();
+
+        let op = hub.export_entities("gs://bucket", &EntityFilter::all(), HashMap::new()).unwrap();
+        let op = hub.operations().wait(op, None).unwrap();
+        let err = op.into_result::<ExportEntitiesResponse>().unwrap_err();
+        assert_eq!(err.status(), Some("PERMISSION_DENIED"));
+        match err {
+            client::Error::ApiError(client::ApiError { error: Some(details), .. }) => {
+                assert_eq!(details.code, Some(7));
+                assert_eq!(
+                    details.message,
+                    Some("the service account can't write to the bucket".to_string())
+                );
+            }
+            err => panic!("expected an api error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn timed_out() {
+        let (script, client) = scripted(&[&running("PROCESSING")]);
+        let hub: Hub = client.hub();
+
+        let op = hub.export_entities("gs://bucket", &EntityFilter::all(), HashMap::new()).unwrap();
+        // shorter than the first poll interval, so it gives up without polling
+        match hub.operations().wait(op, Some(Duration::from_millis(10))) {
+            Err(client::Error::IoError(ref e)) if e.kind() == io::ErrorKind::TimedOut => {}
+            res => panic!("expected a timeout, got {:?}", res),
+        }
+        assert_eq!(script.requests.lock().unwrap().len(), 1);
+    }
+}
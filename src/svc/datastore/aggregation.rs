@@ -0,0 +1,303 @@
+//! Datastore aggregation queries.
+//!
+//! An `AggregationQuery` computes aggregates over the results of a nested `Query`
+//! without returning the entities themselves:
+//!
+//! ```ignore
+//! let query = AggregationQuery::new(Query::new("Task").filter(Filter::eq("done", false)))
+//!     .count("open")
+//!     .avg("avg_priority", "priority");
+//! let res = hub.run_aggregation_query("ns", &query, None)?;
+//! let open = res["open"].as_i64();
+//! ```
+//!
+//! The same can be expressed in GQL with `Hub::gql_aggregation`, e.g.
+//! `AGGREGATE COUNT(*) AS open OVER (SELECT * FROM Task WHERE done = @done)`.
+//!
+//! https://cloud.google.com/datastore/docs/aggregation-queries
+use client::{self, ApiClient};
+use super::{GqlQuery, Hub, PartitionId, Query, ReadOptions, Value, ValueMap};
+use super::query::PropertyReference;
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationQuery {
+    pub nested_query: Query,
+
+    // at most 5 aggregations are allowed per query
+    pub aggregations: Vec<Aggregation>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Aggregation {
+    // defaults to `property_<n>` if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<Count>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sum: Option<PropertyAggregation>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg: Option<PropertyAggregation>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Count {
+    // int64 serialized as a string; counting stops once this many entities matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up_to: Option<String>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyAggregation {
+    pub property: PropertyReference,
+}
+
+impl AggregationQuery {
+    pub fn new(query: Query) -> AggregationQuery {
+        AggregationQuery {
+            nested_query: query,
+            aggregations: vec![],
+        }
+    }
+
+    pub fn count(self, alias: &str) -> AggregationQuery {
+        self.aggregate(alias, Aggregation {
+            count: Some(Count::default()),
+            ..Default::default()
+        })
+    }
+
+    /// Counts at most `up_to` entities, which bounds the cost of the query.
+    pub fn count_up_to(self, alias: &str, up_to: i64) -> AggregationQuery {
+        self.aggregate(alias, Aggregation {
+            count: Some(Count { up_to: Some(up_to.to_string()) }),
+            ..Default::default()
+        })
+    }
+
+    pub fn sum(self, alias: &str, property: &str) -> AggregationQuery {
+        self.aggregate(alias, Aggregation {
+            sum: Some(PropertyAggregation::of(property)),
+            ..Default::default()
+        })
+    }
+
+    pub fn avg(self, alias: &str, property: &str) -> AggregationQuery {
+        self.aggregate(alias, Aggregation {
+            avg: Some(PropertyAggregation::of(property)),
+            ..Default::default()
+        })
+    }
+
+    fn aggregate(mut self, alias: &str, mut aggregation: Aggregation) -> AggregationQuery {
+        aggregation.alias = Some(alias.to_string());
+        self.aggregations.push(aggregation);
+        self
+    }
+}
+
+impl PropertyAggregation {
+    fn of(property: &str) -> PropertyAggregation {
+        PropertyAggregation { property: PropertyReference { name: property.to_string() } }
+    }
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RunAggregationQueryRequest {
+    partition_id: PartitionId,
+    read_options: ReadOptions,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregation_query: Option<AggregationQuery>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gql_query: Option<GqlQuery>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RunAggregationQueryResponse {
+    pub batch: AggregationResultBatch,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationResultBatch {
+    #[serde(default)]
+    pub aggregation_results: Vec<AggregationResult>,
+
+    pub read_time: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationResult {
+    // keyed by alias; COUNT yields an integer, SUM an integer or double, AVG a double
+    // (or null when no entity had the property)
+    #[serde(default)]
+    pub aggregate_properties: ValueMap,
+}
+
+impl<'a> Hub<'a> {
+    /// Runs `query`, returning each aggregate's value by alias.
+    pub fn run_aggregation_query<R: Into<ReadOptions>>(
+        &self,
+        ns: &str,
+        query: &AggregationQuery,
+        read: R,
+    ) -> client::Result<ValueMap> {
+        self.aggregate(ns, read.into(), Some(query.clone()), None)
+    }
+
+    /// Runs a GQL `AGGREGATE ... OVER (...)` query, returning each aggregate's value by alias.
+    pub fn gql_aggregation<R, B>(
+        &self,
+        ns: &str,
+        q: &str,
+        read: R,
+        bindings: B,
+    ) -> client::Result<ValueMap>
+    where
+        R: Into<ReadOptions>,
+        B: IntoIterator<Item = (String, Value)>,
+    {
+        self.aggregate(ns, read.into(), None, Some(GqlQuery::new(q, bindings)))
+    }
+
+    fn aggregate(
+        &self,
+        ns: &str,
+        read_options: ReadOptions,
+        query: Option<AggregationQuery>,
+        gql_query: Option<GqlQuery>,
+    ) -> client::Result<ValueMap> {
+        read_options.validate()?;
+        let req = RunAggregationQueryRequest {
+            partition_id: PartitionId {
+                project_id: self.project_id().to_string(),
+                namespace_id: Some(ns.to_string()),
+                database_id: None,
+            },
+            read_options: read_options,
+            aggregation_query: query,
+            gql_query: gql_query,
+        };
+
+        let uri = self.mk_uri("runAggregationQuery");
+        let res = self.post::<_, RunAggregationQueryResponse>(&uri, req, &[])?;
+
+        // an aggregation query always produces a single result
+        Ok(
+            res.batch
+                .aggregation_results
+                .into_iter()
+                .next()
+                .map(|r| r.aggregate_properties)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper;
+    use serde_json;
+
+    use client::{GoogleCloudClient, Transport};
+    use super::super::{Filter, Hub};
+    use super::*;
+
+    fn json(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn serialization() {
+        let query = AggregationQuery::new(Query::new("Task").filter(Filter::eq("done", false)))
+            .count("open")
+            .count_up_to("some", 1000)
+            .sum("total", "priority")
+            .avg("average", "priority");
+        assert_eq!(
+            serde_json::to_value(&query).unwrap(),
+            json(
+                r#"{
+                    "nestedQuery": {
+                        "kind": [{"name": "Task"}],
+                        "filter": {"propertyFilter": {
+                            "property": {"name": "done"},
+                            "op": "EQUAL",
+                            "value": {"booleanValue": false}
+                        }}
+                    },
+                    "aggregations": [
+                        {"alias": "open", "count": {}},
+                        {"alias": "some", "count": {"upTo": "1000"}},
+                        {"alias": "total", "sum": {"property": {"name": "priority"}}},
+                        {"alias": "average", "avg": {"property": {"name": "priority"}}}
+                    ]
+                }"#
+            )
+        );
+    }
+
+    // answers with a canned response, keeping the request
+    struct Canned {
+        response: &'static str,
+        request: Mutex<Option<serde_json::Value>>,
+    }
+
+    impl Transport for Canned {
+        fn handle(&self, _: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            assert!(uri.path().ends_with(":runAggregationQuery"));
+            *self.request.lock().unwrap() = Some(serde_json::from_slice(body).unwrap());
+            (200, self.response.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn results() {
+        let canned = Arc::new(Canned {
+            response: r#"{"batch": {
+                "aggregationResults": [{"aggregateProperties": {
+                    "open": {"integerValue": "42"},
+                    "total": {"integerValue": "-7"},
+                    "average": {"doubleValue": 2.5},
+                    "none": {"nullValue": null}
+                }}],
+                "moreResults": "NO_MORE_RESULTS",
+                "readTime": "2024-01-02T03:04:05.123456Z"
+            }}"#,
+            request: Mutex::new(None),
+        });
+        let client = GoogleCloudClient::with_transport("test", canned.clone());
+        let hub: Hub = client.hub();
+
+        let query = AggregationQuery::new(Query::new("Task")).count("open");
+        let res = hub.run_aggregation_query("ns", &query, None).unwrap();
+        assert_eq!(res.len(), 4);
+        assert_eq!(res["open"].as_i64(), Some(42));
+        assert_eq!(res["total"].as_i64(), Some(-7));
+        assert_eq!(res["average"].as_f64(), Some(2.5));
+        assert!(res["none"].is_null());
+
+        let request = canned.request.lock().unwrap().take().unwrap();
+        assert_eq!(request["partitionId"]["namespaceId"], "ns");
+        assert_eq!(request["aggregationQuery"]["aggregations"][0]["alias"], "open");
+        assert!(request.get("gqlQuery").is_none());
+
+        // no results, e.g. for an empty batch
+        let batch: RunAggregationQueryResponse = serde_json::from_str(r#"{"batch": {}}"#).unwrap();
+        assert!(batch.batch.aggregation_results.is_empty());
+    }
+}
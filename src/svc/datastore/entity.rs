@@ -0,0 +1,941 @@
+//! Serde-based mapping between rust types and datastore entities.
+//!
+//! Any `#[derive(Serialize, Deserialize)]` struct can be converted to and from the
+//! properties of an `Entity`:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Task {
+//!     #[serde(rename = "__key__")]
+//!     key: Option<Key>,
+//!     title: String,
+//!     #[serde(with = "entity::timestamp")]
+//!     created: DateTime<Utc>,
+//!     #[serde(with = "entity::unindexed")]
+//!     notes: String,
+//!     tags: Vec<String>,
+//! }
+//!
+//! let entity = entity::to_entity(None, &task)?;
+//! let task: Task = entity::from_entity(entity)?;
+//! ```
+//!
+//! Values are mapped as follows:
+//!
+//! - booleans, integers (up to `i64::MAX`), floats and strings map to the matching
+//!   datastore value types, and `None`/`()` to null
+//! - sequences map to array values (datastore does not allow nested arrays)
+//! - structs and maps map to embedded entity values (without a key)
+//! - fields of type `Key` map to key values, and `LatLng` to geo point values
+//! - enums map to a string for unit variants, and to an embedded entity with a
+//!   single property named after the variant otherwise
+//! - fields marked `#[serde(with = "entity::timestamp")]` map to timestamp values
+//!   (`entity::option_timestamp` for optional ones); without it they are strings
+//! - fields marked `#[serde(with = "entity::blob")]` map to (unindexed) blob values
+//! - fields marked `#[serde(with = "entity::unindexed")]` are excluded from indexes
+//!
+//! A field named `__key__` holds the entity's own key: `from_entity` fills it in,
+//! and `to_entity` uses it when no key is given explicitly.
+use std::error;
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::marker::PhantomData;
+
+use serde::{de, ser, Deserialize, Serialize};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde_json;
+
+use super::{Entity, Key, LatLng, Value, ValueMap, ValueType};
+use super::query::KEY_PROPERTY;
+
+// (de)serializers recognize these newtype struct names from the `with` modules
+const TIMESTAMP_TOKEN: &str = "$__datastore_timestamp";
+const UNINDEXED_TOKEN: &str = "$__datastore_unindexed";
+// and these from the `Key` and `LatLng` impls; other formats see through the newtypes
+pub(super) const KEY_TOKEN: &str = "$__datastore_key";
+pub(super) const GEO_POINT_TOKEN: &str = "$__datastore_geo_point";
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Converts `value` into an entity; `key` takes precedence over a `__key__` field.
+pub fn to_entity<T: Serialize>(key: Option<Key>, value: &T) -> Result<Entity> {
+    let mut properties = to_properties(value)?;
+    let own_key = match properties.remove(KEY_PROPERTY).map(|v| v.value_type) {
+        Some(ValueType::Key(key)) => Some(key),
+        _ => None,
+    };
+    Ok(Entity {
+        key: key.or(own_key),
+        properties: Some(properties),
+    })
+}
+
+/// Converts `value` into entity properties; it must serialize as a struct or map.
+pub fn to_properties<T: Serialize>(value: &T) -> Result<ValueMap> {
+    match to_value(value)?.value_type {
+        ValueType::Entity(entity) => Ok(entity.properties.unwrap_or_default()),
+        _ => Err(Error("only structs and maps can be converted to entities".into())),
+    }
+}
+
+pub fn to_value<T: Serialize>(value: &T) -> Result<Value> {
+    value.serialize(Serializer)
+}
+
+/// Converts an entity into `T`, exposing the entity's key as the `__key__` property.
+pub fn from_entity<T>(entity: Entity) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let mut properties = entity.properties.unwrap_or_default();
+    if let Some(key) = entity.key {
+        properties.insert(KEY_PROPERTY.to_string(), Value::key(key));
+    }
+    from_properties(properties)
+}
+
+pub fn from_properties<T>(properties: ValueMap) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    from_value(entity_value(properties))
+}
+
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    T::deserialize(Deserializer { value: value })
+}
+
+/// Stores a `DateTime<Utc>` as a timestamp value, for use with `#[serde(with)]`.
+pub mod timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dt: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(super::TIMESTAMP_TOKEN, &dt.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        parse(String::deserialize(d)?)
+    }
+
+    pub(super) fn parse<E: de::Error>(s: String) -> Result<DateTime<Utc>, E> {
+        DateTime::parse_from_rfc3339(&s)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|e| E::custom(format!("invalid timestamp {}: {}", s, e)))
+    }
+}
+
+/// Like `timestamp`, for `Option<DateTime<Utc>>`.
+pub mod option_timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(dt: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *dt {
+            Some(ref dt) => super::timestamp::serialize(dt, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<String>::deserialize(d)? {
+            Some(s) => super::timestamp::parse(s).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Stores bytes as an unindexed blob value, for use with `#[serde(with)]`.
+pub mod blob {
+    use std::fmt;
+
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(bytes: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        s.serialize_newtype_struct(super::UNINDEXED_TOKEN, &Bytes(bytes.as_ref()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        d.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct Bytes<'b>(&'b [u8]);
+
+    impl<'b> ::serde::Serialize for Bytes<'b> {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_bytes(self.0)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a blob")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+}
+
+/// Excludes a value from indexes, for use with `#[serde(with)]`.
+///
+/// For arrays, each element is excluded (datastore doesn't allow excluding the array).
+pub mod unindexed {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Serialize, S: Serializer>(value: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(super::UNINDEXED_TOKEN, value)
+    }
+
+    pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize(d)
+    }
+}
+
+/// Deserializes the newtype struct named `token` around a `T`, i.e. just a `T` in
+/// formats other than entities.
+pub(super) fn deserialize_newtype<'de, T, D>(
+    d: D,
+    token: &'static str,
+) -> ::std::result::Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: de::Deserializer<'de>,
+{
+    struct NewtypeVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> de::Visitor<'de> for NewtypeVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a newtype struct")
+        }
+
+        fn visit_newtype_struct<D>(self, d: D) -> ::std::result::Result<T, D::Error>
+        where
+            D: de::Deserializer<'de>,
+        {
+            T::deserialize(d)
+        }
+    }
+
+    d.deserialize_newtype_struct(token, NewtypeVisitor(PhantomData))
+}
+
+//
+// value helpers
+
+fn entity_value(properties: ValueMap) -> Value {
+    Value::entity(Entity {
+        key: None,
+        properties: Some(properties),
+    })
+}
+
+fn single_property(name: &str, value: Value) -> Value {
+    let mut properties = ValueMap::new();
+    properties.insert(name.to_string(), value);
+    entity_value(properties)
+}
+
+//
+// serialization
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeEntity;
+    type SerializeStruct = SerializeEntity;
+    type SerializeStructVariant = SerializeVariant<SerializeEntity>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        if v > ::std::i64::MAX as u64 {
+            return Err(Error(format!("{} is out of range for a datastore integer", v)));
+        }
+        Ok(Value::from(v as i64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        Ok(Value::from(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::from(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::blob(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::null())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Value> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Value> {
+        Ok(Value::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        // keys and geo points are converted through their json representation
+        if name == KEY_TOKEN {
+            return via_json::<_, Key>(value).map(Value::key);
+        } else if name == GEO_POINT_TOKEN {
+            return via_json::<_, LatLng>(value).map(Value::from);
+        }
+
+        let v = value.serialize(self)?;
+        if name == TIMESTAMP_TOKEN {
+            let ts = match v.value_type {
+                ValueType::String(ref s) => DateTime::parse_from_rfc3339(s).ok(),
+                _ => None,
+            };
+            match ts {
+                Some(ts) => Ok(Value::from(ts.with_timezone(&Utc))),
+                None => Err(Error("timestamps must be rfc3339 strings".into())),
+            }
+        } else if name == UNINDEXED_TOKEN {
+            Ok(v.unindexed())
+        } else {
+            Ok(v)
+        }
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        Ok(single_property(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray> {
+        Ok(SerializeArray { values: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeArray>> {
+        Ok(SerializeVariant {
+            variant: variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<SerializeEntity> {
+        Ok(SerializeEntity {
+            properties: ValueMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<SerializeEntity> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeEntity>> {
+        Ok(SerializeVariant {
+            variant: variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub struct SerializeArray {
+    values: Vec<Value>,
+}
+
+impl SerializeArray {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let value = value.serialize(Serializer)?;
+        if let ValueType::Array(_) = value.value_type {
+            return Err(Error("datastore arrays cannot contain arrays".into()));
+        }
+        self.values.push(value);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::array(self.values))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::array(self.values))
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::array(self.values))
+    }
+}
+
+pub struct SerializeEntity {
+    properties: ValueMap,
+    next_key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeEntity {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        let key = key.serialize(Serializer)?;
+        let name = match key.value_type {
+            ValueType::String(s) => s,
+            ValueType::Integer(i) => i.to_string(),
+            _ => return Err(Error("property names must be strings".into())),
+        };
+        self.next_key = Some(name);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.next_key.take().expect("serialize_key to be called first");
+        self.properties.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(entity_value(self.properties))
+    }
+}
+
+impl ser::SerializeStruct for SerializeEntity {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.properties.insert(key.to_string(), value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(entity_value(self.properties))
+    }
+}
+
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(single_property(self.variant, Value::array(self.inner.values)))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeEntity> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(single_property(self.variant, entity_value(self.inner.properties)))
+    }
+}
+
+//
+// deserialization
+
+pub struct Deserializer {
+    value: Value,
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        Deserializer { value: self }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value.value_type {
+            ValueType::Null => visitor.visit_unit(),
+            ValueType::Boolean(b) => visitor.visit_bool(b),
+            ValueType::Integer(i) => visitor.visit_i64(i),
+            ValueType::Double(d) => visitor.visit_f64(d),
+            ValueType::Timestamp(t) => {
+                visitor.visit_string(t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            ValueType::String(s) => visitor.visit_string(s),
+            ValueType::Blob(b) => visitor.visit_byte_buf(b),
+            // keys and geo points are deserialized through their json representation
+            ValueType::Key(key) => deserialize_json(&key, visitor),
+            ValueType::GeoPoint(point) => deserialize_json(&point, visitor),
+            ValueType::Entity(entity) => {
+                let properties = entity.properties.unwrap_or_default();
+                let mut map = de::value::MapDeserializer::new(properties.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            ValueType::Array(values) => {
+                let mut seq = de::value::SeqDeserializer::new(values.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.value.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        let json = match self.value.value_type {
+            ValueType::Key(ref key) if name == KEY_TOKEN => Some(serde_json::to_value(key)),
+            ValueType::GeoPoint(ref p) if name == GEO_POINT_TOKEN => Some(serde_json::to_value(p)),
+            _ => None,
+        };
+        match json {
+            Some(json) => json.and_then(|json| visitor.visit_newtype_struct(json))
+                .map_err(|e| Error(e.to_string())),
+            None => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let mut properties = match self.value.value_type {
+            ValueType::String(variant) => return visitor.visit_enum(variant.into_deserializer()),
+            ValueType::Entity(entity) => entity.properties.unwrap_or_default(),
+            _ => ValueMap::new(),
+        };
+        if properties.len() != 1 {
+            return Err(Error("expected a string or an entity with a single property".into()));
+        }
+        let (variant, value) = properties.drain().next().unwrap();
+        visitor.visit_enum(EnumDeserializer {
+            variant: variant,
+            value: value,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+fn via_json<T: ?Sized + Serialize, U: DeserializeOwned>(value: &T) -> Result<U> {
+    serde_json::to_value(value)
+        .and_then(serde_json::from_value)
+        .map_err(|e| Error(e.to_string()))
+}
+
+fn deserialize_json<'de, T, V>(value: &T, visitor: V) -> Result<V::Value>
+where
+    T: Serialize,
+    V: de::Visitor<'de>,
+{
+    serde_json::to_value(value)
+        .and_then(|json| de::Deserializer::deserialize_any(json, visitor))
+        .map_err(|e| Error(e.to_string()))
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Deserializer)> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, Deserializer { value: self.value }))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Address {
+        city: String,
+        zip: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Task {
+        #[serde(rename = "__key__")]
+        key: Option<Key>,
+        title: String,
+        done: bool,
+        priority: i64,
+        address: Address,
+        tags: Vec<String>,
+        assignee: Option<String>,
+        parent: Key,
+        location: LatLng,
+        #[serde(with = "timestamp")]
+        created: DateTime<Utc>,
+        #[serde(with = "option_timestamp")]
+        finished: Option<DateTime<Utc>>,
+        #[serde(with = "blob")]
+        attachment: Vec<u8>,
+        #[serde(with = "unindexed")]
+        notes: String,
+    }
+
+    fn task() -> Task {
+        Task {
+            key: Some(Key::with_id("Task", 1)),
+            title: "write tests".into(),
+            done: false,
+            priority: -3,
+            address: Address {
+                city: "Paris".into(),
+                zip: None,
+            },
+            tags: vec!["a".into(), "b".into()],
+            assignee: None,
+            parent: Key::with_name("Project", "crate"),
+            location: LatLng {
+                latitude: 48.85,
+                longitude: 2.35,
+            },
+            created: Utc.ymd(2020, 1, 2).and_hms_micro(3, 4, 5, 678901),
+            finished: None,
+            attachment: vec![0, 159, 255],
+            notes: "not indexed".into(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let entity = to_entity(None, &task()).unwrap();
+        assert_eq!(entity.key, Some(Key::with_id("Task", 1)));
+        let ref properties = entity.properties.clone().unwrap();
+        assert!(properties.get(KEY_PROPERTY).is_none());
+
+        match properties["address"].value_type {
+            ValueType::Entity(ref address) => {
+                assert!(address.key.is_none());
+                let ref props = *address.properties.as_ref().unwrap();
+                assert_eq!(props["city"].as_str(), Some("Paris"));
+                assert!(props["zip"].is_null());
+            }
+            ref v => panic!("expected an entity value, got {:?}", v),
+        }
+        match properties["tags"].value_type {
+            ValueType::Array(ref values) => {
+                let tags: Vec<_> = values.iter().map(|v| v.as_str().unwrap()).collect();
+                assert_eq!(tags, vec!["a", "b"]);
+            }
+            ref v => panic!("expected an array value, got {:?}", v),
+        }
+        assert!(properties["assignee"].is_null());
+        assert_eq!(properties["priority"].as_i64(), Some(-3));
+        assert_eq!(properties["parent"].as_key(), Some(&Key::with_name("Project", "crate")));
+        match properties["location"].value_type {
+            ValueType::GeoPoint(ref p) => assert_eq!(p.latitude, 48.85),
+            ref v => panic!("expected a geo point value, got {:?}", v),
+        }
+        assert_eq!(properties["created"].as_timestamp(), Some(&task().created));
+        assert!(properties["finished"].is_null());
+        match properties["attachment"].value_type {
+            ValueType::Blob(ref b) => assert_eq!(b, &vec![0, 159, 255]),
+            ref v => panic!("expected a blob value, got {:?}", v),
+        }
+
+        // only the marked fields are excluded from indexes
+        assert_eq!(properties["attachment"].exclude_from_indexes, Some(true));
+        assert_eq!(properties["notes"].exclude_from_indexes, Some(true));
+        assert_eq!(properties["title"].exclude_from_indexes, None);
+
+        let decoded: Task = from_entity(entity).unwrap();
+        assert_eq!(decoded, task());
+    }
+
+    #[test]
+    fn explicit_key() {
+        let key = Key::with_id("Task", 2);
+        let entity = to_entity(Some(key.clone()), &task()).unwrap();
+        assert_eq!(entity.key, Some(key.clone()));
+
+        let decoded: Task = from_entity(entity).unwrap();
+        assert_eq!(decoded.key, Some(key));
+    }
+
+    #[test]
+    fn unindexed_arrays() {
+        #[derive(Serialize)]
+        struct Notes {
+            #[serde(with = "unindexed")]
+            notes: Vec<String>,
+        }
+
+        let notes = Notes { notes: vec!["a".into()] };
+        let properties = to_properties(&notes).unwrap();
+        assert_eq!(properties["notes"].exclude_from_indexes, None);
+        match properties["notes"].value_type {
+            ValueType::Array(ref values) => assert_eq!(values[0].exclude_from_indexes, Some(true)),
+            ref v => panic!("expected an array value, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn maps_and_enums() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum State {
+            Open,
+            Blocked { on: String },
+        }
+
+        let mut states = HashMap::new();
+        states.insert("a".to_string(), State::Open);
+        states.insert("b".to_string(), State::Blocked { on: "a".into() });
+        let properties = to_properties(&states).unwrap();
+        assert_eq!(properties["a"].as_str(), Some("Open"));
+
+        let decoded: HashMap<String, State> = from_properties(properties).unwrap();
+        assert_eq!(decoded, states);
+    }
+
+    #[test]
+    fn unsupported_shapes() {
+        let mut points = HashMap::new();
+        points.insert((1, 2), "x");
+        assert!(to_properties(&points).is_err());
+
+        let mut flags = HashMap::new();
+        flags.insert(true, "x");
+        assert!(to_properties(&flags).is_err());
+
+        assert!(to_entity(None, &42).is_err());
+        assert!(to_entity(None, &vec!["a"]).is_err());
+        assert!(to_value(&::std::u64::MAX).is_err());
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let mut properties = to_properties(&task()).unwrap();
+        properties.insert("priority".into(), Value::from("high"));
+        assert!(from_properties::<Task>(properties).is_err());
+
+        let mut properties = to_properties(&task()).unwrap();
+        properties.remove("title");
+        assert!(from_properties::<Task>(properties).is_err());
+    }
+}
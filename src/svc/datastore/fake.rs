@@ -0,0 +1,1100 @@
+//! An in-memory fake of the datastore api, for tests.
+//!
+//! `FakeDatastore` is a `Transport` that serves a client's datastore requests from
+//! memory, so code written against `datastore::Hub` can run without a project:
+//!
+//! ```ignore
+//! let client = GoogleCloudClient::with_transport("test", Arc::new(FakeDatastore::new()));
+//! let hub: datastore::Hub = client.hub();
+//! hub.insert_entity_by_name("Task", "", vec![], "a", props)?;
+//! ```
+//!
+//! It supports lookups, commits (with insert/update/upsert/delete semantics, base
+//! versions, and transactions that abort when something they read or wrote changed
+//! after they began), allocateIds and reserveIds. Structured queries support kind,
+//! property, ancestor and composite filters, orders, projections, offsets, limits and
+//! cursors. GQL (`Hub::gql` and `Hub::gql_aggregation`), aggregation queries and
+//! `distinctOn` fail with UNIMPLEMENTED, and reads always see the latest commit regardless
+//! of their read time or consistency.
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use base64;
+use hyper;
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use client::Transport;
+use super::{AllocateIdsResponse, BeginTransactionResponse, CommitResponse, Entity, EntityResult,
+            Key, LookupResponse, MoreResultsType, Mutation, MutationResult, PartitionId,
+            PathElement, QueryResultBatch, ReserveIdsResponse, RollbackTransactionResponse,
+            RunQueryResponse, Value, ValueMap, ValueType};
+use super::query::{CompositeOperator, Direction, Filter, Operator, Query, KEY_PROPERTY};
+
+// the most results in a runQuery batch, so that callers page through larger results
+const BATCH_SIZE: usize = 100;
+
+// an entity's namespace and path
+type EntityId = (String, Vec<PathElement>);
+
+#[derive(Default)]
+pub struct FakeDatastore {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    entities: HashMap<EntityId, ValueMap>,
+
+    // the commit version at which each entity was last written or deleted
+    versions: HashMap<EntityId, i64>,
+
+    // the version of the latest commit
+    version: i64,
+
+    // the last allocated (or reserved) id
+    last_id: i64,
+
+    transactions: HashMap<String, Txn>,
+    transaction_count: u64,
+}
+
+struct Txn {
+    read_only: bool,
+
+    // the latest commit version when the transaction began
+    snapshot: i64,
+
+    reads: HashSet<EntityId>,
+}
+
+// an api error, as (http status, canonical status, message)
+struct Status(u16, &'static str, String);
+
+fn invalid<S: Into<String>>(message: S) -> Status {
+    Status(400, "INVALID_ARGUMENT", message.into())
+}
+
+fn unimplemented<S: Into<String>>(message: S) -> Status {
+    Status(501, "UNIMPLEMENTED", message.into())
+}
+
+impl FakeDatastore {
+    pub fn new() -> FakeDatastore {
+        FakeDatastore::default()
+    }
+
+    /// Every stored entity, ordered by namespace and key.
+    pub fn entities(&self) -> Vec<Entity> {
+        let state = self.state.lock().expect("lock to not be poisoned");
+        let mut ids: Vec<&EntityId> = state.entities.keys().collect();
+        ids.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| cmp_paths(&a.1, &b.1)));
+        ids.into_iter()
+            .map(|id| to_entity(id, &state.entities[id], ""))
+            .collect()
+    }
+}
+
+impl Transport for FakeDatastore {
+    fn handle(&self, method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+        // e.g. /v1/projects/my-project:lookup
+        let path = uri.path();
+        let resource = path.rfind("projects/").map(|i| &path[i + 9..]).unwrap_or("");
+        let (project, action) = match resource.find(':') {
+            Some(i) if *method == hyper::Method::Post => (&resource[..i], &resource[i + 1..]),
+            _ => return error(Status(404, "NOT_FOUND", format!("no such method {}", path))),
+        };
+
+        let mut state = self.state.lock().expect("lock to not be poisoned");
+        let res = match action {
+            "lookup" => call(body, |req| state.lookup(project, req)),
+            "runQuery" => call(body, |req| state.run_query(project, req)),
+            "beginTransaction" => call(body, |req| state.begin_transaction(req)),
+            "commit" => call(body, |req| state.commit(project, req)),
+            "rollback" => call(body, |req| state.rollback(req)),
+            "allocateIds" => call(body, |req| state.allocate_ids(project, req)),
+            "reserveIds" => call(body, |req| state.reserve_ids(req)),
+            _ => Err(unimplemented(format!("{} isn't supported by the fake", action))),
+        };
+        match res {
+            Ok(body) => (200, body),
+            Err(status) => error(status),
+        }
+    }
+}
+
+fn call<'de, Req, Res, F>(body: &'de [u8], f: F) -> Result<Vec<u8>, Status>
+where
+    Req: Deserialize<'de>,
+    Res: Serialize,
+    F: FnOnce(Req) -> Result<Res, Status>,
+{
+    let req = serde_json::from_slice(body).map_err(|e| invalid(format!("invalid request: {}", e)))?;
+    let res = f(req)?;
+    Ok(serde_json::to_vec(&res).expect("response to serialize"))
+}
+
+fn error(status: Status) -> (u16, Vec<u8>) {
+    #[derive(Serialize)]
+    struct ErrorResponse {
+        error: ErrorStatus,
+    }
+
+    #[derive(Serialize)]
+    struct ErrorStatus {
+        code: u16,
+        message: String,
+        status: &'static str,
+    }
+
+    let Status(code, status, message) = status;
+    let res = ErrorResponse {
+        error: ErrorStatus {
+            code: code,
+            message: message,
+            status: status,
+        },
+    };
+    (code, serde_json::to_vec(&res).expect("error to serialize"))
+}
+
+//
+// requests, as far as the fake understands them
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct ReadOptions {
+    transaction: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct LookupRequest {
+    keys: Vec<Key>,
+    read_options: ReadOptions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct RunQueryRequest {
+    partition_id: PartitionId,
+    read_options: ReadOptions,
+    query: Option<Query>,
+    gql_query: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct BeginTransactionRequest {
+    transaction_options: TransactionOptions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct TransactionOptions {
+    read_only: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct RollbackRequest {
+    transaction: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct CommitRequest {
+    transaction: Option<String>,
+    mutations: Vec<Mutation>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct KeysRequest {
+    keys: Vec<Key>,
+}
+
+enum Op {
+    Insert(Entity),
+    Upsert(Entity),
+    Update(Entity),
+    Delete(Key),
+}
+
+impl Op {
+    fn from_mutation(m: Mutation) -> Result<Op, Status> {
+        match (m.insert, m.upsert, m.update, m.delete) {
+            (Some(e), None, None, None) => Ok(Op::Insert(e)),
+            (None, Some(e), None, None) => Ok(Op::Upsert(e)),
+            (None, None, Some(e), None) => Ok(Op::Update(e)),
+            (None, None, None, Some(k)) => Ok(Op::Delete(k)),
+            _ => Err(invalid("a mutation must have exactly one operation")),
+        }
+    }
+
+    fn key(&self) -> Option<&Key> {
+        match *self {
+            Op::Insert(ref e) | Op::Upsert(ref e) | Op::Update(ref e) => e.key.as_ref(),
+            Op::Delete(ref k) => Some(k),
+        }
+    }
+}
+
+impl State {
+    fn lookup(&mut self, project: &str, req: LookupRequest) -> Result<LookupResponse, Status> {
+        let ids = req.keys.iter().map(entity_id).collect::<Result<Vec<_>, _>>()?;
+        self.record_reads(&req.read_options, ids.iter().cloned())?;
+
+        let (mut found, mut missing) = (vec![], vec![]);
+        for id in ids {
+            match self.entities.get(&id) {
+                Some(props) => found.push(EntityResult {
+                    cursor: None,
+                    version: Some(self.versions[&id].to_string()),
+                    entity: Some(to_entity(&id, props, project)),
+                }),
+                // the version of a missing entity is that of the snapshot it's missing from
+                None => missing.push(EntityResult {
+                    cursor: None,
+                    version: Some(self.version.to_string()),
+                    entity: Some(Entity {
+                        key: Some(to_key(&id, project)),
+                        properties: None,
+                    }),
+                }),
+            }
+        }
+        Ok(LookupResponse {
+            found: Some(found),
+            missing: Some(missing),
+            deferred: None,
+        })
+    }
+
+    fn begin_transaction(
+        &mut self,
+        req: BeginTransactionRequest,
+    ) -> Result<BeginTransactionResponse, Status> {
+        self.transaction_count += 1;
+        let id = base64::encode(&format!("fake-transaction-{}", self.transaction_count));
+
+        self.transactions.insert(id.clone(), Txn {
+            read_only: req.transaction_options.read_only.is_some(),
+            snapshot: self.version,
+            reads: HashSet::new(),
+        });
+        Ok(BeginTransactionResponse { transaction: id })
+    }
+
+    fn rollback(&mut self, req: RollbackRequest) -> Result<RollbackTransactionResponse, Status> {
+        match self.transactions.remove(&req.transaction) {
+            Some(_) => Ok(RollbackTransactionResponse {}),
+            None => Err(invalid(format!("unknown transaction {}", req.transaction))),
+        }
+    }
+
+    fn record_reads<I>(&mut self, read_options: &ReadOptions, ids: I) -> Result<(), Status>
+    where
+        I: IntoIterator<Item = EntityId>,
+    {
+        if let Some(ref id) = read_options.transaction {
+            match self.transactions.get_mut(id) {
+                Some(txn) => txn.reads.extend(ids),
+                None => return Err(invalid(format!("unknown transaction {}", id))),
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, project: &str, req: CommitRequest) -> Result<CommitResponse, Status> {
+        let txn = match req.transaction {
+            Some(ref id) => match self.transactions.remove(id) {
+                Some(txn) => Some(txn),
+                None => return Err(invalid(format!("unknown transaction {}", id))),
+            },
+            None => None,
+        };
+
+        // check every mutation before applying any, so that a commit is all or nothing
+        let mut writes = vec![];
+        let mut written = HashSet::new();
+        for m in req.mutations {
+            let base_version = match m.base_version {
+                Some(ref v) => Some(v.parse::<i64>().map_err(|_| invalid("invalid base version"))?),
+                None => None,
+            };
+            let op = Op::from_mutation(m)?;
+
+            let key = op.key().ok_or_else(|| invalid("an entity must have a key"))?.clone();
+            let id = match op {
+                Op::Insert(_) | Op::Upsert(_) if key.is_incomplete() => None,
+                _ => Some(entity_id(&key)?),
+            };
+            if let Some(ref id) = id {
+                if !written.insert(id.clone()) {
+                    return Err(invalid("a commit can't mutate the same entity more than once"));
+                }
+            }
+            writes.push((op, id, base_version));
+        }
+
+        if let Some(txn) = txn {
+            if txn.read_only && !writes.is_empty() {
+                return Err(invalid("a read-only transaction can't commit mutations"));
+            }
+            let changed = txn.reads
+                .iter()
+                .chain(written.iter())
+                .any(|id| self.versions.get(id).map_or(false, |&v| v > txn.snapshot));
+            if changed {
+                return Err(Status(
+                    409,
+                    "ABORTED",
+                    "too much contention on these datastore entities. please try again.".into(),
+                ));
+            }
+        }
+
+        let mut conflicts = vec![];
+        for &(ref op, ref id, base_version) in &writes {
+            let id = match *id {
+                Some(ref id) => id,
+                None => {
+                    conflicts.push(false);
+                    continue;
+                }
+            };
+            let exists = self.entities.contains_key(id);
+            let version = self.versions.get(id).cloned();
+
+            // a missing entity only conflicts if it was deleted after the base version
+            let conflict = match (base_version, version) {
+                (Some(base), Some(v)) if exists => base != v,
+                (Some(base), Some(v)) => base < v,
+                _ => false,
+            };
+            if !conflict {
+                match *op {
+                    Op::Insert(_) if exists => {
+                        return Err(Status(409, "ALREADY_EXISTS", "entity already exists".into()))
+                    }
+                    Op::Update(_) if !exists => {
+                        return Err(Status(404, "NOT_FOUND", "no entity to update".into()))
+                    }
+                    _ => {}
+                }
+            }
+            conflicts.push(conflict);
+        }
+
+        self.version += 1;
+        let mut results = vec![];
+        for ((op, id, _), conflict) in writes.into_iter().zip(conflicts) {
+            if conflict {
+                let id = id.expect("conflicts to be for complete keys");
+                results.push(MutationResult {
+                    version: Some(self.versions[&id].to_string()),
+                    conflict_detected: Some(true),
+                    key: None,
+                });
+                continue;
+            }
+
+            let mut allocated = None;
+            match op {
+                Op::Insert(entity) | Op::Upsert(entity) | Op::Update(entity) => {
+                    let mut key = entity.key.expect("key to be checked");
+                    let id = match id {
+                        Some(id) => id,
+                        None => {
+                            key.path.last_mut().expect("path to be non-empty").id =
+                                Some(self.allocate_id());
+                            let id = entity_id(&key)?;
+                            allocated = Some(to_key(&id, project));
+                            id
+                        }
+                    };
+                    self.reserve(&id.1);
+                    self.entities.insert(id.clone(), entity.properties.unwrap_or_default());
+                    self.versions.insert(id, self.version);
+                }
+                Op::Delete(_) => {
+                    let id = id.expect("key to be checked");
+                    self.entities.remove(&id);
+                    self.versions.insert(id, self.version);
+                }
+            }
+            results.push(MutationResult {
+                version: Some(self.version.to_string()),
+                conflict_detected: None,
+                key: allocated,
+            });
+        }
+
+        Ok(CommitResponse {
+            mutation_results: Some(results),
+            index_updates: None,
+        })
+    }
+
+    fn allocate_ids(
+        &mut self,
+        project: &str,
+        req: KeysRequest,
+    ) -> Result<AllocateIdsResponse, Status> {
+        if req.keys.iter().any(|k| !k.is_incomplete()) {
+            return Err(invalid("keys to allocate ids for must be incomplete"));
+        }
+
+        let mut keys = vec![];
+        for mut key in req.keys {
+            key.path.last_mut().expect("path to be non-empty").id = Some(self.allocate_id());
+            keys.push(to_key(&entity_id(&key)?, project));
+        }
+        Ok(AllocateIdsResponse { keys: keys })
+    }
+
+    fn reserve_ids(&mut self, req: KeysRequest) -> Result<ReserveIdsResponse, Status> {
+        for key in &req.keys {
+            let (_, path) = entity_id(key)?;
+            self.reserve(&path);
+        }
+        Ok(ReserveIdsResponse {})
+    }
+
+    fn allocate_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    // keeps ids in use from being allocated
+    fn reserve(&mut self, path: &[PathElement]) {
+        for e in path {
+            if let Some(id) = e.id {
+                self.last_id = cmp::max(self.last_id, id);
+            }
+        }
+    }
+
+    fn run_query(
+        &mut self,
+        project: &str,
+        req: RunQueryRequest,
+    ) -> Result<RunQueryResponse, Status> {
+        if req.gql_query.is_some() {
+            return Err(unimplemented("the fake doesn't support GQL"));
+        }
+        let query = req.query.ok_or_else(|| invalid("a query is required"))?;
+        if !query.distinct_on.is_empty() {
+            return Err(unimplemented("the fake doesn't support distinctOn"));
+        }
+        if query.kind.len() > 1 {
+            return Err(invalid("a query can have at most one kind"));
+        }
+
+        let ns = req.partition_id.namespace_id.unwrap_or_default();
+        let kind = query.kind.first().map(|k| k.name.as_str());
+        let projection: Vec<&str> = query
+            .projection
+            .iter()
+            .map(|p| p.property.name.as_str())
+            .collect();
+        let keys_only = !projection.is_empty() && projection.iter().all(|&p| p == KEY_PROPERTY);
+
+        // the matching entities, with their values for each of the query's orders
+        let mut results = vec![];
+        for (id, props) in &self.entities {
+            let entity_kind = id.1.last().map(|e| e.kind.as_str());
+            if id.0 != ns || kind.map_or(false, |k| entity_kind != Some(k)) {
+                continue;
+            }
+            let key = to_key(id, project);
+            if let Some(ref filter) = query.filter {
+                if !matches(filter, &key, props)? {
+                    continue;
+                }
+            }
+
+            // entities without a value for a projected or ordered property aren't indexed
+            let projected = projection.iter().all(|&p| p == KEY_PROPERTY || props.contains_key(p));
+            let orders: Option<Vec<Value>> = query
+                .order
+                .iter()
+                .map(|o| order_value(&o.property.name, o.direction, &key, props))
+                .collect();
+            if let (true, Some(orders)) = (projected, orders) {
+                results.push((id.clone(), orders));
+            }
+        }
+        results.sort_by(|&(ref a, ref a_orders), &(ref b, ref b_orders)| {
+            for (i, o) in query.order.iter().enumerate() {
+                let ord = match o.direction {
+                    Direction::Ascending => cmp_values(&a_orders[i], &b_orders[i]),
+                    Direction::Descending => cmp_values(&b_orders[i], &a_orders[i]),
+                };
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            cmp_paths(&a.1, &b.1)
+        });
+
+        let start = match query.start_cursor {
+            Some(ref c) => cmp::min(decode_cursor(c)?, results.len()),
+            None => 0,
+        };
+        let end = match query.end_cursor {
+            Some(ref c) => cmp::max(cmp::min(decode_cursor(c)?, results.len()), start),
+            None => results.len(),
+        };
+        let skipped = cmp::min(cmp::max(query.offset.unwrap_or(0), 0) as usize, end - start);
+        let from = start + skipped;
+        let limit = query.limit.map(|l| cmp::max(l, 0) as usize);
+        let to = cmp::min(end, from + cmp::min(limit.unwrap_or(BATCH_SIZE), BATCH_SIZE));
+
+        let truncated = to - from == BATCH_SIZE && limit.map_or(true, |l| l > BATCH_SIZE);
+        let more_results = if to < end && truncated {
+            MoreResultsType::NotFinished
+        } else if limit == Some(to - from) {
+            MoreResultsType::MoreResultsAfterLimit
+        } else if query.end_cursor.is_some() {
+            MoreResultsType::MoreResultsAfterCursor
+        } else {
+            MoreResultsType::NoMoreResults
+        };
+
+        let ids: Vec<EntityId> = results.drain(from..to).map(|(id, _)| id).collect();
+        let entity_results = ids.iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let props = &self.entities[id];
+                let entity = if keys_only {
+                    Entity {
+                        key: Some(to_key(id, project)),
+                        properties: None,
+                    }
+                } else if !projection.is_empty() {
+                    let props = projection
+                        .iter()
+                        .filter_map(|&p| props.get(p).map(|v| (p.to_string(), v.clone())))
+                        .collect();
+                    to_entity(id, &props, project)
+                } else {
+                    to_entity(id, props, project)
+                };
+                EntityResult {
+                    cursor: Some(encode_cursor(from + i + 1)),
+                    version: Some(self.versions[id].to_string()),
+                    entity: Some(entity),
+                }
+            })
+            .collect();
+        self.record_reads(&req.read_options, ids)?;
+
+        let entity_result_type = match (keys_only, projection.is_empty()) {
+            (true, _) => "KEY_ONLY",
+            (false, false) => "PROJECTION",
+            (false, true) => "FULL",
+        };
+        Ok(RunQueryResponse {
+            batch: QueryResultBatch {
+                entity_results: Some(entity_results),
+                entity_result_type: Some(entity_result_type.to_string()),
+                skipped_results: skipped as i32,
+                skipped_cursor: if skipped > 0 { Some(encode_cursor(from)) } else { None },
+                end_cursor: Some(encode_cursor(to)),
+                more_results: Some(more_results),
+                snapshot_version: Some(self.version.to_string()),
+            },
+        })
+    }
+}
+
+// cursors are positions in the query's results, which is good enough for a fake
+fn encode_cursor(position: usize) -> String {
+    base64::encode(&position.to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Result<usize, Status> {
+    base64::decode(cursor)
+        .ok()
+        .and_then(|c| String::from_utf8(c).ok())
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(|| invalid(format!("invalid cursor {}", cursor)))
+}
+
+fn entity_id(key: &Key) -> Result<EntityId, Status> {
+    if !key.is_complete() {
+        return Err(invalid(format!("key {} is incomplete", key)));
+    }
+    Ok((key.namespace().unwrap_or("").to_string(), key.path.clone()))
+}
+
+fn to_key(id: &EntityId, project: &str) -> Key {
+    Key {
+        path: id.1.clone(),
+        partition_id: Some(PartitionId {
+            project_id: project.to_string(),
+            namespace_id: if id.0.is_empty() { None } else { Some(id.0.clone()) },
+            database_id: None,
+        }),
+    }
+}
+
+fn to_entity(id: &EntityId, props: &ValueMap, project: &str) -> Entity {
+    Entity {
+        key: Some(to_key(id, project)),
+        properties: Some(props.clone()),
+    }
+}
+
+//
+// filters and ordering
+
+fn matches(filter: &Filter, key: &Key, props: &ValueMap) -> Result<bool, Status> {
+    let f = match *filter {
+        Filter::CompositeFilter(ref c) => {
+            for f in &c.filters {
+                match (c.op, matches(f, key, props)?) {
+                    (CompositeOperator::And, false) => return Ok(false),
+                    (CompositeOperator::Or, true) => return Ok(true),
+                    _ => {}
+                }
+            }
+            return Ok(c.op == CompositeOperator::And);
+        }
+        Filter::PropertyFilter(ref f) => f,
+    };
+
+    if f.op == Operator::HasAncestor {
+        return match f.value.as_key() {
+            Some(ancestor) => Ok(key.path.starts_with(&ancestor.path)),
+            None => Err(invalid("HAS_ANCESTOR requires a key")),
+        };
+    }
+    let operands = match (f.op, f.value.as_array()) {
+        (Operator::In, Some(values)) | (Operator::NotIn, Some(values)) => values.to_vec(),
+        (Operator::In, None) | (Operator::NotIn, None) => {
+            return Err(invalid("IN and NOT_IN require an array"))
+        }
+        _ => vec![f.value.clone()],
+    };
+
+    let key_value;
+    let value = if f.property.name == KEY_PROPERTY {
+        key_value = Value::key(key.clone());
+        &key_value
+    } else {
+        match props.get(&f.property.name) {
+            Some(v) => v,
+            None => return Ok(false),
+        }
+    };
+
+    // an array property matches if any of its values does; inequalities only match values
+    // of the operand's type, since each type has its own range of the index
+    Ok(indexed_values(value).into_iter().any(|v| {
+        let mut ords = operands.iter().map(|o| cmp_values(v, o));
+        let same_type = || operands.iter().all(|o| rank(&v.value_type) == rank(&o.value_type));
+        match f.op {
+            Operator::Equal | Operator::In => ords.any(|o| o == Ordering::Equal),
+            Operator::NotEqual | Operator::NotIn => ords.all(|o| o != Ordering::Equal),
+            Operator::LessThan => same_type() && ords.all(|o| o == Ordering::Less),
+            Operator::LessThanOrEqual => same_type() && ords.all(|o| o != Ordering::Greater),
+            Operator::GreaterThan => same_type() && ords.all(|o| o == Ordering::Greater),
+            Operator::GreaterThanOrEqual => same_type() && ords.all(|o| o != Ordering::Less),
+            Operator::HasAncestor => false,
+        }
+    }))
+}
+
+fn indexed_values(value: &Value) -> Vec<&Value> {
+    match value.value_type {
+        ValueType::Array(ref values) => values.iter().filter(|v| is_indexed(v)).collect(),
+        _ if is_indexed(value) => vec![value],
+        _ => vec![],
+    }
+}
+
+fn is_indexed(value: &Value) -> bool {
+    value.exclude_from_indexes != Some(true)
+}
+
+// the value an entity is ordered by; arrays are ordered by their smallest value when
+// ascending and their largest when descending
+fn order_value(name: &str, direction: Direction, key: &Key, props: &ValueMap) -> Option<Value> {
+    if name == KEY_PROPERTY {
+        return Some(Value::key(key.clone()));
+    }
+    let values = indexed_values(props.get(name)?);
+    let value = match direction {
+        Direction::Ascending => values.into_iter().min_by(|a, b| cmp_values(a, b)),
+        Direction::Descending => values.into_iter().max_by(|a, b| cmp_values(a, b)),
+    };
+    value.cloned()
+}
+
+// the position of a value's type in datastore's ordering of values of different types:
+// https://cloud.google.com/datastore/docs/concepts/entities#value_type_ordering
+fn rank(v: &ValueType) -> u8 {
+    use self::ValueType::*;
+
+    match *v {
+        Null => 0,
+        Integer(_) | Timestamp(_) => 1,
+        Boolean(_) => 2,
+        Blob(_) => 3,
+        String(_) => 4,
+        Double(_) => 5,
+        GeoPoint(_) => 6,
+        Key(_) => 7,
+        Entity(_) => 8,
+        Array(_) => 9,
+    }
+}
+
+// datastore orders values of different types by type, then by value
+fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    use self::ValueType::*;
+
+    // integers and timestamps (as microseconds) are ordered together
+    fn fixed(v: &ValueType) -> i64 {
+        match *v {
+            Integer(i) => i,
+            Timestamp(ref t) => t.timestamp() * 1_000_000 + i64::from(t.timestamp_subsec_micros()),
+            _ => 0,
+        }
+    }
+
+    fn cmp_f64(a: f64, b: f64) -> Ordering {
+        // NaN is ordered before all other doubles
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        }
+    }
+
+    match (&a.value_type, &b.value_type) {
+        (&Boolean(a), &Boolean(b)) => a.cmp(&b),
+        (&Blob(ref a), &Blob(ref b)) => a.cmp(b),
+        (&String(ref a), &String(ref b)) => a.cmp(b),
+        (&Double(a), &Double(b)) => cmp_f64(a, b),
+        (&GeoPoint(a), &GeoPoint(b)) => {
+            cmp_f64(a.latitude, b.latitude).then_with(|| cmp_f64(a.longitude, b.longitude))
+        }
+        (&Key(ref a), &Key(ref b)) => cmp_paths(&a.path, &b.path),
+        (a, b) if rank(a) == 1 && rank(b) == 1 => fixed(a).cmp(&fixed(b)),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+// keys are ordered by path, element by element; ids come before names
+fn cmp_paths(a: &[PathElement], b: &[PathElement]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ord = a.kind.cmp(&b.kind).then_with(|| match (a.id, &a.name, b.id, &b.name) {
+            (Some(a), _, Some(b), _) => a.cmp(&b),
+            (Some(_), _, None, _) => Ordering::Less,
+            (None, _, Some(_), _) => Ordering::Greater,
+            (None, a, None, b) => a.cmp(b),
+        });
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::Utc;
+    use hyper;
+
+    use client::{self, GoogleCloudClient, Transport};
+    use super::super::{CommitMode, Entity, Hub, Key, Mutation, ReadConsistency, ReadOptions,
+                       Transaction, TransactionOptions, Value, ValueMap, VersionConflict};
+    use super::super::query::{Filter, Query};
+    use super::FakeDatastore;
+
+    fn client() -> (Arc<FakeDatastore>, GoogleCloudClient) {
+        let fake = Arc::new(FakeDatastore::new());
+        let client = GoogleCloudClient::with_transport("test", fake.clone());
+        (fake, client)
+    }
+
+    fn entity(key: Key, n: i64) -> Entity {
+        let mut properties = ValueMap::new();
+        properties.insert("n".to_string(), Value::from(n));
+        Entity {
+            key: Some(key),
+            properties: Some(properties),
+        }
+    }
+
+    fn n(hub: &Hub, key: &Key) -> Option<i64> {
+        let properties = hub.lookup_one(key.clone(), None).unwrap();
+        properties.and_then(|p| p["n"].as_i64())
+    }
+
+    fn status(res: client::Result<Vec<super::MutationResult>>) -> String {
+        res.unwrap_err().status().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn insert_and_update() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let a = Key::with_name("Task", "a");
+        let b = Key::with_name("Task", "b");
+
+        hub.insert(vec![entity(a.clone(), 1)]).unwrap();
+        assert_eq!(status(hub.insert(vec![entity(a.clone(), 2)])), "ALREADY_EXISTS");
+        assert_eq!(n(&hub, &a), Some(1));
+
+        assert_eq!(status(hub.update(vec![entity(b.clone(), 1)])), "NOT_FOUND");
+        assert_eq!(n(&hub, &b), None);
+        hub.update(vec![entity(a.clone(), 2)]).unwrap();
+        hub.upsert(vec![entity(b.clone(), 1)]).unwrap();
+        assert_eq!(n(&hub, &a), Some(2));
+        assert_eq!(n(&hub, &b), Some(1));
+
+        // a failed commit applies none of its mutations
+        let c = Key::with_name("Task", "c");
+        assert_eq!(
+            status(hub.insert(vec![entity(c.clone(), 1), entity(a.clone(), 3)])),
+            "ALREADY_EXISTS"
+        );
+        assert_eq!(n(&hub, &c), None);
+
+        hub.delete(vec![a.clone()]).unwrap();
+        assert_eq!(n(&hub, &a), None);
+    }
+
+    #[test]
+    fn base_versions() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let a = Key::with_name("Task", "a");
+        hub.insert(vec![entity(a.clone(), 1)]).unwrap();
+
+        let found = hub.lookup(vec![a.clone()], None).unwrap();
+        let version = found[0].version.clone().unwrap();
+        let new_version = hub.update_if_version(entity(a.clone(), 2), &version).unwrap();
+
+        let err = hub.update_if_version(entity(a.clone(), 3), &version).unwrap_err();
+        let conflict = VersionConflict::of(&err).expect("a conflict");
+        assert_eq!(conflict.keys, vec![a.clone()]);
+        assert_eq!(conflict.results[0].version, Some(new_version.clone()));
+        assert_eq!(n(&hub, &a), Some(2));
+
+        assert!(hub.delete_if_version(a.clone(), &version).is_err());
+        hub.delete_if_version(a.clone(), &new_version).unwrap();
+        assert_eq!(n(&hub, &a), None);
+
+        // a missing entity still has a version, that of the snapshot it's missing from
+        let missing = hub.lookup(vec![a.clone()], None).unwrap().remove(0);
+        assert!(missing.entity.is_none());
+        hub.delete_if_version(a.clone(), &missing.version.unwrap()).unwrap();
+        assert!(hub.delete_if_version(a.clone(), &version).is_err());
+    }
+
+    // records the action of every request, e.g. `commit`
+    #[derive(Default)]
+    struct Recording {
+        fake: FakeDatastore,
+        actions: Mutex<Vec<String>>,
+    }
+
+    impl Transport for Recording {
+        fn handle(&self, method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            let action = uri.path().rsplit(':').next().unwrap_or("").to_string();
+            self.actions.lock().unwrap().push(action);
+            self.fake.handle(method, uri, body)
+        }
+    }
+
+    #[test]
+    fn failed_commits_roll_back() {
+        let transport = Arc::new(Recording::default());
+        let client = GoogleCloudClient::with_transport("test", transport.clone());
+        let hub: Hub = client.hub();
+        let a = Key::with_name("Task", "a");
+        let b = Key::with_name("Task", "b");
+        hub.insert(vec![entity(a.clone(), 1)]).unwrap();
+        transport.actions.lock().unwrap().clear();
+
+        assert_eq!(
+            status(hub.insert(vec![entity(b.clone(), 1), entity(a.clone(), 2)])),
+            "ALREADY_EXISTS"
+        );
+        assert_eq!(
+            *transport.actions.lock().unwrap(),
+            vec!["beginTransaction", "commit", "rollback"]
+        );
+        transport.actions.lock().unwrap().clear();
+
+        let insert = Mutation {
+            insert: Some(entity(a.clone(), 2)),
+            ..Default::default()
+        };
+        assert_eq!(
+            status(hub.commit_all(vec![insert], CommitMode::NonTransactional)),
+            "ALREADY_EXISTS"
+        );
+        assert_eq!(*transport.actions.lock().unwrap(), vec!["commit"]);
+    }
+
+    #[test]
+    fn aborted_transactions() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let a = Key::with_name("Counter", "a");
+        let b = Key::with_name("Counter", "b");
+        hub.insert(vec![entity(a.clone(), 0), entity(b.clone(), 0)]).unwrap();
+
+        // something the transaction read changed
+        let mut txn = Transaction::begin(&hub, TransactionOptions::read_write()).unwrap();
+        txn.lookup(a.clone()).unwrap();
+        hub.upsert(vec![entity(a.clone(), 10)]).unwrap();
+        txn.upsert(entity(a.clone(), 1));
+        assert_eq!(txn.commit().unwrap_err().status(), Some("ABORTED"));
+
+        // something the transaction wrote changed
+        let mut txn = Transaction::begin(&hub, TransactionOptions::read_write()).unwrap();
+        hub.upsert(vec![entity(b.clone(), 10)]).unwrap();
+        txn.upsert(entity(b.clone(), 1));
+        assert_eq!(txn.commit().unwrap_err().status(), Some("ABORTED"));
+        assert_eq!(n(&hub, &a), Some(10));
+        assert_eq!(n(&hub, &b), Some(10));
+
+        // retried with a fresh snapshot
+        let mut attempts = 0;
+        hub.run_in_transaction(TransactionOptions::read_write(), |txn| {
+            attempts += 1;
+            let current = txn.lookup(a.clone())?.and_then(|p| p["n"].as_i64()).unwrap();
+            if attempts == 1 {
+                hub.upsert(vec![entity(a.clone(), 20)])?;
+            }
+            txn.upsert(entity(a.clone(), current + 1));
+            Ok(())
+        }).unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(n(&hub, &a), Some(21));
+
+        let mut txn = Transaction::begin(&hub, TransactionOptions::read_only()).unwrap();
+        txn.mutate(Mutation {
+            upsert: Some(entity(a.clone(), 0)),
+            ..Default::default()
+        });
+        assert_eq!(txn.commit().unwrap_err().status(), Some("INVALID_ARGUMENT"));
+    }
+
+    #[test]
+    fn read_options() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let a = Key::with_name("Task", "a");
+        hub.insert(vec![entity(a.clone(), 1)]).unwrap();
+        assert_eq!(n(&hub, &a), Some(1));
+        assert!(hub.lookup_one(a.clone(), ReadOptions::eventual()).unwrap().is_some());
+
+        let txn = hub.begin_transaction().unwrap();
+        let invalid = ReadOptions {
+            read_consistency: Some(ReadConsistency::Strong),
+            ..ReadOptions::in_transaction(&txn)
+        };
+        match hub.lookup(vec![a.clone()], invalid.clone()) {
+            Err(client::Error::InvalidArgument(_)) => {}
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+        match hub.run_query("", &Query::new("Task"), invalid) {
+            Err(client::Error::InvalidArgument(_)) => {}
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+        let invalid = ReadOptions {
+            read_time: Some(Utc::now()),
+            ..ReadOptions::eventual()
+        };
+        assert!(invalid.validate().is_err());
+        assert!(ReadOptions::default().validate().is_ok());
+        assert!(ReadOptions::at(Utc::now()).validate().is_ok());
+    }
+
+    #[test]
+    fn paging() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let entities = (1..251).map(|i| entity(Key::with_id("Item", i), i)).collect();
+        hub.insert(entities).unwrap();
+
+        // runQuery returns batches of BATCH_SIZE, resumable from their end cursor
+        let query = Query::new("Item");
+        let first = hub.run_query("", &query, None).unwrap().batch;
+        assert_eq!(first.entity_results.unwrap().len(), super::BATCH_SIZE);
+        let cursor = first.end_cursor.unwrap();
+        let second = hub.run_query("", &query.clone().start_cursor(&cursor), None)
+            .unwrap()
+            .batch;
+        let ids: Vec<i64> = second.entity_results
+            .unwrap()
+            .iter()
+            .map(|r| r.entity.as_ref().unwrap().key.as_ref().unwrap().id().unwrap())
+            .collect();
+        assert_eq!(ids, (101..201).collect::<Vec<_>>());
+
+        let all: Vec<_> = hub.query_iter("", query.clone(), None).map(|r| r.unwrap()).collect();
+        assert_eq!(all.len(), 250);
+
+        let limited: Vec<_> = hub.query_iter("", query.clone().offset(20).limit(150), None)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(limited.len(), 150);
+        let first_id = limited[0].entity.as_ref().unwrap().key.as_ref().unwrap().id();
+        assert_eq!(first_id, Some(21));
+    }
+
+    #[test]
+    fn ordering() {
+        let (_, client) = client();
+        let hub: Hub = client.hub();
+        let mut entities: Vec<Entity> = (1..7).map(|i| entity(Key::with_id("Item", i), i % 3))
+            .collect();
+        // values of different types are ordered by type, e.g. null before integers
+        // and integers before strings
+        entities.push(entity(Key::with_id("Item", 7), 0));
+        entities[6].properties.as_mut().unwrap().insert("n".to_string(), Value::null());
+        let mut string = entity(Key::with_id("Item", 8), 0);
+        string.properties.as_mut().unwrap().insert("n".to_string(), Value::from("x"));
+        entities.push(string);
+        hub.insert(entities).unwrap();
+
+        let ids = |query: Query| -> Vec<i64> {
+            hub.query_iter("", query, None)
+                .map(|r| r.unwrap().entity.unwrap().key.unwrap().id().unwrap())
+                .collect()
+        };
+        assert_eq!(ids(Query::new("Item")), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(ids(Query::new("Item").order_asc("n")), vec![7, 3, 6, 1, 4, 2, 5, 8]);
+        assert_eq!(
+            ids(Query::new("Item").order_desc("n").order_desc("__key__")),
+            vec![8, 5, 2, 4, 1, 6, 3, 7]
+        );
+        assert_eq!(
+            ids(Query::new("Item").filter(Filter::ge("n", 1)).order_asc("n").limit(3)),
+            vec![1, 4, 2]
+        );
+
+        // inequalities only match values of the operand's type
+        assert_eq!(ids(Query::new("Item").filter(Filter::ge("n", 1))), vec![1, 2, 4, 5]);
+        assert_eq!(ids(Query::new("Item").filter(Filter::lt("n", "y"))), vec![8]);
+        assert!(ids(Query::new("Item").filter(Filter::gt("n", Value::null()))).is_empty());
+        assert_eq!(
+            ids(Query::new("Item").filter(Filter::ne("n", 1))),
+            vec![2, 3, 5, 6, 7, 8]
+        );
+    }
+}
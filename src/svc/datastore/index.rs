@@ -0,0 +1,482 @@
+//! Datastore composite index management.
+//!
+//! Queries that filter or sort on more than one property need a composite index.
+//! `Query::required_indexes` works out which ones a query needs, and
+//! `Hub::missing_indexes` which of those don't exist (or aren't ready) yet:
+//!
+//! ```ignore
+//! let query = Query::new("Task").filter(Filter::eq("done", false)).order_desc("priority");
+//! for index in hub.missing_indexes(&query)? {
+//!     let op = hub.create_index(&index)?;
+//!     hub.operations().wait(op, None)?;
+//! }
+//! ```
+//!
+//! https://cloud.google.com/datastore/docs/concepts/indexes
+use std::str::FromStr;
+
+use hyper::Uri;
+
+use client::{self, ApiClient};
+use svc::common::{ListRequest, Operation};
+use super::{Hub, DATASTORE_ROOT};
+use super::query::{CompositeOperator, Direction, Filter, Operator, PropertyFilter, Query,
+                   KEY_PROPERTY};
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    // output only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+
+    // output only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_id: Option<String>,
+
+    pub kind: String,
+
+    pub ancestor: AncestorMode,
+
+    // in index order; the entity key is implicitly the last property
+    pub properties: Vec<IndexedProperty>,
+
+    // output only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<IndexState>,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AncestorMode {
+    AncestorModeUnspecified,
+    // the index can't serve queries with an ancestor filter
+    None,
+    AllAncestors,
+}
+
+impl Default for AncestorMode {
+    fn default() -> Self {
+        AncestorMode::None
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedProperty {
+    pub name: String,
+    pub direction: Direction,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IndexState {
+    StateUnspecified,
+    Creating,
+    // only ready indexes serve queries
+    Ready,
+    Deleting,
+    // the index couldn't be built, e.g. because of an entity with too many index entries
+    Error,
+}
+
+pub type ListIndexesRequest = ListRequest;
+
+#[derive(Clone, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListIndexesResponse {
+    #[serde(default)]
+    pub indexes: Vec<Index>,
+
+    pub next_page_token: Option<String>,
+}
+
+impl Index {
+    pub fn new(kind: &str, ancestor: bool) -> Index {
+        Index {
+            kind: kind.to_string(),
+            ancestor: if ancestor {
+                AncestorMode::AllAncestors
+            } else {
+                AncestorMode::None
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn asc(mut self, property: &str) -> Index {
+        self.push(property, Direction::Ascending);
+        self
+    }
+
+    pub fn desc(mut self, property: &str) -> Index {
+        self.push(property, Direction::Descending);
+        self
+    }
+
+    fn push(&mut self, property: &str, direction: Direction) {
+        if !self.properties.iter().any(|p| p.name == property) {
+            self.properties.push(IndexedProperty {
+                name: property.to_string(),
+                direction: direction,
+            });
+        }
+    }
+
+    /// Whether the index is ready and can serve the queries that need `required`.
+    ///
+    /// The leading properties of `required` that come from equality filters may be in
+    /// any order (and direction) in the index.
+    pub fn serves(&self, required: &Index, equalities: usize) -> bool {
+        if self.state != Some(IndexState::Ready)
+            || self.kind != required.kind
+            || self.ancestor != required.ancestor
+            || self.properties.len() != required.properties.len()
+        {
+            return false;
+        }
+
+        let (eq, rest) = self.properties.split_at(equalities);
+        let (required_eq, required_rest) = required.properties.split_at(equalities);
+        required_eq.iter().all(|r| eq.iter().any(|p| p.name == r.name)) && rest == required_rest
+    }
+}
+
+// the properties of a query (or one of its disjunctions) that determine its index
+struct Shape<'q> {
+    ancestor: bool,
+    equalities: Vec<&'q str>,
+    inequalities: Vec<&'q str>,
+}
+
+impl Query {
+    /// The composite indexes the query needs, with the number of leading equality
+    /// properties in each (see `Index::serves`); empty if built-in indexes suffice.
+    ///
+    /// A query with OR (or IN) filters runs as several queries, and may need an index
+    /// for each of them.
+    pub fn required_indexes(&self) -> Vec<(Index, usize)> {
+        // kindless queries can only filter on keys and ancestors
+        let kind = match self.kind.first() {
+            Some(kind) => &kind.name,
+            None => return vec![],
+        };
+
+        let disjunctions = match self.filter {
+            Some(ref filter) => disjunctions(filter),
+            None => vec![vec![]],
+        };
+
+        let mut required: Vec<(Index, usize)> = vec![];
+        for filters in disjunctions {
+            let shape = Shape::of(&filters);
+            if let Some(index) = self.required_index(kind, &shape) {
+                if !required.iter().any(|r| r.0 == index) {
+                    required.push((index, shape.equalities.len()));
+                }
+            }
+        }
+        required
+    }
+
+    fn required_index(&self, kind: &str, shape: &Shape) -> Option<Index> {
+        // sorts on properties with an equality filter are ignored, as are sorts on the
+        // key in ascending order, which every index ends with
+        let orders: Vec<_> = self.order
+            .iter()
+            .filter(|o| !shape.equalities.contains(&o.property.name.as_str()))
+            .filter(|o| !(o.property.name == KEY_PROPERTY && o.direction == Direction::Ascending))
+            .collect();
+
+        let mut index = Index::new(kind, shape.ancestor);
+        for &p in &shape.equalities {
+            index = index.asc(p);
+        }
+        // inequality properties must be sorted first, in whichever direction is asked for
+        for &p in &shape.inequalities {
+            let direction = orders
+                .iter()
+                .find(|o| o.property.name == p)
+                .map_or(Direction::Ascending, |o| o.direction);
+            index.push(p, direction);
+        }
+        for o in &orders {
+            index.push(&o.property.name, o.direction);
+        }
+        let ordered = index.properties.len();
+        for p in self.projection.iter().map(|p| &p.property).chain(&self.distinct_on) {
+            if p.name != KEY_PROPERTY {
+                index = index.asc(&p.name);
+            }
+        }
+
+        // built-in indexes serve queries with only equality (and ancestor) filters, by
+        // merging single property indexes, and queries on a single property
+        let merge_join = shape.inequalities.is_empty() && orders.is_empty() &&
+            index.properties.len() == ordered;
+        let single = !shape.ancestor && index.properties.len() <= 1;
+        if merge_join || single || index.properties.is_empty() {
+            return None;
+        }
+        Some(index)
+    }
+}
+
+impl<'q> Shape<'q> {
+    fn of(filters: &[&'q PropertyFilter]) -> Shape<'q> {
+        let mut shape = Shape {
+            ancestor: false,
+            equalities: vec![],
+            inequalities: vec![],
+        };
+        for f in filters {
+            let name = f.property.name.as_str();
+            match f.op {
+                Operator::HasAncestor => shape.ancestor = true,
+                // key filters are served by the key at the end of every index
+                _ if name == KEY_PROPERTY => {}
+                Operator::Equal | Operator::In => {
+                    if !shape.equalities.contains(&name) {
+                        shape.equalities.push(name);
+                    }
+                }
+                _ => {
+                    if !shape.inequalities.contains(&name) {
+                        shape.inequalities.push(name);
+                    }
+                }
+            }
+        }
+        shape.equalities.sort();
+        shape
+    }
+}
+
+// the filter in disjunctive normal form, i.e. as an OR of ANDs of property filters
+fn disjunctions(filter: &Filter) -> Vec<Vec<&PropertyFilter>> {
+    match *filter {
+        Filter::PropertyFilter(ref f) => vec![vec![f]],
+        Filter::CompositeFilter(ref c) if c.op == CompositeOperator::Or => {
+            c.filters.iter().flat_map(disjunctions).collect()
+        }
+        Filter::CompositeFilter(ref c) => {
+            c.filters.iter().fold(vec![vec![]], |acc, f| {
+                let mut product = vec![];
+                for left in &acc {
+                    for right in disjunctions(f) {
+                        let mut and = left.clone();
+                        and.extend(right);
+                        product.push(and);
+                    }
+                }
+                product
+            })
+        }
+    }
+}
+
+impl<'a> Hub<'a> {
+    pub fn list_indexes(&self, req: &ListIndexesRequest) -> client::Result<ListIndexesResponse> {
+        let uri = self.mk_index_uri(&format!("?{}", req.to_query()));
+        self.get(&uri, &[])
+    }
+
+    pub fn get_index(&self, index_id: &str) -> client::Result<Index> {
+        let uri = self.mk_index_uri(&format!("/{}", index_id));
+        self.get(&uri, &[])
+    }
+
+    /// Starts building a composite index; its `kind`, `ancestor` and `properties`
+    /// define it, and the rest of its fields are ignored.
+    pub fn create_index(&self, index: &Index) -> client::Result<Operation> {
+        let index = Index {
+            project_id: None,
+            index_id: None,
+            state: None,
+            ..index.clone()
+        };
+        let uri = self.mk_index_uri("");
+        self.post(&uri, index, &[])
+    }
+
+    pub fn delete_index(&self, index_id: &str) -> client::Result<Operation> {
+        let uri = self.mk_index_uri(&format!("/{}", index_id));
+        // `Hub::delete` deletes entities
+        ApiClient::delete(self, &uri, &[])
+    }
+
+    /// The composite indexes `query` needs that aren't ready yet, e.g. to create them
+    /// ahead of running the query.
+    pub fn missing_indexes(&self, query: &Query) -> client::Result<Vec<Index>> {
+        let required = query.required_indexes();
+        if required.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut indexes = vec![];
+        let mut req = ListIndexesRequest::default();
+        loop {
+            let res = self.list_indexes(&req)?;
+            indexes.extend(res.indexes);
+            match res.next_page_token {
+                Some(token) => req.page_token = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(
+            required
+                .into_iter()
+                .filter(|&(ref r, eq)| !indexes.iter().any(|i| i.serves(r, eq)))
+                .map(|(r, _)| r)
+                .collect(),
+        )
+    }
+
+    fn mk_index_uri(&self, suffix: &str) -> Uri {
+        let path = format!(
+            "{}/projects/{}/indexes{}",
+            DATASTORE_ROOT,
+            self.project_id(),
+            suffix
+        );
+        Uri::from_str(&path).expect("uri to be valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Key;
+    use super::super::query::{Filter, Operator, Query};
+    use super::{disjunctions, Index, IndexState};
+
+    fn ready(index: Index) -> Index {
+        Index {
+            state: Some(IndexState::Ready),
+            ..index
+        }
+    }
+
+    #[test]
+    fn required_indexes() {
+        let task = || Query::new("Task");
+        let cases = vec![
+            // built-in indexes
+            (task(), vec![]),
+            (task().filter(Filter::eq("done", false)).filter(Filter::eq("owner", "a")), vec![]),
+            (
+                task().ancestor(Key::with_name("List", "a")).filter(Filter::eq("done", false)),
+                vec![],
+            ),
+            (task().filter(Filter::ge("priority", 4)).order_desc("priority"), vec![]),
+            (task().order_asc("priority"), vec![]),
+            (
+                task().filter(Filter::eq("done", false)).order_asc("done").order_asc("__key__"),
+                vec![],
+            ),
+            (Query::default().ancestor(Key::with_name("List", "a")).order_asc("due"), vec![]),
+            // composite indexes
+            (
+                task().filter(Filter::eq("done", false)).order_desc("priority"),
+                vec![(Index::new("Task", false).asc("done").desc("priority"), 1)],
+            ),
+            (
+                task().ancestor(Key::with_name("List", "a")).order_asc("due"),
+                vec![(Index::new("Task", true).asc("due"), 0)],
+            ),
+            (
+                task().filter(Filter::ge("priority", 4)).order_asc("due"),
+                vec![(Index::new("Task", false).asc("priority").asc("due"), 0)],
+            ),
+            (
+                task().filter(Filter::eq("owner", "a")).filter(Filter::eq("done", false))
+                    .filter(Filter::lt("due", 10)).order_desc("due"),
+                vec![(Index::new("Task", false).asc("done").asc("owner").desc("due"), 2)],
+            ),
+            (
+                task().filter(Filter::eq("done", false)).project(&["priority"]),
+                vec![(Index::new("Task", false).asc("done").asc("priority"), 1)],
+            ),
+            // IN is an equality
+            (
+                task().filter(Filter::in_("owner", vec!["a", "b"])).order_asc("due"),
+                vec![(Index::new("Task", false).asc("owner").asc("due"), 1)],
+            ),
+            // OR needs an index per disjunct, each listed once
+            (
+                task()
+                    .filter(Filter::or(vec![Filter::eq("done", false), Filter::eq("owner", "a")]))
+                    .order_asc("due"),
+                vec![
+                    (Index::new("Task", false).asc("done").asc("due"), 1),
+                    (Index::new("Task", false).asc("owner").asc("due"), 1),
+                ],
+            ),
+            (
+                task()
+                    .filter(Filter::or(vec![Filter::eq("done", false), Filter::eq("done", true)]))
+                    .order_asc("due"),
+                vec![(Index::new("Task", false).asc("done").asc("due"), 1)],
+            ),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(query.required_indexes(), expected, "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn disjunctive_normal_form() {
+        // (a OR b) AND (c OR d) AND e
+        let filter = Filter::and(vec![
+            Filter::or(vec![Filter::eq("a", 1), Filter::eq("b", 1)]),
+            Filter::or(vec![Filter::eq("c", 1), Filter::eq("d", 1)]),
+            Filter::ge("e", 1),
+        ]);
+        let names: Vec<Vec<&str>> = disjunctions(&filter)
+            .iter()
+            .map(|and| and.iter().map(|f| f.property.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                vec!["a", "c", "e"],
+                vec!["a", "d", "e"],
+                vec!["b", "c", "e"],
+                vec!["b", "d", "e"],
+            ]
+        );
+        assert_eq!(disjunctions(&Filter::ge("e", 1))[0][0].op, Operator::GreaterThanOrEqual);
+    }
+
+    #[test]
+    fn serves() {
+        let required = Index::new("Task", false).asc("done").asc("owner").desc("due");
+
+        // equality properties in any order and direction
+        assert!(ready(required.clone()).serves(&required, 2));
+        assert!(ready(Index::new("Task", false).desc("owner").asc("done").desc("due"))
+            .serves(&required, 2));
+        assert!(!ready(Index::new("Task", false).asc("owner").asc("done").desc("due"))
+            .serves(&required, 1));
+
+        // the rest must match exactly
+        assert!(!ready(Index::new("Task", false).asc("done").asc("owner").asc("due"))
+            .serves(&required, 2));
+        assert!(!ready(Index::new("Task", false).asc("done").desc("due").asc("owner"))
+            .serves(&required, 2));
+        assert!(!ready(Index::new("Task", false).asc("done").asc("owner"))
+            .serves(&required, 2));
+        assert!(!ready(Index::new("Task", true).asc("done").asc("owner").desc("due"))
+            .serves(&required, 2));
+        assert!(!ready(Index::new("Item", false).asc("done").asc("owner").desc("due"))
+            .serves(&required, 2));
+
+        // only ready indexes serve queries
+        assert!(!required.serves(&required, 2));
+        let creating = Index {
+            state: Some(IndexState::Creating),
+            ..required.clone()
+        };
+        assert!(!creating.serves(&required, 2));
+    }
+}
@@ -0,0 +1,607 @@
+//! Building, formatting and encoding datastore keys.
+//!
+//! Keys are built from their root down:
+//!
+//! ```ignore
+//! let key = Key::with_name("Account", "alice")
+//!     .child_with_id("Task", 42)
+//!     .in_namespace("prod");
+//! ```
+//!
+//! A key's path has a human readable form, e.g. `Account:"alice"/Task:42`, which
+//! `Display` produces and `FromStr` parses (the partition isn't part of it); kinds
+//! containing `:`, `/` or `"` are quoted like names. Keys can also be converted to and
+//! from the legacy url-safe encoding used by App Engine's `Key.urlsafe()`, which does
+//! include the project and namespace.
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use base64;
+use serde_json;
+
+use super::{Key, PartitionId, PathElement};
+
+#[derive(Debug)]
+pub struct KeyError(String);
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for KeyError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PathElement {
+    pub fn with_name(kind: &str, name: &str) -> PathElement {
+        PathElement {
+            kind: kind.to_string(),
+            id: None,
+            name: Some(name.to_string()),
+        }
+    }
+
+    pub fn with_id(kind: &str, id: i64) -> PathElement {
+        PathElement {
+            kind: kind.to_string(),
+            id: Some(id),
+            name: None,
+        }
+    }
+
+    /// An element without an id or name, which datastore assigns an id to on insert.
+    pub fn incomplete(kind: &str) -> PathElement {
+        PathElement {
+            kind: kind.to_string(),
+            id: None,
+            name: None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.id.is_some() || self.name.is_some()
+    }
+}
+
+impl Key {
+    pub fn from_path(path: Vec<PathElement>) -> Key {
+        Key {
+            path: path,
+            partition_id: None,
+        }
+    }
+
+    pub fn with_name(kind: &str, name: &str) -> Key {
+        Key::from_path(vec![PathElement::with_name(kind, name)])
+    }
+
+    pub fn with_id(kind: &str, id: i64) -> Key {
+        Key::from_path(vec![PathElement::with_id(kind, id)])
+    }
+
+    pub fn incomplete(kind: &str) -> Key {
+        Key::from_path(vec![PathElement::incomplete(kind)])
+    }
+
+    pub fn child(mut self, element: PathElement) -> Key {
+        self.path.push(element);
+        self
+    }
+
+    pub fn child_with_name(self, kind: &str, name: &str) -> Key {
+        self.child(PathElement::with_name(kind, name))
+    }
+
+    pub fn child_with_id(self, kind: &str, id: i64) -> Key {
+        self.child(PathElement::with_id(kind, id))
+    }
+
+    pub fn child_incomplete(self, kind: &str) -> Key {
+        self.child(PathElement::incomplete(kind))
+    }
+
+    /// Sets the project; keys without one belong to the project of the request.
+    pub fn in_project(mut self, project_id: &str) -> Key {
+        self.partition_mut().project_id = project_id.to_string();
+        self
+    }
+
+    pub fn in_namespace(mut self, namespace_id: &str) -> Key {
+        self.partition_mut().namespace_id = Some(namespace_id.to_string());
+        self
+    }
+
+    pub fn in_database(mut self, database_id: &str) -> Key {
+        self.partition_mut().database_id = Some(database_id.to_string());
+        self
+    }
+
+    fn partition_mut(&mut self) -> &mut PartitionId {
+        if self.partition_id.is_none() {
+            self.partition_id = Some(PartitionId::default());
+        }
+        self.partition_id.as_mut().unwrap()
+    }
+
+    pub fn kind(&self) -> &str {
+        self.path.last().map(|e| e.kind.as_str()).unwrap_or("")
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.path.last().and_then(|e| e.id)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.path.last().and_then(|e| e.name.as_ref()).map(|n| n.as_str())
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.partition_id
+            .as_ref()
+            .and_then(|p| p.namespace_id.as_ref())
+            .map(|ns| ns.as_str())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.path.is_empty() && self.path.iter().all(|e| e.is_complete())
+    }
+
+    /// Whether only the last path element lacks an id or name, i.e. whether
+    /// datastore can allocate an id for the key.
+    pub fn is_incomplete(&self) -> bool {
+        match self.path.split_last() {
+            Some((last, ancestors)) => {
+                !last.is_complete() && ancestors.iter().all(|e| e.is_complete())
+            }
+            None => false,
+        }
+    }
+
+    /// The key of the parent entity (in the same partition), if any.
+    pub fn parent(&self) -> Option<Key> {
+        if self.path.len() < 2 {
+            return None;
+        }
+        Some(Key {
+            path: self.path[..self.path.len() - 1].to_vec(),
+            partition_id: self.partition_id.clone(),
+        })
+    }
+}
+
+//
+// path format
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, e) in self.path.iter().enumerate() {
+            if i != 0 {
+                f.write_str("/")?;
+            }
+            // kinds are only quoted when they'd otherwise be ambiguous
+            if e.kind.contains(|c| c == ':' || c == '/' || c == '"') {
+                write!(f, "{}", serde_json::Value::from(e.kind.as_str()))?;
+            } else {
+                f.write_str(&e.kind)?;
+            }
+            match (e.id, e.name.as_ref()) {
+                (Some(id), _) => write!(f, ":{}", id)?,
+                // names are quoted (with json escaping) so they can't be mistaken for ids
+                (None, Some(name)) => write!(f, ":{}", serde_json::Value::from(name.as_str()))?,
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Key {
+    type Err = KeyError;
+
+    /// Parses the format produced by `Display`, e.g. `Account:"alice"/Task:42`.
+    fn from_str(s: &str) -> Result<Key, KeyError> {
+        let mut path = vec![];
+        let mut rest = s;
+        loop {
+            let kind = if rest.starts_with('"') {
+                let (kind, end) = parse_quoted(rest, s, "kind")?;
+                rest = &rest[end..];
+                kind
+            } else {
+                let end = rest.find(|c| c == ':' || c == '/').unwrap_or(rest.len());
+                let kind = rest[..end].to_string();
+                rest = &rest[end..];
+                kind
+            };
+            if kind.is_empty() {
+                return Err(KeyError(format!("missing kind in key path {}", s)));
+            }
+
+            if rest.starts_with(':') {
+                rest = &rest[1..];
+                if rest.starts_with('"') {
+                    let (name, end) = parse_quoted(rest, s, "name")?;
+                    path.push(PathElement::with_name(&kind, &name));
+                    rest = &rest[end..];
+                } else {
+                    let end = rest.find('/').unwrap_or(rest.len());
+                    let id = rest[..end]
+                        .parse()
+                        .map_err(|_| KeyError(format!("invalid id in key path {}", s)))?;
+                    path.push(PathElement::with_id(&kind, id));
+                    rest = &rest[end..];
+                }
+            } else {
+                path.push(PathElement::incomplete(&kind));
+            }
+
+            if rest.is_empty() {
+                return Ok(Key::from_path(path));
+            }
+            if !rest.starts_with('/') {
+                return Err(KeyError(format!("unexpected {} in key path {}", rest, s)));
+            }
+            rest = &rest[1..];
+        }
+    }
+}
+
+// parses the json string literal at the start of `rest`, returning it and its length
+fn parse_quoted(rest: &str, s: &str, what: &str) -> Result<(String, usize), KeyError> {
+    let end = quoted_len(rest)
+        .ok_or_else(|| KeyError(format!("unterminated {} in key path {}", what, s)))?;
+    let value = serde_json::from_str(&rest[..end])
+        .map_err(|e| KeyError(format!("invalid {} in key path {}: {}", what, s, e)))?;
+    Ok((value, end))
+}
+
+// the length of the json string literal at the start of `s`, including its quotes
+fn quoted_len(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+//
+// legacy url-safe encoding
+//
+// this is the web-safe base64 (without padding) of App Engine's `Reference` proto:
+//
+//   message Reference {
+//     required string app = 13;
+//     optional string name_space = 20;
+//     required Path path = 14;
+//     optional string database_id = 23;
+//   }
+//   message Path {
+//     repeated group Element = 1 {
+//       required string type = 2;
+//       optional int64 id = 3;
+//       optional string name = 4;
+//     }
+//   }
+
+const APP_TAG: u64 = 13 << 3 | 2;
+const PATH_TAG: u64 = 14 << 3 | 2;
+const NAMESPACE_TAG: u64 = 20 << 3 | 2;
+const DATABASE_TAG: u64 = 23 << 3 | 2;
+const ELEMENT_START_TAG: u64 = 1 << 3 | 3;
+const ELEMENT_END_TAG: u64 = 1 << 3 | 4;
+const TYPE_TAG: u64 = 2 << 3 | 2;
+const ID_TAG: u64 = 3 << 3;
+const NAME_TAG: u64 = 4 << 3 | 2;
+
+impl Key {
+    /// Encodes the key the way App Engine's `Key.urlsafe()` does.
+    ///
+    /// App Engine prefixes project ids with a location, usually `s~`; pass it as
+    /// `location_prefix` for the result to match what an App Engine app produces.
+    pub fn to_legacy_urlsafe(&self, location_prefix: Option<&str>) -> Result<String, KeyError> {
+        let partition = self.partition_id.clone().unwrap_or_default();
+        if partition.project_id.is_empty() {
+            return Err(KeyError("a project id is required to encode a key".into()));
+        }
+        if !self.is_complete() {
+            return Err(KeyError("only complete keys can be encoded".into()));
+        }
+
+        let mut path = vec![];
+        for e in &self.path {
+            put_varint(&mut path, ELEMENT_START_TAG);
+            put_bytes(&mut path, TYPE_TAG, e.kind.as_bytes());
+            if let Some(id) = e.id {
+                put_varint(&mut path, ID_TAG);
+                put_varint(&mut path, id as u64);
+            }
+            if let Some(ref name) = e.name {
+                put_bytes(&mut path, NAME_TAG, name.as_bytes());
+            }
+            put_varint(&mut path, ELEMENT_END_TAG);
+        }
+
+        let app = format!("{}{}", location_prefix.unwrap_or(""), partition.project_id);
+        let mut buf = vec![];
+        put_bytes(&mut buf, APP_TAG, app.as_bytes());
+        put_bytes(&mut buf, PATH_TAG, &path);
+        match partition.namespace_id {
+            Some(ref ns) if !ns.is_empty() => put_bytes(&mut buf, NAMESPACE_TAG, ns.as_bytes()),
+            _ => {}
+        }
+        match partition.database_id {
+            Some(ref db) if !db.is_empty() => put_bytes(&mut buf, DATABASE_TAG, db.as_bytes()),
+            _ => {}
+        }
+        Ok(base64::encode_config(&buf, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// Decodes a key produced by App Engine's `Key.urlsafe()` (or `to_legacy_urlsafe`),
+    /// dropping any location prefix from the project id.
+    pub fn from_legacy_urlsafe(s: &str) -> Result<Key, KeyError> {
+        let bytes = base64::decode_config(s.trim_right_matches('='), base64::URL_SAFE_NO_PAD)
+            .map_err(|e| KeyError(format!("invalid url-safe key: {}", e)))?;
+
+        let mut partition = PartitionId::default();
+        let mut path = None;
+        let mut r = Reader { buf: &bytes };
+        while !r.is_empty() {
+            match r.varint()? {
+                APP_TAG => {
+                    let app = r.string()?;
+                    // e.g. s~my-project
+                    partition.project_id = match app.find('~') {
+                        Some(i) => app[i + 1..].to_string(),
+                        None => app,
+                    };
+                }
+                PATH_TAG => path = Some(read_path(r.bytes()?)?),
+                NAMESPACE_TAG => partition.namespace_id = Some(r.string()?),
+                DATABASE_TAG => partition.database_id = Some(r.string()?),
+                tag => r.skip(tag)?,
+            }
+        }
+
+        let path = path.ok_or_else(|| KeyError("url-safe key has no path".into()))?;
+        Ok(Key {
+            path: path,
+            partition_id: Some(partition),
+        })
+    }
+}
+
+fn read_path(buf: &[u8]) -> Result<Vec<PathElement>, KeyError> {
+    let mut path = vec![];
+    let mut r = Reader { buf: buf };
+    while !r.is_empty() {
+        match r.varint()? {
+            ELEMENT_START_TAG => {}
+            tag => {
+                r.skip(tag)?;
+                continue;
+            }
+        }
+
+        let mut element = PathElement::default();
+        loop {
+            match r.varint()? {
+                TYPE_TAG => element.kind = r.string()?,
+                ID_TAG => element.id = Some(r.varint()? as i64),
+                NAME_TAG => element.name = Some(r.string()?),
+                ELEMENT_END_TAG => break,
+                tag => r.skip(tag)?,
+            }
+        }
+        path.push(element);
+    }
+    Ok(path)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, tag: u64, bytes: &[u8]) {
+    put_varint(buf, tag);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+struct Reader<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn varint(&mut self) -> Result<u64, KeyError> {
+        let mut v = 0u64;
+        for (i, b) in self.buf.iter().enumerate().take(10) {
+            v |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(v);
+            }
+        }
+        Err(KeyError("truncated url-safe key".into()))
+    }
+
+    fn bytes(&mut self) -> Result<&'b [u8], KeyError> {
+        let len = self.varint()? as usize;
+        if len > self.buf.len() {
+            return Err(KeyError("truncated url-safe key".into()));
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, KeyError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| KeyError("invalid utf-8 in url-safe key".into()))
+    }
+
+    // skips the value of an unknown field
+    fn skip(&mut self, tag: u64) -> Result<(), KeyError> {
+        match tag & 7 {
+            0 => self.varint().map(|_| ()),
+            1 => self.advance(8),
+            2 => self.bytes().map(|_| ()),
+            5 => self.advance(4),
+            _ => Err(KeyError(format!("unsupported field {} in url-safe key", tag >> 3))),
+        }
+    }
+
+    fn advance(&mut self, n: usize) -> Result<(), KeyError> {
+        if n > self.buf.len() {
+            return Err(KeyError("truncated url-safe key".into()));
+        }
+        self.buf = &self.buf[n..];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::i64;
+
+    use super::*;
+
+    // the example from App Engine's ndb documentation, Key('Account', 34201) in app hello
+    const URLSAFE_ACCOUNT: &str = "agVoZWxsb3IPCxIHQWNjb3VudBiZiwIM";
+
+    fn urlsafe_round_trip(key: &Key) -> Key {
+        let encoded = key.to_legacy_urlsafe(None).unwrap();
+        Key::from_legacy_urlsafe(&encoded).unwrap()
+    }
+
+    #[test]
+    fn app_engine_urlsafe() {
+        let key = Key::from_legacy_urlsafe(URLSAFE_ACCOUNT).unwrap();
+        assert_eq!(key, Key::with_id("Account", 34201).in_project("hello"));
+        assert_eq!(key.to_legacy_urlsafe(None).unwrap(), URLSAFE_ACCOUNT);
+
+        // a location prefix is dropped from the project, and can be added back
+        let key = key.in_project("s~hello");
+        let prefixed = key.to_legacy_urlsafe(None).unwrap();
+        let key = Key::from_legacy_urlsafe(&prefixed).unwrap();
+        assert_eq!(key.partition_id.as_ref().unwrap().project_id, "hello");
+        assert_eq!(key.to_legacy_urlsafe(Some("s~")).unwrap(), prefixed);
+
+        // padding is accepted too
+        let padding = "==="[..(4 - prefixed.len() % 4) % 4].to_string();
+        assert_eq!(Key::from_legacy_urlsafe(&(prefixed + &padding)).unwrap(), key);
+    }
+
+    #[test]
+    fn urlsafe_round_trips() {
+        let keys = vec![
+            Key::with_id("Task", -1),
+            Key::with_id("Task", i64::MIN),
+            Key::with_id("Task", i64::MAX),
+            Key::with_name("Task", "caf\u{e9}/\"x\":y"),
+            Key::with_name("Account", "alice")
+                .child_with_id("Project", 7)
+                .child_with_name("Task", "t"),
+        ];
+        for key in keys {
+            let key = key.in_project("p");
+            assert_eq!(urlsafe_round_trip(&key), key);
+
+            let key = key.in_namespace("ns").in_database("db");
+            assert_eq!(urlsafe_round_trip(&key), key);
+        }
+    }
+
+    #[test]
+    fn urlsafe_requires_complete_keys_in_a_project() {
+        assert!(Key::with_id("Task", 1).to_legacy_urlsafe(None).is_err());
+        let key = Key::incomplete("Task").in_project("p");
+        assert!(key.to_legacy_urlsafe(None).is_err());
+    }
+
+    #[test]
+    fn malformed_urlsafe() {
+        let bytes = base64::decode_config(URLSAFE_ACCOUNT, base64::URL_SAFE_NO_PAD).unwrap();
+        let encode = |b: &[u8]| base64::encode_config(b, base64::URL_SAFE_NO_PAD);
+
+        for len in 0..bytes.len() {
+            assert!(Key::from_legacy_urlsafe(&encode(&bytes[..len])).is_err(), "{}", len);
+        }
+        let inputs = vec![
+            "not base64!".to_string(),
+            // a varint that never ends
+            encode(&[0xff; 12]),
+            // an end group tag outside of an element
+            encode(&[0x0c]),
+            // a path with an unterminated element
+            encode(&[0x72, 0x02, 0x0b, 0x12]),
+            // invalid utf-8 in the app
+            encode(&[0x6a, 0x01, 0xff, 0x72, 0x00]),
+        ];
+        for input in inputs {
+            assert!(Key::from_legacy_urlsafe(&input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let key = Key::with_name("Account", "alice").child_with_id("Task", -42);
+        assert_eq!(key.to_string(), "Account:\"alice\"/Task:-42");
+
+        let keys = vec![
+            key,
+            Key::with_name("Task", "a \"quoted\" name"),
+            Key::with_name("Task", "a:b/c\\d"),
+            Key::with_name("Task", "42"),
+            Key::with_name("Task", ""),
+            Key::with_id("Task", i64::MIN),
+            Key::with_id("Account", 1).child_incomplete("Task"),
+            Key::with_name("a:b", "x").child_with_id("c/d", 1),
+            Key::with_id("say \"hi\"", 1),
+        ];
+        for key in keys {
+            assert_eq!(key.to_string().parse::<Key>().unwrap(), key, "{}", key);
+        }
+        assert_eq!(Key::with_id("a:b", 1).to_string(), "\"a:b\":1");
+    }
+
+    #[test]
+    fn malformed_paths() {
+        let inputs = vec![
+            "",
+            "/",
+            "Task/",
+            "Task:",
+            "Task:abc",
+            "Task:1x",
+            "Task:\"x",
+            "Task:\"x\"y",
+            "Task:1//Task:2",
+            ":1",
+            "\"Task",
+            "\"\":1",
+            "Task:99999999999999999999",
+        ];
+        for input in inputs {
+            assert!(input.parse::<Key>().is_err(), "{}", input);
+        }
+    }
+}
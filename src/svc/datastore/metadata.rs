@@ -0,0 +1,282 @@
+//! Datastore metadata queries.
+//!
+//! Datastore describes the namespaces, kinds and properties of a database through
+//! the special `__namespace__`, `__kind__` and `__property__` kinds:
+//!
+//! ```ignore
+//! for ns in hub.namespaces(None, None)? {
+//!     for kind in hub.kinds(&ns, None, None)? {
+//!         for prop in hub.properties(&ns, Some(&kind))? {
+//!             println!("{}/{}.{} {:?}", ns, kind, prop.name, prop.representations);
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Ranges are over names, with `start` inclusive and `end` exclusive.
+//!
+//! https://cloud.google.com/datastore/docs/concepts/metadataqueries
+use client;
+use super::{Entity, Filter, Hub, Key, Query, Value};
+use super::query::KEY_PROPERTY;
+
+pub const NAMESPACE_KIND: &str = "__namespace__";
+pub const KIND_KIND: &str = "__kind__";
+pub const PROPERTY_KIND: &str = "__property__";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertyInfo {
+    pub kind: String,
+
+    // properties of embedded entities are named by their path, e.g. `address.city`
+    pub name: String,
+
+    // the value types stored in the property, e.g. INT64, STRING or REFERENCE
+    pub representations: Vec<String>,
+}
+
+impl<'a> Hub<'a> {
+    /// Lists the names of the namespaces with entities; the default namespace is "".
+    pub fn namespaces(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> client::Result<Vec<String>> {
+        let query = Query::new(NAMESPACE_KIND).keys_only();
+        let query = key_range(query, "", start, end, |name| Key::with_name(NAMESPACE_KIND, name));
+
+        self.query_iter("", query, None)
+            .map(|r| {
+                // the default namespace's key has an id instead of a name
+                r.map(|r| last_name(r.entity.and_then(|e| e.key)).unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// Lists the names of the kinds in `ns`.
+    pub fn kinds(
+        &self,
+        ns: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> client::Result<Vec<String>> {
+        let query = Query::new(KIND_KIND).keys_only();
+        let query = key_range(query, ns, start, end, |name| Key::with_name(KIND_KIND, name));
+
+        self.query_iter(ns, query, None)
+            .filter_map(|r| match r {
+                Ok(r) => last_name(r.entity.and_then(|e| e.key)).map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect()
+    }
+
+    /// Lists the indexed properties in `ns`, either of every kind or only of `kind`.
+    pub fn properties(&self, ns: &str, kind: Option<&str>) -> client::Result<Vec<PropertyInfo>> {
+        let mut query = Query::new(PROPERTY_KIND);
+        if let Some(kind) = kind {
+            query = query.ancestor(Key::with_name(KIND_KIND, kind).in_namespace(ns));
+        }
+        self.property_infos(ns, query)
+    }
+
+    /// Lists the indexed properties of `kind` in `ns` whose names are in the range.
+    pub fn properties_in_range(
+        &self,
+        ns: &str,
+        kind: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> client::Result<Vec<PropertyInfo>> {
+        let parent = Key::with_name(KIND_KIND, kind);
+        let query = Query::new(PROPERTY_KIND).ancestor(parent.clone().in_namespace(ns));
+        let query = key_range(query, ns, start, end, |name| {
+            parent.clone().child_with_name(PROPERTY_KIND, name)
+        });
+        self.property_infos(ns, query)
+    }
+
+    fn property_infos(&self, ns: &str, query: Query) -> client::Result<Vec<PropertyInfo>> {
+        self.query_iter(ns, query, None)
+            .filter_map(|r| match r {
+                Ok(r) => r.entity.and_then(property_info).map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect()
+    }
+}
+
+// restricts a metadata query to the keys named within [start, end)
+fn key_range<F>(query: Query, ns: &str, start: Option<&str>, end: Option<&str>, key: F) -> Query
+where
+    F: Fn(&str) -> Key,
+{
+    let mut query = query;
+    if let Some(start) = start {
+        query = query.filter(Filter::ge(KEY_PROPERTY, Value::key(key(start).in_namespace(ns))));
+    }
+    if let Some(end) = end {
+        query = query.filter(Filter::lt(KEY_PROPERTY, Value::key(key(end).in_namespace(ns))));
+    }
+    query
+}
+
+fn last_name(key: Option<Key>) -> Option<String> {
+    key.and_then(|mut k| k.path.pop()).and_then(|e| e.name)
+}
+
+// a __property__ entity's key is __kind__:<kind>/__property__:<name>
+fn property_info(entity: Entity) -> Option<PropertyInfo> {
+    let representation = entity
+        .properties
+        .as_ref()
+        .and_then(|p| p.get("property_representation"));
+    // a property with a single representation has a string rather than an array
+    let representations = match representation.and_then(|v| v.as_array()) {
+        Some(vs) => vs.iter().filter_map(|v| v.as_str()).map(|s| s.to_string()).collect(),
+        None => representation
+            .and_then(|v| v.as_str())
+            .map(|s| vec![s.to_string()])
+            .unwrap_or_default(),
+    };
+
+    let mut path = match entity.key {
+        Some(key) => key.path,
+        None => return None,
+    };
+    match (path.pop().and_then(|p| p.name), path.pop().and_then(|k| k.name)) {
+        (Some(name), Some(kind)) => Some(PropertyInfo {
+            kind: kind,
+            name: name,
+            representations: representations,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper;
+    use serde_json;
+
+    use client::{GoogleCloudClient, Transport};
+    use super::*;
+
+    // answers every runQuery with one batch, keeping the requests' queries
+    struct Canned {
+        batch: &'static str,
+        queries: Mutex<Vec<serde_json::Value>>,
+    }
+
+    impl Transport for Canned {
+        fn handle(&self, _: &hyper::Method, _: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            let mut req: serde_json::Value = serde_json::from_slice(body).unwrap();
+            self.queries.lock().unwrap().push(req["query"].take());
+            (200, format!("{{\"batch\":{}}}", self.batch).into_bytes())
+        }
+    }
+
+    fn canned(batch: &'static str) -> (Arc<Canned>, GoogleCloudClient) {
+        let canned = Arc::new(Canned {
+            batch: batch,
+            queries: Mutex::new(vec![]),
+        });
+        let client = GoogleCloudClient::with_transport("test", canned.clone());
+        (canned, client)
+    }
+
+    fn json(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn namespaces() {
+        let (canned, client) = canned(
+            r#"{"entityResults": [
+                {"entity": {"key": {"path": [{"kind": "__namespace__", "id": "1"}]}}},
+                {"entity": {"key": {"path": [{"kind": "__namespace__", "name": "a"}]}}}
+            ], "moreResults": "NO_MORE_RESULTS"}"#,
+        );
+        let hub: Hub = client.hub();
+        // the default namespace's name is empty
+        assert_eq!(hub.namespaces(None, None).unwrap(), vec!["", "a"]);
+
+        let queries = canned.queries.lock().unwrap();
+        assert_eq!(queries[0]["kind"][0]["name"], "__namespace__");
+        assert_eq!(queries[0]["projection"][0]["property"]["name"], "__key__");
+        assert!(queries[0].get("filter").is_none());
+    }
+
+    #[test]
+    fn properties() {
+        let (canned, client) = canned(
+            r#"{"entityResults": [
+                {"entity": {
+                    "key": {"path": [
+                        {"kind": "__kind__", "name": "Task"},
+                        {"kind": "__property__", "name": "done"}
+                    ]},
+                    "properties": {"property_representation": {"stringValue": "BOOLEAN"}}
+                }},
+                {"entity": {
+                    "key": {"path": [
+                        {"kind": "__kind__", "name": "Task"},
+                        {"kind": "__property__", "name": "owner.name"}
+                    ]},
+                    "properties": {"property_representation": {"arrayValue": {"values": [
+                        {"stringValue": "NULL"}, {"stringValue": "STRING"}
+                    ]}}}
+                }},
+                {"entity": {
+                    "key": {"path": [
+                        {"kind": "__kind__", "name": "Task"},
+                        {"kind": "__property__", "name": "blob"}
+                    ]}
+                }}
+            ], "moreResults": "NO_MORE_RESULTS"}"#,
+        );
+        let hub: Hub = client.hub();
+        let info = |name: &str, representations: Vec<&str>| PropertyInfo {
+            kind: "Task".to_string(),
+            name: name.to_string(),
+            representations: representations.into_iter().map(|s| s.to_string()).collect(),
+        };
+        assert_eq!(
+            hub.properties_in_range("ns", "Task", Some("a"), Some("p")).unwrap(),
+            vec![
+                info("done", vec!["BOOLEAN"]),
+                info("owner.name", vec!["NULL", "STRING"]),
+                info("blob", vec![]),
+            ]
+        );
+
+        // the range is over the keys of the kind's __property__ entities
+        let key = |name: &str| -> serde_json::Value {
+            let mut key = json(
+                r#"{"partitionId": {"namespaceId": "ns"},
+                    "path": [{"kind": "__kind__", "name": "Task"}]}"#,
+            );
+            if !name.is_empty() {
+                let element = format!(r#"{{"kind": "__property__", "name": "{}"}}"#, name);
+                key["path"].as_array_mut().unwrap().push(json(&element));
+            }
+            key
+        };
+        let filter = |op: &str, name: &str| -> serde_json::Value {
+            let mut filter = json(r#"{"propertyFilter": {"property": {"name": "__key__"}}}"#);
+            filter["propertyFilter"]["op"] = serde_json::Value::String(op.to_string());
+            filter["propertyFilter"]["value"] = json("{}");
+            filter["propertyFilter"]["value"]["keyValue"] = key(name);
+            filter
+        };
+        let queries = canned.queries.lock().unwrap();
+        let filters = &queries[0]["filter"]["compositeFilter"]["filters"];
+        assert_eq!(queries[0]["filter"]["compositeFilter"]["op"], "AND");
+        assert_eq!(filters[0], filter("HAS_ANCESTOR", ""));
+        assert_eq!(filters[1], filter("GREATER_THAN_OR_EQUAL", "a"));
+        assert_eq!(filters[2], filter("LESS_THAN", "p"));
+        assert!(filters.get(3).is_none());
+    }
+}
@@ -0,0 +1,986 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::Uri;
+use serde::Serializer;
+
+use client::{self, ApiClient};
+
+pub mod admin;
+pub mod aggregation;
+pub mod entity;
+pub mod fake;
+pub mod index;
+pub mod key;
+pub mod metadata;
+pub mod query;
+pub mod transaction;
+pub mod validate;
+pub mod value;
+
+pub use self::admin::{EntityFilter, ExportEntitiesResponse};
+pub use self::aggregation::AggregationQuery;
+pub use self::index::Index;
+pub use self::key::KeyError;
+pub use self::metadata::PropertyInfo;
+pub use self::query::{Filter, Query, QueryIter};
+pub use self::transaction::{Transaction, TransactionOptions};
+pub use self::validate::{ValidationError, Violation};
+pub use self::value::{LatLng, Value, ValueType};
+pub use svc::common::{ListOperationsRequest, ListOperationsResponse, Operation, Operations, Status};
+
+static DATASTORE_ROOT: &str = "https://datastore.googleapis.com/v1";
+
+// the most mutations a single commit may contain
+const MAX_MUTATIONS_PER_COMMIT: usize = 500;
+// the most keys a single lookup may contain
+const MAX_KEYS_PER_LOOKUP: usize = 1000;
+
+pub struct DatastoreService {}
+pub type Hub<'a> = client::Hub<'a, DatastoreService>;
+
+pub type ValueMap = HashMap<String, Value>;
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BeginTransactionRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_options: Option<TransactionOptions>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BeginTransactionResponse {
+    pub transaction: String,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackTransactionRequest {
+    pub transaction: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackTransactionResponse {}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocateIdsRequest {
+    // keys whose last path element has neither an id nor a name
+    pub keys: Vec<Key>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_id: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocateIdsResponse {
+    // the requested keys, in order, each completed with a newly allocated id
+    #[serde(default)]
+    pub keys: Vec<Key>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReserveIdsRequest {
+    // complete keys whose ids should never be allocated automatically
+    pub keys: Vec<Key>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_id: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReserveIdsResponse {}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitRequest {
+    // required for transactional commits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<CommitMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutations: Option<Vec<Mutation>>,
+}
+
+#[derive(Copy, Clone, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CommitMode {
+    Transactional,
+    // applies the mutations without a transaction, saving the beginTransaction
+    // round trip; the mutations may not be applied all or none
+    NonTransactional,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutation_results: Option<Vec<MutationResult>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_updates: Option<i32>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RunQueryRequest {
+    partition_id: PartitionId,
+    read_options: ReadOptions,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<Query>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gql_query: Option<GqlQuery>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlQuery {
+    query_string: String,
+    allow_literals: bool,
+    named_bindings: HashMap<String, GqlQueryParameter>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GqlQueryParameter {
+    value: Value,
+}
+
+impl GqlQuery {
+    fn new<B>(q: &str, bindings: B) -> GqlQuery
+    where
+        B: IntoIterator<Item = (String, Value)>,
+    {
+        GqlQuery {
+            query_string: q.to_string(),
+            allow_literals: false,
+            named_bindings: bindings
+                .into_iter()
+                .map(|(k, v)| (k, GqlQueryParameter { value: v }))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RunQueryResponse {
+    pub batch: QueryResultBatch,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResultBatch {
+    pub entity_results: Option<Vec<EntityResult>>,
+
+    // FULL, PROJECTION or KEY_ONLY
+    pub entity_result_type: Option<String>,
+
+    // the number of results skipped due to the query offset
+    #[serde(default)]
+    pub skipped_results: i32,
+
+    pub skipped_cursor: Option<String>,
+
+    // the cursor after the last result in the batch
+    pub end_cursor: Option<String>,
+
+    pub more_results: Option<MoreResultsType>,
+
+    // int64 serialized as a string
+    pub snapshot_version: Option<String>,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MoreResultsType {
+    MoreResultsTypeUnspecified,
+    // there may be more results after the end cursor
+    NotFinished,
+    MoreResultsAfterLimit,
+    MoreResultsAfterCursor,
+    NoMoreResults,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Mutation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insert: Option<Entity>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upsert: Option<Entity>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<Entity>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<Key>,
+}
+
+impl Mutation {
+    /// The key of the entity written or deleted.
+    pub fn key(&self) -> Option<&Key> {
+        let entity = self.insert.as_ref().or(self.upsert.as_ref()).or(self.update.as_ref());
+        match entity {
+            Some(entity) => entity.key.as_ref(),
+            None => self.delete.as_ref(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MutationResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict_detected: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<Key>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Vec<Key>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_options: Option<ReadOptions>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub found: Option<Vec<EntityResult>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing: Option<Vec<EntityResult>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deferred: Option<Vec<Key>>,
+}
+
+/// How a lookup or query reads; at most one of the fields may be set.
+///
+/// Every lookup and query method accepts anything that converts into read options,
+/// which includes a transaction id (`&str` or `Option<&str>`) for existing callers.
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_consistency: Option<ReadConsistency>,
+
+    // reads a snapshot of the database as of this time (at most an hour ago, or up
+    // to 7 days ago with point-in-time recovery enabled)
+    #[serde(serialize_with = "serialize_timestamp")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReadConsistency {
+    Strong,
+    // may return stale results, but is faster and can't be contended
+    Eventual,
+}
+
+impl ReadOptions {
+    pub fn in_transaction(txn: &str) -> ReadOptions {
+        ReadOptions {
+            transaction: Some(txn.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn strong() -> ReadOptions {
+        ReadOptions {
+            read_consistency: Some(ReadConsistency::Strong),
+            ..Default::default()
+        }
+    }
+
+    pub fn eventual() -> ReadOptions {
+        ReadOptions {
+            read_consistency: Some(ReadConsistency::Eventual),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the database as it was at `read_time`.
+    pub fn at(read_time: DateTime<Utc>) -> ReadOptions {
+        ReadOptions {
+            read_time: Some(read_time),
+            ..Default::default()
+        }
+    }
+
+    /// Fails with `InvalidArgument` if more than one field is set, which datastore
+    /// rejects; every lookup and query checks this before sending its request.
+    pub fn validate(&self) -> client::Result<()> {
+        let set = [
+            self.transaction.is_some(),
+            self.read_consistency.is_some(),
+            self.read_time.is_some(),
+        ];
+        if set.iter().filter(|&&s| s).count() > 1 {
+            return Err(client::Error::InvalidArgument(
+                format!("at most one read option may be set: {:?}", self),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a str> for ReadOptions {
+    fn from(txn: &'a str) -> ReadOptions {
+        ReadOptions::in_transaction(txn)
+    }
+}
+
+impl<'a> From<Option<&'a str>> for ReadOptions {
+    fn from(txn: Option<&'a str>) -> ReadOptions {
+        txn.map(ReadOptions::in_transaction).unwrap_or_default()
+    }
+}
+
+fn serialize_int64<S: Serializer>(v: &Option<i64>, s: S) -> Result<S::Ok, S::Error> {
+    match *v {
+        Some(v) => s.serialize_str(&v.to_string()),
+        None => s.serialize_none(),
+    }
+}
+
+fn serialize_timestamp<S>(t: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match *t {
+        Some(ref t) => s.serialize_str(&t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        None => s.serialize_none(),
+    }
+}
+
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<Entity>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Entity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<HashMap<String, Value>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<Key>,
+}
+
+// (de)serialized by hand as the json mapping of the api, see value.rs
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub path: Vec<PathElement>,
+    pub partition_id: Option<PartitionId>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct PathElement {
+    pub kind: String,
+
+    // int64 is a string in the json mapping of the api
+    #[serde(default, serialize_with = "serialize_int64", deserialize_with = "value::int64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct PartitionId {
+    // defaults to the project of the request when empty
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub project_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_id: Option<String>,
+
+    // unset for the default database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_id: Option<String>,
+}
+
+impl<'a> Hub<'a> {
+    //
+    // api-level operations
+
+    pub fn begin_transaction(&self) -> client::Result<String> {
+        let uri = self.mk_uri("beginTransaction");
+        let req = BeginTransactionRequest::default();
+        self.post::<_, BeginTransactionResponse>(&uri, req, &[])
+            .map(|r| r.transaction)
+    }
+
+    pub fn begin_transaction_with(&self, options: TransactionOptions) -> client::Result<String> {
+        let uri = self.mk_uri("beginTransaction");
+        let req = BeginTransactionRequest { transaction_options: Some(options) };
+        self.post::<_, BeginTransactionResponse>(&uri, req, &[])
+            .map(|r| r.transaction)
+    }
+
+    pub fn rollback(&self, txn: &str) -> client::Result<()> {
+        let uri = self.mk_uri("rollback");
+        let req = RollbackTransactionRequest { transaction: txn.to_string() };
+        self.post::<_, RollbackTransactionResponse>(&uri, req, &[])
+            .map(|_| ())
+    }
+
+    /// Validates the mutations (see `validate`) before committing them.
+    pub fn commit(&self, req: CommitRequest) -> client::Result<CommitResponse> {
+        req.validate()?;
+        let uri = self.mk_uri("commit");
+        self.post(&uri, req, &[])
+    }
+
+    /// Allocates ids for incomplete keys, returning the completed keys in order.
+    pub fn allocate_ids(&self, keys: Vec<Key>) -> client::Result<Vec<Key>> {
+        if let Some(key) = keys.iter().find(|k| !k.is_incomplete()) {
+            return Err(client::Error::InvalidArgument(
+                format!("can't allocate an id for key {}", key),
+            ));
+        }
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let uri = self.mk_uri("allocateIds");
+        let req = AllocateIdsRequest {
+            keys: keys,
+            database_id: None,
+        };
+        self.post::<_, AllocateIdsResponse>(&uri, req, &[]).map(
+            |r| {
+                r.keys
+            },
+        )
+    }
+
+    /// Prevents the ids of complete keys from being allocated, e.g. when migrating
+    /// entities whose ids were assigned elsewhere.
+    pub fn reserve_ids(&self, keys: Vec<Key>) -> client::Result<()> {
+        if let Some(key) = keys.iter().find(|k| !k.is_complete()) {
+            return Err(client::Error::InvalidArgument(
+                format!("can't reserve the id of incomplete key {}", key),
+            ));
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let uri = self.mk_uri("reserveIds");
+        let req = ReserveIdsRequest {
+            keys: keys,
+            database_id: None,
+        };
+        self.post::<_, ReserveIdsResponse>(&uri, req, &[])
+            .map(|_| ())
+    }
+
+    /// Allocates ids for every entity with an incomplete key, filling in the keys in
+    /// place and returning the entities' keys in order.
+    ///
+    /// The entities can then refer to each other by key before they're committed
+    /// together, e.g. with `insert`. Fails if an entity has no key at all.
+    pub fn allocate_entity_ids(&self, entities: &mut [Entity]) -> client::Result<Vec<Key>> {
+        if let Some(i) = entities.iter().position(|e| e.key.is_none()) {
+            return Err(client::Error::InvalidArgument(format!("entity {} has no key", i)));
+        }
+        let incomplete: Vec<usize> = (0..entities.len())
+            .filter(|&i| entities[i].key.as_ref().map_or(false, |k| k.is_incomplete()))
+            .collect();
+        let keys = incomplete
+            .iter()
+            .map(|&i| entities[i].key.clone().unwrap())
+            .collect();
+
+        for (i, key) in incomplete.into_iter().zip(self.allocate_ids(keys)?) {
+            entities[i].key = Some(key);
+        }
+        Ok(entities.iter().map(|e| e.key.clone().unwrap()).collect())
+    }
+
+    /// Allocates ids for incomplete keys (see `allocate_entity_ids`) and then inserts
+    /// the entities, returning their keys in order.
+    pub fn insert_with_allocated_ids(&self, mut entities: Vec<Entity>) -> client::Result<Vec<Key>> {
+        let keys = self.allocate_entity_ids(&mut entities)?;
+        self.insert(entities)?;
+        Ok(keys)
+    }
+
+
+    //
+    // high-level operations
+
+    pub fn insert_entity_auto_id(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        props: ValueMap,
+    ) -> client::Result<i64> {
+        let key = self.mk_key(kind, Some(ns), ancestors, None, None);
+        let mut results = self.insert_one(key, props)?;
+
+        let mut key = results.remove(0).key.expect("key to be valid");
+        Ok(key.path.pop().expect("path to be non-empty").id.expect(
+            "id to be valid",
+        ))
+    }
+
+    pub fn insert_entity_by_name(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        name: &str,
+        props: ValueMap,
+    ) -> client::Result<()> {
+        let key = self.mk_key(kind, Some(ns), ancestors, Some(name), None);
+        self.insert_one(key, props)?;
+        Ok(())
+    }
+
+    fn insert_one(&self, key: Key, props: ValueMap) -> client::Result<Vec<MutationResult>> {
+        let entity = Entity {
+            key: Some(key),
+            properties: Some(props),
+        };
+        self.insert(vec![entity])
+    }
+
+    pub fn lookup_by_id<R: Into<ReadOptions>>(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        id: i64,
+        read: R,
+    ) -> client::Result<Option<ValueMap>> {
+        let key = self.mk_key(kind, Some(ns), ancestors, None, Some(id));
+        self.lookup_one(key, read)
+    }
+
+    pub fn lookup_by_name<R: Into<ReadOptions>>(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        name: &str,
+        read: R,
+    ) -> client::Result<Option<ValueMap>> {
+        let key = self.mk_key(kind, Some(ns), ancestors, Some(name), None);
+        self.lookup_one(key, read)
+    }
+
+    /// Runs a GQL query with named `bindings`. `datastore::fake` doesn't parse GQL and fails
+    /// these with UNIMPLEMENTED, so code tested against it should build a `Query` instead.
+    pub fn gql<R, B>(
+        &self,
+        ns: &str,
+        q: &str,
+        read: R,
+        bindings: B,
+    ) -> client::Result<RunQueryResponse>
+    where
+        R: Into<ReadOptions>,
+        B: IntoIterator<Item = (String, Value)>,
+    {
+        let read_options = read.into();
+        read_options.validate()?;
+        let query = GqlQuery::new(q, bindings);
+
+        let req = RunQueryRequest {
+            partition_id: PartitionId {
+                project_id: self.project_id().to_string(),
+                namespace_id: Some(ns.to_string()),
+                database_id: None,
+            },
+            read_options: read_options,
+            query: None,
+            gql_query: Some(query),
+        };
+
+        let uri = self.mk_uri("runQuery");
+        self.post::<_, RunQueryResponse>(&uri, req, &[])
+    }
+
+    pub fn run_query<R: Into<ReadOptions>>(
+        &self,
+        ns: &str,
+        query: &Query,
+        read: R,
+    ) -> client::Result<RunQueryResponse> {
+        let read_options = read.into();
+        read_options.validate()?;
+
+        let req = RunQueryRequest {
+            partition_id: PartitionId {
+                project_id: self.project_id().to_string(),
+                namespace_id: Some(ns.to_string()),
+                database_id: None,
+            },
+            read_options: read_options,
+            query: Some(query.clone()),
+            gql_query: None,
+        };
+
+        let uri = self.mk_uri("runQuery");
+        self.post::<_, RunQueryResponse>(&uri, req, &[])
+    }
+
+    /// Iterates over every result of `query`, issuing as many `runQuery` calls as needed.
+    ///
+    /// To resume a paginated query, set the query's start cursor to a saved
+    /// `QueryIter::cursor`.
+    pub fn query_iter<'h, R: Into<ReadOptions>>(
+        &'h self,
+        ns: &str,
+        query: Query,
+        read: R,
+    ) -> QueryIter<'h, 'a> {
+        QueryIter::new(self, ns, query, read)
+    }
+
+    // Lookup a key using the given read options:
+    // https://cloud.google.com/datastore/docs/reference/rest/v1/ReadOptions
+    pub fn lookup_one<R>(&self, key: Key, read: R) -> client::Result<Option<ValueMap>>
+    where
+        R: Into<ReadOptions>,
+    {
+        let mut found = self.lookup(vec![key], read)?;
+        Ok(found.remove(0).entity.and_then(|e| e.properties))
+    }
+
+    /// Looks up `keys`, returning a result per key in the same order.
+    ///
+    /// A missing entity's result has no entity, and its version is that of the snapshot
+    /// it was missing from; either version can be passed to `update_if_version` and
+    /// `delete_if_version`. Keys that datastore defers are requested again until every
+    /// key is resolved.
+    pub fn lookup<R>(&self, keys: Vec<Key>, read: R) -> client::Result<Vec<EntityResult>>
+    where
+        R: Into<ReadOptions>,
+    {
+        let read_options = read.into();
+        read_options.validate()?;
+
+        let mut found = HashMap::new();
+        let mut pending = keys.clone();
+        while !pending.is_empty() {
+            let rest = pending.split_off(cmp::min(pending.len(), MAX_KEYS_PER_LOOKUP));
+            let req = LookupRequest {
+                keys: Some(pending),
+                read_options: Some(read_options.clone()),
+            };
+
+            let uri = self.mk_uri("lookup");
+            let res = self.post::<_, LookupResponse>(&uri, req, &[])?;
+
+            for result in res.found.unwrap_or_default() {
+                let id = match result.entity.as_ref().and_then(|e| e.key.as_ref()) {
+                    Some(key) => lookup_id(key),
+                    None => continue,
+                };
+                found.insert(id, result);
+            }
+            // only the version of a missing entity is kept, its entity is just the key
+            for mut result in res.missing.unwrap_or_default() {
+                let id = match result.entity.take().and_then(|e| e.key) {
+                    Some(key) => lookup_id(&key),
+                    None => continue,
+                };
+                found.insert(id, result);
+            }
+
+            pending = rest;
+            pending.extend(res.deferred.unwrap_or_default());
+        }
+
+        Ok(keys.iter()
+            .map(|k| found.get(&lookup_id(k)).cloned().unwrap_or_default())
+            .collect())
+    }
+
+    /// Upserts `entities`, in as many commits as needed.
+    ///
+    /// NOTE each commit is atomic, but a batch larger than 500 entities isn't; see
+    /// `commit_all` to skip the transactions.
+    pub fn upsert(&self, entities: Vec<Entity>) -> client::Result<Vec<MutationResult>> {
+        let mutations = entities.into_iter().map(|e| {
+            Mutation {
+                upsert: Some(e),
+                ..Default::default()
+            }
+        });
+        self.commit_all(mutations.collect(), CommitMode::Transactional)
+    }
+
+    /// Inserts `entities`, in as many commits as needed; see `upsert`.
+    pub fn insert(&self, entities: Vec<Entity>) -> client::Result<Vec<MutationResult>> {
+        let mutations = entities.into_iter().map(|e| {
+            Mutation {
+                insert: Some(e),
+                ..Default::default()
+            }
+        });
+        self.commit_all(mutations.collect(), CommitMode::Transactional)
+    }
+
+    /// Updates `entities`, in as many commits as needed; see `upsert`.
+    pub fn update(&self, entities: Vec<Entity>) -> client::Result<Vec<MutationResult>> {
+        let mutations = entities.into_iter().map(|e| {
+            Mutation {
+                update: Some(e),
+                ..Default::default()
+            }
+        });
+        self.commit_all(mutations.collect(), CommitMode::Transactional)
+    }
+
+    /// Deletes `keys`, in as many commits as needed; see `upsert`.
+    pub fn delete(&self, keys: Vec<Key>) -> client::Result<Vec<MutationResult>> {
+        let mutations = keys.into_iter().map(|k| {
+            Mutation {
+                delete: Some(k),
+                ..Default::default()
+            }
+        });
+        self.commit_all(mutations.collect(), CommitMode::Transactional)
+    }
+
+    /// Updates `entity` only if its current version is `base_version` (as returned
+    /// by a lookup or a previous mutation), returning the new version.
+    ///
+    /// Fails with `Error::Conflict` if the entity was modified in the meantime, see
+    /// `VersionConflict`.
+    pub fn update_if_version(&self, entity: Entity, base_version: &str) -> client::Result<String> {
+        let update = Mutation {
+            update: Some(entity),
+            base_version: Some(base_version.to_string()),
+            ..Default::default()
+        };
+        let mut results = self.commit_all(vec![update], CommitMode::Transactional)?;
+        Ok(results.remove(0).version.unwrap_or_default())
+    }
+
+    /// Deletes `key` only if its current version is `base_version`; see `update_if_version`.
+    pub fn delete_if_version(&self, key: Key, base_version: &str) -> client::Result<()> {
+        let delete = Mutation {
+            delete: Some(key),
+            base_version: Some(base_version.to_string()),
+            ..Default::default()
+        };
+        self.commit_all(vec![delete], CommitMode::Transactional).map(|_| ())
+    }
+
+    /// Commits `mutations` in chunks of at most MAX_MUTATIONS_PER_COMMIT, each in its own
+    /// transaction unless `mode` is `NonTransactional`, which saves a round trip per
+    /// chunk but may leave a failed chunk partially applied.
+    pub fn commit_all(
+        &self,
+        mutations: Vec<Mutation>,
+        mode: CommitMode,
+    ) -> client::Result<Vec<MutationResult>> {
+        // so that an invalid mutation doesn't leave the earlier chunks committed
+        for m in &mutations {
+            m.validate()?;
+        }
+
+        let keys = mutations.iter().map(|m| m.key().cloned()).collect();
+        let mut results = Vec::with_capacity(mutations.len());
+        for chunk in mutations.chunks(MAX_MUTATIONS_PER_COMMIT) {
+            let transaction = match mode {
+                CommitMode::Transactional => Some(self.begin_transaction()?),
+                CommitMode::NonTransactional => None,
+            };
+            let req = CommitRequest {
+                transaction: transaction.clone(),
+                mode: Some(mode),
+                mutations: Some(chunk.to_vec()),
+            };
+            let res = match (self.commit(req), transaction) {
+                (Err(e), Some(txn)) => {
+                    // the rollback is best-effort, as in `run_in_transaction`
+                    let _ = self.rollback(&txn);
+                    return Err(e);
+                }
+                (res, _) => res?,
+            };
+            results.extend(res.mutation_results.unwrap_or_default());
+        }
+
+        check_conflicts(keys, results)
+    }
+
+    pub fn update_by_id(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        id: i64,
+        props: ValueMap,
+    ) -> client::Result<()> {
+        let key = self.mk_key(kind, Some(ns), ancestors, None, Some(id));
+
+        let entity = Entity {
+            key: Some(key),
+            properties: Some(props),
+        };
+        self.update(vec![entity]).map(|_| ())
+    }
+
+    pub fn delete_by_id(
+        &self,
+        kind: &str,
+        ns: &str,
+        ancestors: Vec<PathElement>,
+        id: i64,
+    ) -> client::Result<()> {
+        let key = self.mk_key(kind, Some(ns), ancestors, None, Some(id));
+        self.delete(vec![key]).map(|_| ())
+    }
+
+    fn mk_uri(&self, action: &str) -> Uri {
+        let path = format!(
+            "{}/projects/{}:{}",
+            DATASTORE_ROOT,
+            self.project_id(),
+            action
+        );
+        Uri::from_str(&path).expect("uri to be valid")
+    }
+
+    fn mk_key(
+        &self,
+        kind: &str,
+        ns: Option<&str>,
+        mut path: Vec<PathElement>,
+        name: Option<&str>,
+        id: Option<i64>,
+    ) -> Key {
+        let partition_id = PartitionId {
+            project_id: self.project_id().to_string(),
+            namespace_id: ns.map(|ns| ns.to_string()),
+            database_id: None,
+        };
+
+        path.push(PathElement {
+            kind: kind.to_string(),
+            name: name.map(|name| name.to_string()),
+            id: id,
+        });
+
+        Key {
+            path: path,
+            partition_id: Some(partition_id),
+        }
+    }
+}
+
+// identifies a key within a lookup; datastore fills in the partition's project id
+// in its response, so only the namespace and path are compared
+fn lookup_id(key: &Key) -> (String, Vec<PathElement>) {
+    let ns = key.partition_id.as_ref().and_then(|p| p.namespace_id.clone());
+    (ns.unwrap_or_default(), key.path.clone())
+}
+
+/// The details of a `client::Error::Conflict` from a commit, see `VersionConflict::of`.
+#[derive(Clone, Debug)]
+pub struct VersionConflict {
+    // the keys of the mutations whose base version no longer matched
+    pub keys: Vec<Key>,
+
+    // the results of every mutation in the commit
+    pub results: Vec<MutationResult>,
+}
+
+impl VersionConflict {
+    /// The details of `err`, if it's a conflict between a mutation's base version and
+    /// the entity's current version.
+    pub fn of(err: &client::Error) -> Option<&VersionConflict> {
+        match *err {
+            client::Error::Conflict(ref e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "base version of {} mutations didn't match", self.keys.len())
+    }
+}
+
+impl error::Error for VersionConflict {
+    fn description(&self) -> &str {
+        "datastore mutation's base version didn't match"
+    }
+}
+
+// fails with `Error::Conflict` if any mutation's base version didn't match; `keys` are
+// the keys of the mutations that produced `results`, in order
+fn check_conflicts(
+    keys: Vec<Option<Key>>,
+    results: Vec<MutationResult>,
+) -> client::Result<Vec<MutationResult>> {
+    // only set for mutations with a base version
+    let conflicts: Vec<Key> = keys.into_iter()
+        .zip(&results)
+        .filter(|&(_, r)| r.conflict_detected == Some(true))
+        .filter_map(|(key, _)| key)
+        .collect();
+    if !conflicts.is_empty() {
+        let conflict = VersionConflict {
+            keys: conflicts,
+            results: results,
+        };
+        return Err(client::Error::Conflict(Box::new(conflict)));
+    }
+    Ok(results)
+}
@@ -0,0 +1,616 @@
+//! Structured datastore queries.
+//!
+//! A `Query` is composed with a builder and run with `Hub::run_query`, as an
+//! alternative to building GQL strings:
+//!
+//! ```ignore
+//! let query = Query::new("Task")
+//!     .filter(Filter::eq("done", false))
+//!     .filter(Filter::ge("priority", 4))
+//!     .order_desc("priority")
+//!     .limit(10);
+//! let res = hub.run_query("ns", &query, None)?;
+//! ```
+//!
+//! https://cloud.google.com/datastore/docs/reference/rest/v1/projects/runQuery#Query
+use client;
+use super::{EntityResult, Hub, Key, MoreResultsType, ReadOptions, Value};
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Query {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub projection: Vec<Projection>,
+
+    // at most one kind is supported; an empty list is a kindless query
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kind: Vec<KindExpression>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<PropertyOrder>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub distinct_on: Vec<PropertyReference>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_cursor: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_cursor: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KindExpression {
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyReference {
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Projection {
+    pub property: PropertyReference,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyOrder {
+    pub property: PropertyReference,
+    pub direction: Direction,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Filter {
+    CompositeFilter(CompositeFilter),
+    PropertyFilter(PropertyFilter),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositeFilter {
+    pub op: CompositeOperator,
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CompositeOperator {
+    And,
+    Or,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyFilter {
+    pub property: PropertyReference,
+    pub op: Operator,
+    pub value: Value,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Operator {
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Equal,
+    NotEqual,
+    // the value must be an array of at most 10 values
+    In,
+    NotIn,
+    // the property must be `__key__` and the value a key
+    HasAncestor,
+}
+
+impl Filter {
+    pub fn property<V: Into<Value>>(name: &str, op: Operator, value: V) -> Filter {
+        Filter::PropertyFilter(PropertyFilter {
+            property: PropertyReference { name: name.to_string() },
+            op: op,
+            value: value.into(),
+        })
+    }
+
+    pub fn eq<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::Equal, value)
+    }
+
+    pub fn ne<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::NotEqual, value)
+    }
+
+    pub fn lt<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::LessThan, value)
+    }
+
+    pub fn le<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::LessThanOrEqual, value)
+    }
+
+    pub fn gt<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::GreaterThan, value)
+    }
+
+    pub fn ge<V: Into<Value>>(name: &str, value: V) -> Filter {
+        Filter::property(name, Operator::GreaterThanOrEqual, value)
+    }
+
+    pub fn in_<I, V>(name: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::property(name, Operator::In, Value::array(values))
+    }
+
+    pub fn not_in<I, V>(name: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::property(name, Operator::NotIn, Value::array(values))
+    }
+
+    /// Restricts results to descendants of `key` (including the entity itself).
+    pub fn has_ancestor(key: Key) -> Filter {
+        Filter::property(KEY_PROPERTY, Operator::HasAncestor, Value::key(key))
+    }
+
+    pub fn and(filters: Vec<Filter>) -> Filter {
+        Filter::CompositeFilter(CompositeFilter {
+            op: CompositeOperator::And,
+            filters: filters,
+        })
+    }
+
+    pub fn or(filters: Vec<Filter>) -> Filter {
+        Filter::CompositeFilter(CompositeFilter {
+            op: CompositeOperator::Or,
+            filters: filters,
+        })
+    }
+}
+
+/// The special property name that refers to an entity's key.
+pub const KEY_PROPERTY: &str = "__key__";
+
+impl Query {
+    pub fn new(kind: &str) -> Query {
+        Query {
+            kind: vec![KindExpression { name: kind.to_string() }],
+            ..Default::default()
+        }
+    }
+
+    /// Adds a filter; multiple filters are combined with AND.
+    pub fn filter(mut self, filter: Filter) -> Query {
+        self.filter = Some(match self.filter.take() {
+            None => filter,
+            Some(Filter::CompositeFilter(mut and)) if and.op == CompositeOperator::And => {
+                and.filters.push(filter);
+                Filter::CompositeFilter(and)
+            }
+            Some(existing) => Filter::and(vec![existing, filter]),
+        });
+        self
+    }
+
+    pub fn ancestor(self, key: Key) -> Query {
+        self.filter(Filter::has_ancestor(key))
+    }
+
+    pub fn order_asc(mut self, property: &str) -> Query {
+        self.order.push(PropertyOrder {
+            property: PropertyReference { name: property.to_string() },
+            direction: Direction::Ascending,
+        });
+        self
+    }
+
+    pub fn order_desc(mut self, property: &str) -> Query {
+        self.order.push(PropertyOrder {
+            property: PropertyReference { name: property.to_string() },
+            direction: Direction::Descending,
+        });
+        self
+    }
+
+    /// Only returns the given properties; use `__key__` for a keys-only query.
+    pub fn project(mut self, properties: &[&str]) -> Query {
+        self.projection.extend(properties.iter().map(|p| {
+            Projection { property: PropertyReference { name: p.to_string() } }
+        }));
+        self
+    }
+
+    pub fn keys_only(self) -> Query {
+        self.project(&[KEY_PROPERTY])
+    }
+
+    pub fn distinct_on(mut self, properties: &[&str]) -> Query {
+        self.distinct_on.extend(properties.iter().map(|p| {
+            PropertyReference { name: p.to_string() }
+        }));
+        self
+    }
+
+    pub fn limit(mut self, limit: i32) -> Query {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i32) -> Query {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn start_cursor(mut self, cursor: &str) -> Query {
+        self.start_cursor = Some(cursor.to_string());
+        self
+    }
+
+    pub fn end_cursor(mut self, cursor: &str) -> Query {
+        self.end_cursor = Some(cursor.to_string());
+        self
+    }
+}
+
+/// An iterator over the results of a structured query, see `Hub::query_iter`.
+///
+/// Batches are fetched until datastore reports `NO_MORE_RESULTS`, or until the
+/// query's limit or end cursor has been reached.
+pub struct QueryIter<'h, 'a: 'h> {
+    hub: &'h Hub<'a>,
+    ns: String,
+    read_options: ReadOptions,
+    query: Query,
+    results: ::std::vec::IntoIter<EntityResult>,
+    cursor: Option<String>,
+    more_results: Option<MoreResultsType>,
+    done: bool,
+}
+
+impl<'h, 'a> QueryIter<'h, 'a> {
+    pub fn new<R: Into<ReadOptions>>(hub: &'h Hub<'a>, ns: &str, query: Query, read: R) -> Self {
+        QueryIter {
+            hub: hub,
+            ns: ns.to_string(),
+            read_options: read.into(),
+            cursor: query.start_cursor.clone(),
+            query: query,
+            results: vec![].into_iter(),
+            more_results: None,
+            done: false,
+        }
+    }
+
+    /// The cursor after the last returned result, which can be used to resume the query.
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_ref().map(|c| c.as_str())
+    }
+
+    /// The `moreResults` of the most recent batch.
+    pub fn more_results(&self) -> Option<MoreResultsType> {
+        self.more_results
+    }
+
+    fn fetch_batch(&mut self) -> client::Result<()> {
+        let read_options = self.read_options.clone();
+        let batch = self.hub.run_query(&self.ns, &self.query, read_options)?.batch;
+        let results = batch.entity_results.unwrap_or_default();
+
+        // continue from the end of this batch, accounting for what it consumed
+        self.query.start_cursor = batch.end_cursor.clone();
+        if let Some(offset) = self.query.offset {
+            self.query.offset = Some(::std::cmp::max(offset - batch.skipped_results, 0));
+        }
+        if let Some(limit) = self.query.limit {
+            self.query.limit = Some(::std::cmp::max(limit - results.len() as i32, 0));
+        }
+
+        self.done = match batch.more_results {
+            Some(MoreResultsType::NotFinished) => {
+                batch.end_cursor.is_none() || self.query.limit == Some(0)
+            }
+            _ => true,
+        };
+        if results.is_empty() {
+            self.cursor = batch.end_cursor.or_else(|| self.cursor.take());
+        }
+        self.more_results = batch.more_results;
+        self.results = results.into_iter();
+        Ok(())
+    }
+}
+
+impl<'h, 'a> Iterator for QueryIter<'h, 'a> {
+    type Item = client::Result<EntityResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.results.next() {
+                // the last result of a batch has no cursor of its own in some cases
+                match result.cursor {
+                    Some(ref cursor) => self.cursor = Some(cursor.clone()),
+                    None if self.results.len() == 0 => {
+                        self.cursor = self.query.start_cursor.clone()
+                    }
+                    None => {}
+                }
+                return Some(Ok(result));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fetch_batch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use hyper;
+    use serde_json;
+
+    use client::{GoogleCloudClient, Transport};
+    use super::super::{Hub, MoreResultsType};
+    use super::*;
+
+    // answers runQuery requests with scripted batches, keeping the requests' queries
+    struct Script {
+        batches: Mutex<VecDeque<String>>,
+        queries: Mutex<Vec<serde_json::Value>>,
+    }
+
+    impl Transport for Script {
+        fn handle(&self, _: &hyper::Method, _: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            let mut req: serde_json::Value = serde_json::from_slice(body).unwrap();
+            self.queries.lock().unwrap().push(req["query"].take());
+            let batch = self.batches.lock().unwrap().pop_front().expect("a scripted batch");
+            (200, format!("{{\"batch\":{}}}", batch).into_bytes())
+        }
+    }
+
+    // a batch of entities with the given ids, each with a cursor `c<id>`
+    fn batch(ids: &[i64], skipped: i32, end_cursor: Option<&str>, more: &str) -> String {
+        let results: Vec<String> = ids.iter()
+            .map(|id| {
+                let key = format!(r#"{{"path":[{{"kind":"Item","id":"{}"}}]}}"#, id);
+                format!(r#"{{"entity":{{"key":{}}},"cursor":"c{}"}}"#, key, id)
+            })
+            .collect();
+        let end_cursor = end_cursor.map_or("null".to_string(), |c| format!("{:?}", c));
+        format!(
+            r#"{{"entityResults":[{}],"skippedResults":{},"endCursor":{},"moreResults":"{}"}}"#,
+            results.join(","),
+            skipped,
+            end_cursor,
+            more
+        )
+    }
+
+    fn scripted(batches: Vec<String>) -> Arc<Script> {
+        Arc::new(Script {
+            batches: Mutex::new(batches.into_iter().collect()),
+            queries: Mutex::new(vec![]),
+        })
+    }
+
+    // the ids of every result, and the iterator's final cursor and `moreResults`
+    fn run(
+        script: &Arc<Script>,
+        query: Query,
+    ) -> (Vec<i64>, Option<String>, Option<MoreResultsType>) {
+        let client = GoogleCloudClient::with_transport("test", script.clone());
+        let hub: Hub = client.hub();
+        let mut iter = hub.query_iter("", query, None);
+        let ids = iter.by_ref()
+            .map(|r| r.unwrap().entity.unwrap().key.unwrap().id().unwrap())
+            .collect();
+        (ids, iter.cursor().map(|c| c.to_string()), iter.more_results())
+    }
+
+    fn queries(script: &Arc<Script>) -> Vec<serde_json::Value> {
+        script.queries.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn offset_beyond_a_batch() {
+        let script = scripted(vec![
+            batch(&[], 100, Some("s100"), "NOT_FINISHED"),
+            batch(&[], 40, Some("s140"), "NOT_FINISHED"),
+            batch(&[151, 152], 10, Some("c152"), "NO_MORE_RESULTS"),
+        ]);
+        let (ids, cursor, more) = run(&script, Query::new("Item").offset(150));
+        assert_eq!(ids, vec![151, 152]);
+        assert_eq!(cursor, Some("c152".to_string()));
+        assert_eq!(more, Some(MoreResultsType::NoMoreResults));
+
+        // each batch continues from the last one, with what's left of the offset
+        let queries = queries(&script);
+        assert_eq!(queries.len(), 3);
+        assert_eq!(queries[0]["offset"], 150);
+        assert!(queries[0].get("startCursor").is_none());
+        assert_eq!(queries[1]["offset"], 50);
+        assert_eq!(queries[1]["startCursor"], "s100");
+        assert_eq!(queries[2]["offset"], 10);
+        assert_eq!(queries[2]["startCursor"], "s140");
+    }
+
+    #[test]
+    fn limit_ends_mid_batch() {
+        let script = scripted(vec![
+            batch(&[1, 2], 0, Some("c2"), "NOT_FINISHED"),
+            batch(&[3], 0, Some("c3"), "MORE_RESULTS_AFTER_LIMIT"),
+        ]);
+        let (ids, cursor, more) = run(&script, Query::new("Item").limit(3));
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(cursor, Some("c3".to_string()));
+        assert_eq!(more, Some(MoreResultsType::MoreResultsAfterLimit));
+
+        let queries = queries(&script);
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0]["limit"], 3);
+        assert_eq!(queries[1]["limit"], 1);
+        assert_eq!(queries[1]["startCursor"], "c2");
+    }
+
+    #[test]
+    fn not_finished() {
+        // the limit is used up, so there's no need to ask for more
+        let script = scripted(vec![batch(&[1, 2], 0, Some("c2"), "NOT_FINISHED")]);
+        let (ids, _, more) = run(&script, Query::new("Item").limit(2));
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(more, Some(MoreResultsType::NotFinished));
+        assert_eq!(queries(&script).len(), 1);
+
+        // there's nowhere to continue from
+        let script = scripted(vec![batch(&[1], 0, None, "NOT_FINISHED")]);
+        assert_eq!(run(&script, Query::new("Item")).0, vec![1]);
+        assert_eq!(queries(&script).len(), 1);
+
+        // any other value is final
+        let script = scripted(vec![batch(&[1], 0, Some("c1"), "MORE_RESULTS_AFTER_CURSOR")]);
+        let (ids, cursor, more) = run(&script, Query::new("Item").end_cursor("c1"));
+        assert_eq!(ids, vec![1]);
+        assert_eq!(cursor, Some("c1".to_string()));
+        assert_eq!(more, Some(MoreResultsType::MoreResultsAfterCursor));
+        assert_eq!(queries(&script).len(), 1);
+
+        // an empty batch still moves the cursor
+        let script = scripted(vec![
+            batch(&[], 0, Some("s1"), "NOT_FINISHED"),
+            batch(&[], 0, Some("s2"), "NO_MORE_RESULTS"),
+        ]);
+        let (ids, cursor, _) = run(&script, Query::new("Item").start_cursor("s0"));
+        assert!(ids.is_empty());
+        assert_eq!(cursor, Some("s2".to_string()));
+        assert_eq!(queries(&script)[1]["startCursor"], "s1");
+    }
+
+    #[test]
+    fn serialization() {
+        let query = Query::new("Task")
+            .ancestor(Key::with_name("List", "a"))
+            .filter(Filter::or(vec![
+                Filter::in_("owner", vec!["a", "b"]),
+                Filter::not_in("owner", vec!["c"]),
+                Filter::ne("done", true),
+            ]))
+            .filter(Filter::lt("priority", 4))
+            .filter(Filter::le("priority", 3))
+            .filter(Filter::gt("due", 1))
+            .filter(Filter::ge("due", 2))
+            .filter(Filter::eq("done", false))
+            .order_desc("priority")
+            .order_asc("__key__")
+            .project(&["priority", "done"])
+            .distinct_on(&["priority"])
+            .start_cursor("s")
+            .offset(5)
+            .limit(10);
+        let expected: serde_json::Value = serde_json::from_str(
+            r#"{
+                "projection": [{"property": {"name": "priority"}}, {"property": {"name": "done"}}],
+                "kind": [{"name": "Task"}],
+                "filter": {"compositeFilter": {"op": "AND", "filters": [
+                    {"propertyFilter": {
+                        "property": {"name": "__key__"},
+                        "op": "HAS_ANCESTOR",
+                        "value": {"keyValue": {"path": [{"kind": "List", "name": "a"}]}}
+                    }},
+                    {"compositeFilter": {"op": "OR", "filters": [
+                        {"propertyFilter": {
+                            "property": {"name": "owner"},
+                            "op": "IN",
+                            "value": {"arrayValue": {"values": [
+                                {"stringValue": "a"}, {"stringValue": "b"}
+                            ]}}
+                        }},
+                        {"propertyFilter": {
+                            "property": {"name": "owner"},
+                            "op": "NOT_IN",
+                            "value": {"arrayValue": {"values": [{"stringValue": "c"}]}}
+                        }},
+                        {"propertyFilter": {
+                            "property": {"name": "done"},
+                            "op": "NOT_EQUAL",
+                            "value": {"booleanValue": true}
+                        }}
+                    ]}},
+                    {"propertyFilter": {
+                        "property": {"name": "priority"},
+                        "op": "LESS_THAN",
+                        "value": {"integerValue": "4"}
+                    }},
+                    {"propertyFilter": {
+                        "property": {"name": "priority"},
+                        "op": "LESS_THAN_OR_EQUAL",
+                        "value": {"integerValue": "3"}
+                    }},
+                    {"propertyFilter": {
+                        "property": {"name": "due"},
+                        "op": "GREATER_THAN",
+                        "value": {"integerValue": "1"}
+                    }},
+                    {"propertyFilter": {
+                        "property": {"name": "due"},
+                        "op": "GREATER_THAN_OR_EQUAL",
+                        "value": {"integerValue": "2"}
+                    }},
+                    {"propertyFilter": {
+                        "property": {"name": "done"},
+                        "op": "EQUAL",
+                        "value": {"booleanValue": false}
+                    }}
+                ]}},
+                "order": [
+                    {"property": {"name": "priority"}, "direction": "DESCENDING"},
+                    {"property": {"name": "__key__"}, "direction": "ASCENDING"}
+                ],
+                "distinctOn": [{"name": "priority"}],
+                "startCursor": "s",
+                "offset": 5,
+                "limit": 10
+            }"#,
+        ).unwrap();
+        assert_eq!(serde_json::to_value(&query).unwrap(), expected);
+
+        // and back, e.g. from a saved query
+        let parsed: Query = serde_json::from_value(expected.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), expected);
+
+        let empty: serde_json::Value = serde_json::from_str(r#"{"kind": [{"name": "Task"}]}"#)
+            .unwrap();
+        assert_eq!(serde_json::to_value(&Query::new("Task")).unwrap(), empty);
+    }
+}
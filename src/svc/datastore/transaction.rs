@@ -0,0 +1,244 @@
+//! Datastore transactions.
+//!
+//! `Hub::run_in_transaction` begins a transaction, passes a `Transaction` handle to
+//! a closure, and commits the mutations buffered on the handle once the closure
+//! returns. If the closure fails the transaction is rolled back, and if it (or the
+//! commit) fails with `ABORTED` because of contention, the whole closure is retried:
+//!
+//! ```ignore
+//! hub.run_in_transaction(TransactionOptions::read_write(), |txn| {
+//!     let mut props = txn.lookup(key.clone())?.unwrap_or_default();
+//!     let count = props.get("count").and_then(|v| v.as_i64()).unwrap_or(0);
+//!     props.insert("count".to_string(), Value::from(count + 1));
+//!     txn.upsert(Entity { key: Some(key.clone()), properties: Some(props) });
+//!     Ok(())
+//! })?;
+//! ```
+//!
+//! https://cloud.google.com/datastore/docs/concepts/transactions
+use std::{cmp, thread, time};
+
+use chrono::{DateTime, Utc};
+
+use client;
+use super::{check_conflicts, serialize_timestamp, AggregationQuery, CommitMode, CommitRequest,
+            CommitResponse, Entity, Hub, Key, Mutation, Query, QueryIter, RunQueryResponse, Value,
+            ValueMap};
+
+// the closure is run at most this many times before giving up on contention
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 2000;
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_write: Option<ReadWrite>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<ReadOnly>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadWrite {
+    // the transaction being retried, which lets datastore keep its place in line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_transaction: Option<String>,
+}
+
+#[derive(Clone, Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnly {
+    // reads within the transaction see the database as of this time
+    #[serde(serialize_with = "serialize_timestamp")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_time: Option<DateTime<Utc>>,
+}
+
+impl TransactionOptions {
+    pub fn read_write() -> TransactionOptions {
+        TransactionOptions {
+            read_write: Some(ReadWrite::default()),
+            read_only: None,
+        }
+    }
+
+    /// A read-write transaction that retries `previous`, e.g. after it was aborted.
+    pub fn retry_of(previous: &str) -> TransactionOptions {
+        TransactionOptions {
+            read_write: Some(ReadWrite { previous_transaction: Some(previous.to_string()) }),
+            read_only: None,
+        }
+    }
+
+    pub fn read_only() -> TransactionOptions {
+        TransactionOptions {
+            read_write: None,
+            read_only: Some(ReadOnly::default()),
+        }
+    }
+
+    /// A read-only transaction over a snapshot of the database as of `read_time`.
+    pub fn read_only_at(read_time: DateTime<Utc>) -> TransactionOptions {
+        TransactionOptions {
+            read_write: None,
+            read_only: Some(ReadOnly { read_time: Some(read_time) }),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.is_some()
+    }
+}
+
+/// A handle to an open transaction; reads go through the transaction, and
+/// mutations are buffered until the transaction is committed.
+pub struct Transaction<'h, 'a: 'h> {
+    hub: &'h Hub<'a>,
+    id: String,
+    mutations: Vec<Mutation>,
+}
+
+impl<'h, 'a> Transaction<'h, 'a> {
+    pub fn begin(hub: &'h Hub<'a>, options: TransactionOptions) -> client::Result<Self> {
+        Ok(Transaction {
+            hub: hub,
+            id: hub.begin_transaction_with(options)?,
+            mutations: vec![],
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn lookup(&self, key: Key) -> client::Result<Option<ValueMap>> {
+        self.hub.lookup_one(key, self.id.as_str())
+    }
+
+    pub fn run_query(&self, ns: &str, query: &Query) -> client::Result<RunQueryResponse> {
+        self.hub.run_query(ns, query, self.id.as_str())
+    }
+
+    pub fn run_aggregation_query(&self, ns: &str, query: &AggregationQuery) -> client::Result<ValueMap> {
+        self.hub.run_aggregation_query(ns, query, self.id.as_str())
+    }
+
+    pub fn query_iter(&self, ns: &str, query: Query) -> QueryIter<'h, 'a> {
+        self.hub.query_iter(ns, query, self.id.as_str())
+    }
+
+    pub fn gql<B>(&self, ns: &str, q: &str, bindings: B) -> client::Result<RunQueryResponse>
+    where
+        B: IntoIterator<Item = (String, Value)>,
+    {
+        self.hub.gql(ns, q, self.id.as_str(), bindings)
+    }
+
+    pub fn insert(&mut self, entity: Entity) {
+        self.mutations.push(Mutation {
+            insert: Some(entity),
+            ..Default::default()
+        });
+    }
+
+    pub fn upsert(&mut self, entity: Entity) {
+        self.mutations.push(Mutation {
+            upsert: Some(entity),
+            ..Default::default()
+        });
+    }
+
+    pub fn update(&mut self, entity: Entity) {
+        self.mutations.push(Mutation {
+            update: Some(entity),
+            ..Default::default()
+        });
+    }
+
+    pub fn delete(&mut self, key: Key) {
+        self.mutations.push(Mutation {
+            delete: Some(key),
+            ..Default::default()
+        });
+    }
+
+    /// Buffers any mutation, e.g. an update with a base version.
+    pub fn mutate(&mut self, mutation: Mutation) {
+        self.mutations.push(mutation);
+    }
+
+    /// The mutations buffered so far, in the order they'll be applied.
+    pub fn mutations(&self) -> &[Mutation] {
+        &self.mutations
+    }
+
+    /// Fails with `Error::Conflict` if a mutation's base version didn't match, see
+    /// `VersionConflict`.
+    pub fn commit(self) -> client::Result<CommitResponse> {
+        let keys = self.mutations.iter().map(|m| m.key().cloned()).collect();
+        let req = CommitRequest {
+            transaction: Some(self.id),
+            mode: Some(CommitMode::Transactional),
+            mutations: Some(self.mutations),
+        };
+        let mut res = self.hub.commit(req)?;
+        let results = check_conflicts(keys, res.mutation_results.take().unwrap_or_default())?;
+        res.mutation_results = Some(results);
+        Ok(res)
+    }
+
+    pub fn rollback(self) -> client::Result<()> {
+        self.hub.rollback(&self.id)
+    }
+}
+
+impl<'a> Hub<'a> {
+    /// Runs `f` in a transaction and commits its mutations, see the module docs.
+    ///
+    /// Read-write transactions that are aborted are retried (as a retry of the
+    /// aborted transaction) with exponential backoff, up to `MAX_ATTEMPTS` times.
+    pub fn run_in_transaction<F, T>(&self, options: TransactionOptions, mut f: F) -> client::Result<T>
+    where
+        F: FnMut(&mut Transaction) -> client::Result<T>,
+    {
+        let mut options = options;
+        let mut attempt = 1;
+        loop {
+            let mut txn = Transaction::begin(self, options.clone())?;
+            let id = txn.id.clone();
+
+            let res = match f(&mut txn) {
+                Ok(value) => txn.commit().map(|_| value),
+                Err(e) => {
+                    // the rollback is best-effort, the closure's error is what matters
+                    let _ = txn.rollback();
+                    Err(e)
+                }
+            };
+
+            match res {
+                Err(ref e) if is_aborted(e) && attempt < MAX_ATTEMPTS => {
+                    debug!("transaction {} aborted (attempt {}), retrying", id, attempt);
+                    thread::sleep(backoff(attempt));
+                    if !options.is_read_only() {
+                        options = TransactionOptions::retry_of(&id);
+                    }
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+fn is_aborted(e: &client::Error) -> bool {
+    e.status() == Some("ABORTED")
+}
+
+fn backoff(attempt: u32) -> time::Duration {
+    let ms = INITIAL_BACKOFF_MS.saturating_mul(1 << cmp::min(attempt - 1, 16));
+    time::Duration::from_millis(cmp::min(ms, MAX_BACKOFF_MS))
+}
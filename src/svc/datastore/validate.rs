@@ -0,0 +1,600 @@
+//! Client-side checks of entities and mutations against datastore's limits.
+//!
+//! Datastore rejects a commit that breaks any of its limits with a 400 that doesn't
+//! say which entity or property is at fault. `Hub::commit` runs these checks first,
+//! failing with a `client::Error::InvalidArgument` that names them instead. The
+//! checks can also be run ahead of time, for a structured error:
+//!
+//! ```ignore
+//! if let Err(e) = entity.validate() {
+//!     println!("{:?} is invalid: {}", e.property, e.violation)
+//! }
+//! ```
+//!
+//! Entity sizes are estimated the way datastore documents its storage sizes, and
+//! only the built-in indexes are counted towards an entity's index entries.
+//!
+//! https://cloud.google.com/datastore/docs/concepts/limits
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use client;
+use super::{CommitRequest, Entity, Key, Mutation, Value, ValueType, MAX_MUTATIONS_PER_COMMIT};
+
+pub const MAX_ENTITY_SIZE: usize = 1_048_572;
+pub const MAX_INDEXED_VALUE_SIZE: usize = 1500;
+pub const MAX_INDEX_ENTRIES: usize = 20_000;
+// for kinds, key names and property names
+pub const MAX_NAME_SIZE: usize = 1500;
+pub const MAX_KEY_DEPTH: usize = 100;
+
+// added to the size of every entity
+const ENTITY_OVERHEAD: usize = 32;
+const KEY_OVERHEAD: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationError {
+    // the key of the offending entity, if it has one
+    pub key: Option<Key>,
+
+    // properties of embedded entities are named by their path, e.g. `address.city`
+    pub property: Option<String>,
+
+    pub violation: Violation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    // the estimated size in bytes; `property` is the largest property
+    EntityTooLarge(usize),
+    // the string or blob's size in bytes
+    IndexedValueTooLong(usize),
+    // `property` is the property with the most entries
+    TooManyIndexEntries(usize),
+    // names matching `__.*__` are reserved by datastore
+    ReservedName,
+    // empty or longer than MAX_NAME_SIZE bytes
+    InvalidName,
+    // an array's elements are excluded from indexes individually
+    ExcludedArray,
+    NestedArray,
+    MissingKey,
+    InvalidKey(&'static str),
+    // a mutation must have exactly one of insert, upsert, update or delete
+    InvalidMutation,
+    TooManyMutations(usize),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::EntityTooLarge(size) => write!(
+                f,
+                "entity of about {} bytes is larger than {} bytes",
+                size,
+                MAX_ENTITY_SIZE
+            ),
+            Violation::IndexedValueTooLong(size) => write!(
+                f,
+                "indexed value of {} bytes is longer than {} bytes",
+                size,
+                MAX_INDEXED_VALUE_SIZE
+            ),
+            Violation::TooManyIndexEntries(entries) => write!(
+                f,
+                "{} index entries are more than {}",
+                entries,
+                MAX_INDEX_ENTRIES
+            ),
+            Violation::ReservedName => f.write_str("name is reserved"),
+            Violation::InvalidName => f.write_str("name is empty or too long"),
+            Violation::ExcludedArray => f.write_str("array can't be excluded from indexes"),
+            Violation::NestedArray => f.write_str("array can't contain arrays"),
+            Violation::MissingKey => f.write_str("entity has no key"),
+            Violation::InvalidKey(reason) => write!(f, "invalid key: {}", reason),
+            Violation::InvalidMutation => f.write_str("mutation must have exactly one operation"),
+            Violation::TooManyMutations(n) => write!(
+                f,
+                "{} mutations are more than {} in one commit",
+                n,
+                MAX_MUTATIONS_PER_COMMIT
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref key) = self.key {
+            write!(f, "{}: ", key)?;
+        }
+        if let Some(ref property) = self.property {
+            write!(f, "property {}: ", property)?;
+        }
+        write!(f, "{}", self.violation)
+    }
+}
+
+impl error::Error for ValidationError {
+    fn description(&self) -> &str {
+        "datastore entity or mutation is invalid"
+    }
+}
+
+impl From<ValidationError> for client::Error {
+    fn from(e: ValidationError) -> client::Error {
+        client::Error::InvalidArgument(e.to_string())
+    }
+}
+
+impl CommitRequest {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mutations = match self.mutations {
+            Some(ref mutations) => mutations,
+            None => return Ok(()),
+        };
+        if mutations.len() > MAX_MUTATIONS_PER_COMMIT {
+            return Err(ValidationError {
+                key: None,
+                property: None,
+                violation: Violation::TooManyMutations(mutations.len()),
+            });
+        }
+        for m in mutations {
+            m.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl Mutation {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match (&self.insert, &self.upsert, &self.update, &self.delete) {
+            (&Some(ref e), &None, &None, &None) | (&None, &Some(ref e), &None, &None) => {
+                validate_entity(e, true)
+            }
+            (&None, &None, &Some(ref e), &None) => validate_entity(e, false),
+            (&None, &None, &None, &Some(ref key)) => check_key(key, false).map_err(|v| {
+                ValidationError {
+                    key: Some(key.clone()),
+                    property: None,
+                    violation: v,
+                }
+            }),
+            _ => Err(ValidationError {
+                key: None,
+                property: None,
+                violation: Violation::InvalidMutation,
+            }),
+        }
+    }
+}
+
+impl Entity {
+    /// Checks the entity as it would be inserted or upserted, i.e. allowing an
+    /// incomplete key.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_entity(self, true)
+    }
+}
+
+// the estimated storage size and number of built-in index entries of a value
+#[derive(Copy, Clone, Default)]
+struct Footprint {
+    size: usize,
+    index_entries: usize,
+}
+
+impl Footprint {
+    fn add(&mut self, other: Footprint) {
+        self.size += other.size;
+        self.index_entries += other.index_entries;
+    }
+}
+
+fn validate_entity(entity: &Entity, allow_incomplete: bool) -> Result<(), ValidationError> {
+    let invalid = |property: Option<String>, violation: Violation| ValidationError {
+        key: entity.key.clone(),
+        property: property,
+        violation: violation,
+    };
+
+    let key = match entity.key {
+        Some(ref key) => key,
+        None => return Err(invalid(None, Violation::MissingKey)),
+    };
+    check_key(key, allow_incomplete).map_err(|v| invalid(None, v))?;
+
+    let properties = match entity.properties {
+        Some(ref properties) => properties_footprints(properties, None, true)
+            .map_err(|(p, v)| invalid(Some(p), v))?,
+        None => vec![],
+    };
+
+    let mut total = Footprint {
+        size: key_size(key) + ENTITY_OVERHEAD,
+        index_entries: 0,
+    };
+    for &(_, fp) in &properties {
+        total.add(fp);
+    }
+
+    if total.size > MAX_ENTITY_SIZE {
+        let largest = properties.iter().max_by_key(|p| p.1.size).map(|p| p.0.clone());
+        return Err(invalid(largest, Violation::EntityTooLarge(total.size)));
+    }
+    if total.index_entries > MAX_INDEX_ENTRIES {
+        let most = properties.iter().max_by_key(|p| p.1.index_entries).map(|p| p.0.clone());
+        return Err(invalid(most, Violation::TooManyIndexEntries(total.index_entries)));
+    }
+    Ok(())
+}
+
+fn check_key(key: &Key, allow_incomplete: bool) -> Result<(), Violation> {
+    if key.path.is_empty() {
+        return Err(Violation::InvalidKey("the path is empty"));
+    }
+    if key.path.len() > MAX_KEY_DEPTH {
+        return Err(Violation::InvalidKey("the path is too deep"));
+    }
+
+    let last = key.path.len() - 1;
+    for (i, e) in key.path.iter().enumerate() {
+        if e.kind.is_empty() || e.kind.len() > MAX_NAME_SIZE {
+            return Err(Violation::InvalidKey("a kind is empty or too long"));
+        }
+        if is_reserved(&e.kind) {
+            return Err(Violation::InvalidKey("a kind is reserved"));
+        }
+        if e.id.map_or(false, |id| id <= 0) {
+            return Err(Violation::InvalidKey("an id isn't positive"));
+        }
+        if let Some(ref name) = e.name {
+            if e.id.is_some() {
+                return Err(Violation::InvalidKey("an element has both an id and a name"));
+            }
+            if name.is_empty() || name.len() > MAX_NAME_SIZE {
+                return Err(Violation::InvalidKey("a name is empty or too long"));
+            }
+            if is_reserved(name) {
+                return Err(Violation::InvalidKey("a name is reserved"));
+            }
+        }
+        if !e.is_complete() {
+            if i != last {
+                return Err(Violation::InvalidKey("an ancestor is incomplete"));
+            }
+            if !allow_incomplete {
+                return Err(Violation::InvalidKey("the key is incomplete"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// checks the named properties, returning each one's path and footprint
+fn properties_footprints(
+    properties: &HashMap<String, Value>,
+    parent: Option<&str>,
+    indexed: bool,
+) -> Result<Vec<(String, Footprint)>, (String, Violation)> {
+    // in order, so that the same property is reported each time
+    let mut names: Vec<_> = properties.keys().collect();
+    names.sort();
+
+    let mut footprints = Vec::with_capacity(names.len());
+    for name in names {
+        let path = match parent {
+            Some(parent) => format!("{}.{}", parent, name),
+            None => name.clone(),
+        };
+        if name.is_empty() || name.len() > MAX_NAME_SIZE {
+            return Err((path, Violation::InvalidName));
+        }
+        if is_reserved(name) {
+            return Err((path, Violation::ReservedName));
+        }
+
+        let mut fp = Footprint {
+            size: name.len() + 1,
+            index_entries: 0,
+        };
+        fp.add(value_footprint(&properties[name], &path, indexed, false)?);
+        footprints.push((path, fp));
+    }
+    Ok(footprints)
+}
+
+fn value_footprint(
+    value: &Value,
+    path: &str,
+    indexed: bool,
+    in_array: bool,
+) -> Result<Footprint, (String, Violation)> {
+    let indexed = indexed && value.exclude_from_indexes != Some(true);
+    // an indexed value has an entry in both the ascending and descending built-in index
+    let mut fp = Footprint {
+        size: 0,
+        index_entries: if indexed { 2 } else { 0 },
+    };
+
+    let long = |len: usize| indexed && len > MAX_INDEXED_VALUE_SIZE;
+    match value.value_type {
+        ValueType::Null | ValueType::Boolean(_) => fp.size = 1,
+        ValueType::Integer(_) | ValueType::Double(_) | ValueType::Timestamp(_) => fp.size = 8,
+        ValueType::GeoPoint(_) => fp.size = 16,
+        ValueType::Key(ref key) => fp.size = key_size(key),
+        ValueType::String(ref s) if long(s.len()) => {
+            return Err((path.to_string(), Violation::IndexedValueTooLong(s.len())));
+        }
+        ValueType::String(ref s) => fp.size = s.len() + 1,
+        ValueType::Blob(ref b) if long(b.len()) => {
+            return Err((path.to_string(), Violation::IndexedValueTooLong(b.len())));
+        }
+        ValueType::Blob(ref b) => fp.size = b.len(),
+        // an embedded entity's properties are indexed, rather than the entity itself
+        ValueType::Entity(ref e) => {
+            fp.index_entries = 0;
+            if let Some(ref key) = e.key {
+                fp.size += key_size(key);
+            }
+            if let Some(ref properties) = e.properties {
+                for (_, p) in properties_footprints(properties, Some(path), indexed)? {
+                    fp.add(p);
+                }
+            }
+        }
+        ValueType::Array(_) if in_array => {
+            return Err((path.to_string(), Violation::NestedArray));
+        }
+        ValueType::Array(_) if value.exclude_from_indexes == Some(true) => {
+            return Err((path.to_string(), Violation::ExcludedArray));
+        }
+        // as are an array's elements
+        ValueType::Array(ref values) => {
+            fp.index_entries = 0;
+            for v in values {
+                fp.add(value_footprint(v, path, indexed, true)?);
+            }
+        }
+    }
+    Ok(fp)
+}
+
+fn key_size(key: &Key) -> usize {
+    let path: usize = key.path
+        .iter()
+        .map(|e| {
+            let id = match e.name {
+                Some(ref name) => name.len() + 1,
+                None => 8,
+            };
+            e.kind.len() + 1 + id
+        })
+        .sum();
+    path + KEY_OVERHEAD
+}
+
+fn is_reserved(name: &str) -> bool {
+    name.len() >= 4 && name.starts_with("__") && name.ends_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyper;
+
+    use client::{self, GoogleCloudClient, Transport};
+    use super::super::{CommitMode, CommitRequest, Entity, Hub, Key, Mutation, Value, ValueMap};
+    use super::super::fake::FakeDatastore;
+    use super::*;
+
+    // counts the requests that reach the fake
+    #[derive(Default)]
+    struct Counting {
+        fake: FakeDatastore,
+        calls: Mutex<usize>,
+    }
+
+    impl Transport for Counting {
+        fn handle(&self, method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> (u16, Vec<u8>) {
+            *self.calls.lock().unwrap() += 1;
+            self.fake.handle(method, uri, body)
+        }
+    }
+
+    fn entity(properties: Vec<(&str, Value)>) -> Entity {
+        let properties: ValueMap = properties
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        Entity {
+            key: Some(Key::with_name("Task", "a")),
+            properties: Some(properties),
+        }
+    }
+
+    fn nested(name: &str, value: Value) -> Value {
+        let mut e = entity(vec![(name, value)]);
+        e.key = None;
+        Value::entity(e)
+    }
+
+    fn violation(entity: Entity) -> (Option<String>, Violation) {
+        let e = entity.validate().unwrap_err();
+        assert_eq!(e.key, entity.key);
+        (e.property, e.violation)
+    }
+
+    fn path(p: &str) -> Option<String> {
+        Some(p.to_string())
+    }
+
+    #[test]
+    fn valid() {
+        let long = "x".repeat(MAX_INDEXED_VALUE_SIZE);
+        entity(vec![
+            ("title", Value::from(long.as_str())),
+            ("notes", Value::from("x".repeat(100_000)).unindexed()),
+            ("tags", Value::array(vec!["a", "b"])),
+            ("blobs", Value::array(vec![Value::blob(vec![0; 2000])]).unindexed()),
+            ("address", nested("city", Value::from("Paris"))),
+        ]).validate()
+            .unwrap();
+        Entity {
+            key: Some(Key::incomplete("Task")),
+            properties: None,
+        }.validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn invalid_properties() {
+        let long = "x".repeat(MAX_INDEXED_VALUE_SIZE + 1);
+        assert_eq!(
+            violation(entity(vec![("title", Value::from(long.as_str()))])),
+            (path("title"), Violation::IndexedValueTooLong(1501))
+        );
+        assert_eq!(
+            violation(entity(vec![("address", nested("city", Value::from(long.as_str())))])),
+            (path("address.city"), Violation::IndexedValueTooLong(1501))
+        );
+        assert_eq!(
+            violation(entity(vec![("blob", Value::blob(vec![0; 1501]))])),
+            (path("blob"), Violation::IndexedValueTooLong(1501))
+        );
+        assert_eq!(
+            violation(entity(vec![("__foo__", Value::from(1))])),
+            (path("__foo__"), Violation::ReservedName)
+        );
+        assert_eq!(
+            violation(entity(vec![("address", nested("__foo__", Value::from(1)))])),
+            (path("address.__foo__"), Violation::ReservedName)
+        );
+        assert_eq!(
+            violation(entity(vec![("", Value::from(1))])),
+            (path(""), Violation::InvalidName)
+        );
+
+        let mut excluded = Value::array(vec![1, 2]);
+        excluded.exclude_from_indexes = Some(true);
+        assert_eq!(
+            violation(entity(vec![("tags", excluded)])),
+            (path("tags"), Violation::ExcludedArray)
+        );
+        let nested_array = Value::array(vec![Value::array(vec![1])]);
+        assert_eq!(
+            violation(entity(vec![("tags", nested_array)])),
+            (path("tags"), Violation::NestedArray)
+        );
+    }
+
+    #[test]
+    fn too_large() {
+        // unindexed, so that only the entity's size is at fault
+        let half = || Value::from("x".repeat(MAX_ENTITY_SIZE / 2)).unindexed();
+        let (property, v) = violation(entity(vec![
+            ("a", half()),
+            ("b", Value::from("x".repeat(MAX_ENTITY_SIZE / 2 + 1)).unindexed()),
+            ("c", Value::from(1)),
+        ]));
+        assert_eq!(property, path("b"));
+        match v {
+            Violation::EntityTooLarge(size) => assert!(size > MAX_ENTITY_SIZE),
+            v => panic!("expected EntityTooLarge, got {:?}", v),
+        }
+        entity(vec![("a", half())]).validate().unwrap();
+
+        let values: Vec<Value> = (0..MAX_INDEX_ENTRIES as i64 / 2 + 1).map(Value::from).collect();
+        let (property, v) = violation(entity(vec![
+            ("many", Value::array(values)),
+            ("one", Value::from(1)),
+        ]));
+        assert_eq!(property, path("many"));
+        assert_eq!(v, Violation::TooManyIndexEntries(MAX_INDEX_ENTRIES + 4));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let key = |key: Key| {
+            let mut e = entity(vec![]);
+            e.key = Some(key);
+            violation(e).1
+        };
+        assert_eq!(key(Key::with_id("Task", 0)), Violation::InvalidKey("an id isn't positive"));
+        assert_eq!(key(Key::with_id("Task", -1)), Violation::InvalidKey("an id isn't positive"));
+        assert_eq!(
+            key(Key::incomplete("List").child_with_id("Task", 1)),
+            Violation::InvalidKey("an ancestor is incomplete")
+        );
+        assert_eq!(
+            key(Key::with_name("__Task__", "a")),
+            Violation::InvalidKey("a kind is reserved")
+        );
+        assert_eq!(key(Key::from_path(vec![])), Violation::InvalidKey("the path is empty"));
+
+        let update = Mutation {
+            update: Some(Entity {
+                key: Some(Key::incomplete("Task")),
+                properties: None,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            update.validate().unwrap_err().violation,
+            Violation::InvalidKey("the key is incomplete")
+        );
+        let delete = Mutation {
+            delete: Some(Key::with_id("Task", 0)),
+            ..Default::default()
+        };
+        assert_eq!(delete.validate().unwrap_err().key, Some(Key::with_id("Task", 0)));
+        let empty = Mutation::default();
+        assert_eq!(empty.validate().unwrap_err().violation, Violation::InvalidMutation);
+    }
+
+    #[test]
+    fn commits_fail_before_sending() {
+        let transport = Arc::new(Counting::default());
+        let client = GoogleCloudClient::with_transport("test", transport.clone());
+        let hub: Hub = client.hub();
+        let valid = entity(vec![("n", Value::from(1))]);
+        let invalid = entity(vec![("__n__", Value::from(1))]);
+        let upsert = |e: &Entity| Mutation {
+            upsert: Some(e.clone()),
+            ..Default::default()
+        };
+
+        let req = CommitRequest {
+            mode: Some(CommitMode::NonTransactional),
+            mutations: Some(vec![upsert(&valid), upsert(&invalid)]),
+            ..Default::default()
+        };
+        match hub.commit(req) {
+            Err(client::Error::InvalidArgument(e)) => assert!(e.contains("__n__"), "{}", e),
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+
+        let mut mutations: Vec<Mutation> = (1..1001)
+            .map(|i| upsert(&Entity {
+                key: Some(Key::with_id("Task", i)),
+                properties: None,
+            }))
+            .collect();
+        mutations.push(upsert(&invalid));
+        match hub.commit_all(mutations, CommitMode::Transactional) {
+            Err(client::Error::InvalidArgument(_)) => {}
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+        match hub.upsert(vec![valid.clone(), invalid.clone()]) {
+            Err(client::Error::InvalidArgument(_)) => {}
+            res => panic!("expected InvalidArgument, got {:?}", res),
+        }
+        assert_eq!(*transport.calls.lock().unwrap(), 0);
+
+        hub.upsert(vec![valid]).unwrap();
+        assert!(*transport.calls.lock().unwrap() > 0);
+    }
+}
@@ -0,0 +1,619 @@
+//! Datastore property values.
+//!
+//! The api represents a value as an object with exactly one `*Value` field set,
+//! plus optional `meaning` and `excludeFromIndexes` metadata. Here the value itself
+//! is a `ValueType`, wrapped by `Value` together with the metadata.
+//!
+//! https://cloud.google.com/datastore/docs/reference/rest/v1/projects/runQuery#Value
+use std::fmt;
+
+use base64;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeMap;
+
+use super::{entity, Entity, Key, PartitionId, PathElement};
+
+#[derive(Clone, Default, Debug)]
+pub struct Value {
+    pub value_type: ValueType,
+
+    // set by datastore for some legacy and special values (e.g. 22 for long strings)
+    pub meaning: Option<i32>,
+
+    pub exclude_from_indexes: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ValueType {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Double(f64),
+    Timestamp(DateTime<Utc>),
+    Key(Key),
+    String(String),
+    Blob(Vec<u8>),
+    GeoPoint(LatLng),
+    Entity(Entity),
+    // arrays can't contain other arrays
+    Array(Vec<Value>),
+}
+
+impl Default for ValueType {
+    fn default() -> Self {
+        ValueType::Null
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct LatLng {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Value {
+    pub fn new(value_type: ValueType) -> Value {
+        Value {
+            value_type: value_type,
+            meaning: None,
+            exclude_from_indexes: None,
+        }
+    }
+
+    pub fn null() -> Value {
+        Value::new(ValueType::Null)
+    }
+
+    pub fn key(key: Key) -> Value {
+        Value::new(ValueType::Key(key))
+    }
+
+    pub fn blob(bytes: Vec<u8>) -> Value {
+        Value::new(ValueType::Blob(bytes))
+    }
+
+    pub fn entity(entity: Entity) -> Value {
+        Value::new(ValueType::Entity(entity))
+    }
+
+    pub fn array<I, V>(values: I) -> Value
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Value::new(ValueType::Array(values.into_iter().map(Into::into).collect()))
+    }
+
+    /// Excludes the value from indexes; for arrays this applies to each element,
+    /// since datastore rejects the flag on the array itself.
+    pub fn unindexed(mut self) -> Value {
+        match self.value_type {
+            ValueType::Array(ref mut values) => {
+                for v in values.iter_mut() {
+                    v.exclude_from_indexes = Some(true);
+                }
+            }
+            _ => self.exclude_from_indexes = Some(true),
+        }
+        self
+    }
+
+    pub fn is_null(&self) -> bool {
+        match self.value_type {
+            ValueType::Null => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.value_type {
+            ValueType::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.value_type {
+            ValueType::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.value_type {
+            ValueType::Double(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.value_type {
+            ValueType::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<&DateTime<Utc>> {
+        match self.value_type {
+            ValueType::Timestamp(ref t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn as_key(&self) -> Option<&Key> {
+        match self.value_type {
+            ValueType::Key(ref k) => Some(k),
+            _ => None,
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        match self.value_type {
+            ValueType::Blob(ref b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_geo_point(&self) -> Option<&LatLng> {
+        match self.value_type {
+            ValueType::GeoPoint(ref p) => Some(p),
+            _ => None,
+        }
+    }
+
+    pub fn as_entity(&self) -> Option<&Entity> {
+        match self.value_type {
+            ValueType::Entity(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self.value_type {
+            ValueType::Array(ref a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl From<ValueType> for Value {
+    fn from(v: ValueType) -> Value {
+        Value::new(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::new(ValueType::Boolean(v))
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Value {
+        Value::from(v as i64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Value {
+        Value::new(ValueType::Integer(v))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Value {
+        Value::new(ValueType::Double(v))
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(v: &'a str) -> Value {
+        Value::from(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Value {
+        Value::new(ValueType::String(v))
+    }
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(v: DateTime<Utc>) -> Value {
+        Value::new(ValueType::Timestamp(v))
+    }
+}
+
+impl From<Key> for Value {
+    fn from(v: Key) -> Value {
+        Value::key(v)
+    }
+}
+
+impl From<LatLng> for Value {
+    fn from(v: LatLng) -> Value {
+        Value::new(ValueType::GeoPoint(v))
+    }
+}
+
+impl From<Entity> for Value {
+    fn from(v: Entity) -> Value {
+        Value::entity(v)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(v: Vec<Value>) -> Value {
+        Value::new(ValueType::Array(v))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map(Into::into).unwrap_or_else(Value::null)
+    }
+}
+
+//
+// serde
+
+// wrapped in a newtype struct so that entity's serializer recognizes geo points
+#[derive(Serialize, Deserialize)]
+struct RawLatLng {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl Serialize for LatLng {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let raw = RawLatLng {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        };
+        s.serialize_newtype_struct(entity::GEO_POINT_TOKEN, &raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for LatLng {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<LatLng, D::Error> {
+        let raw: RawLatLng = entity::deserialize_newtype(d, entity::GEO_POINT_TOKEN)?;
+        Ok(LatLng {
+            latitude: raw.latitude,
+            longitude: raw.longitude,
+        })
+    }
+}
+
+// the json mapping, wrapped in a newtype struct so that entity's serializer recognizes keys
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyRef<'a> {
+    path: &'a [PathElement],
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partition_id: Option<&'a PartitionId>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawKey {
+    path: Vec<PathElement>,
+    partition_id: Option<PartitionId>,
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let key = KeyRef {
+            path: &self.path,
+            partition_id: self.partition_id.as_ref(),
+        };
+        s.serialize_newtype_struct(entity::KEY_TOKEN, &key)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Key, D::Error> {
+        let raw: RawKey = entity::deserialize_newtype(d, entity::KEY_TOKEN)?;
+        Ok(Key {
+            path: raw.path,
+            partition_id: raw.partition_id,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct ArrayRef<'a> {
+    values: &'a [Value],
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(None)?;
+        match self.value_type {
+            ValueType::Null => map.serialize_entry("nullValue", &())?,
+            ValueType::Boolean(ref b) => map.serialize_entry("booleanValue", b)?,
+            // int64 is a string in the json mapping of the api
+            ValueType::Integer(ref i) => map.serialize_entry("integerValue", &i.to_string())?,
+            ValueType::Double(d) if d.is_nan() => map.serialize_entry("doubleValue", "NaN")?,
+            ValueType::Double(d) if d.is_infinite() && d > 0.0 => {
+                map.serialize_entry("doubleValue", "Infinity")?
+            }
+            ValueType::Double(d) if d.is_infinite() => {
+                map.serialize_entry("doubleValue", "-Infinity")?
+            }
+            ValueType::Double(ref d) => map.serialize_entry("doubleValue", d)?,
+            ValueType::Timestamp(ref t) => {
+                let t = t.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                map.serialize_entry("timestampValue", &t)?
+            }
+            ValueType::Key(ref k) => map.serialize_entry("keyValue", k)?,
+            ValueType::String(ref s) => map.serialize_entry("stringValue", s)?,
+            ValueType::Blob(ref b) => map.serialize_entry("blobValue", &base64::encode(b))?,
+            ValueType::GeoPoint(ref p) => map.serialize_entry("geoPointValue", p)?,
+            ValueType::Entity(ref e) => map.serialize_entry("entityValue", e)?,
+            ValueType::Array(ref a) => map.serialize_entry("arrayValue", &ArrayRef { values: a })?,
+        }
+        if let Some(ref meaning) = self.meaning {
+            map.serialize_entry("meaning", meaning)?;
+        }
+        if let Some(ref exclude) = self.exclude_from_indexes {
+            map.serialize_entry("excludeFromIndexes", exclude)?;
+        }
+        map.end()
+    }
+}
+
+// the wire format, with every value type as an optional field
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawValue {
+    #[serde(default, deserialize_with = "present")]
+    null_value: Option<()>,
+    boolean_value: Option<bool>,
+    #[serde(default, deserialize_with = "int64")]
+    integer_value: Option<i64>,
+    #[serde(default, deserialize_with = "double")]
+    double_value: Option<f64>,
+    timestamp_value: Option<String>,
+    key_value: Option<Key>,
+    string_value: Option<String>,
+    blob_value: Option<String>,
+    geo_point_value: Option<LatLng>,
+    entity_value: Option<Entity>,
+    array_value: Option<RawArray>,
+    meaning: Option<i32>,
+    exclude_from_indexes: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct RawArray {
+    // NOTE datastore omits this for empty arrays
+    #[serde(default)]
+    values: Vec<Value>,
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        let raw = RawValue::deserialize(d)?;
+
+        let mut types = vec![];
+        if raw.null_value.is_some() {
+            types.push(ValueType::Null);
+        }
+        if let Some(b) = raw.boolean_value {
+            types.push(ValueType::Boolean(b));
+        }
+        if let Some(i) = raw.integer_value {
+            types.push(ValueType::Integer(i));
+        }
+        if let Some(f) = raw.double_value {
+            types.push(ValueType::Double(f));
+        }
+        if let Some(t) = raw.timestamp_value {
+            let t = DateTime::parse_from_rfc3339(&t)
+                .map_err(|e| de::Error::custom(format!("invalid timestampValue {}: {}", t, e)))?;
+            types.push(ValueType::Timestamp(t.with_timezone(&Utc)));
+        }
+        if let Some(k) = raw.key_value {
+            types.push(ValueType::Key(k));
+        }
+        if let Some(s) = raw.string_value {
+            types.push(ValueType::String(s));
+        }
+        if let Some(b) = raw.blob_value {
+            let b = base64::decode(&b)
+                .map_err(|e| de::Error::custom(format!("invalid blobValue: {}", e)))?;
+            types.push(ValueType::Blob(b));
+        }
+        if let Some(p) = raw.geo_point_value {
+            types.push(ValueType::GeoPoint(p));
+        }
+        if let Some(e) = raw.entity_value {
+            types.push(ValueType::Entity(e));
+        }
+        if let Some(a) = raw.array_value {
+            types.push(ValueType::Array(a.values));
+        }
+
+        if types.len() > 1 {
+            return Err(de::Error::custom("value has more than one value type set"));
+        }
+        Ok(Value {
+            // a value without any type set is treated as null as well
+            value_type: types.pop().unwrap_or(ValueType::Null),
+            meaning: raw.meaning,
+            exclude_from_indexes: raw.exclude_from_indexes,
+        })
+    }
+}
+
+// `"nullValue": null` marks a null value, so only the field's presence matters
+fn present<'de, D: Deserializer<'de>>(d: D) -> Result<Option<()>, D::Error> {
+    de::IgnoredAny::deserialize(d)?;
+    Ok(Some(()))
+}
+
+// accepts int64 both as a string (as datastore sends it) and as a number
+pub(super) fn int64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i64>, D::Error> {
+    struct Int64;
+
+    impl<'de> de::Visitor<'de> for Int64 {
+        type Value = i64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an int64 as a string or a number")
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<i64, E> {
+            Ok(v)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<i64, E> {
+            if v > ::std::i64::MAX as u64 {
+                return Err(E::custom(format!("{} is out of range for an int64", v)));
+            }
+            Ok(v as i64)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<i64, E> {
+            v.parse().map_err(|_| E::custom(format!("invalid int64 {}", v)))
+        }
+    }
+
+    d.deserialize_any(Int64).map(Some)
+}
+
+// accepts doubles as numbers and the special "NaN"/"Infinity"/"-Infinity" strings
+fn double<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    struct Double;
+
+    impl<'de> de::Visitor<'de> for Double {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a double")
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<f64, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<f64, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<f64, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<f64, E> {
+            match v {
+                "NaN" => Ok(::std::f64::NAN),
+                "Infinity" => Ok(::std::f64::INFINITY),
+                "-Infinity" => Ok(::std::f64::NEG_INFINITY),
+                _ => v.parse().map_err(|_| E::custom(format!("invalid double {}", v))),
+            }
+        }
+    }
+
+    d.deserialize_any(Double).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn json(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn to_json(value: &Value) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn two_value_types() {
+        let res = serde_json::from_str::<Value>(r#"{"stringValue": "a", "booleanValue": true}"#);
+        assert!(res.is_err());
+        let res = serde_json::from_str::<Value>(r#"{"nullValue": null, "integerValue": "1"}"#);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn int64() {
+        let value = parse(r#"{"integerValue": "-9007199254740993"}"#);
+        assert_eq!(value.as_i64(), Some(-9007199254740993));
+        assert_eq!(to_json(&value), json(r#"{"integerValue": "-9007199254740993"}"#));
+
+        // numbers are accepted too
+        assert_eq!(parse(r#"{"integerValue": 42}"#).as_i64(), Some(42));
+        assert!(serde_json::from_str::<Value>(r#"{"integerValue": "1.5"}"#).is_err());
+        assert!(serde_json::from_str::<Value>(r#"{"integerValue": 9223372036854775808}"#).is_err());
+    }
+
+    #[test]
+    fn special_doubles() {
+        assert!(parse(r#"{"doubleValue": "NaN"}"#).as_f64().unwrap().is_nan());
+        assert_eq!(parse(r#"{"doubleValue": "Infinity"}"#).as_f64(), Some(::std::f64::INFINITY));
+        assert_eq!(
+            parse(r#"{"doubleValue": "-Infinity"}"#).as_f64(),
+            Some(::std::f64::NEG_INFINITY)
+        );
+        assert_eq!(parse(r#"{"doubleValue": 1.5}"#).as_f64(), Some(1.5));
+        assert_eq!(parse(r#"{"doubleValue": 2}"#).as_f64(), Some(2.0));
+
+        for json in &[r#"{"doubleValue":"NaN"}"#, r#"{"doubleValue":"-Infinity"}"#] {
+            assert_eq!(serde_json::to_string(&parse(json)).unwrap(), *json);
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        match parse(r#"{"arrayValue": {}}"#).value_type {
+            ValueType::Array(ref values) => assert!(values.is_empty()),
+            ref v => panic!("expected an array value, got {:?}", v),
+        }
+
+        let array = r#"{"arrayValue": {"values": [{"integerValue": "1"}, {"nullValue": null}]}}"#;
+        assert_eq!(to_json(&parse(array)), json(array));
+    }
+
+    #[test]
+    fn nulls() {
+        assert!(parse(r#"{"nullValue": null}"#).is_null());
+        assert!(parse(r#"{"nullValue": "NULL_VALUE"}"#).is_null());
+        // no value type at all
+        assert!(parse(r#"{"meaning": 1}"#).is_null());
+        assert_eq!(to_json(&Value::null()), json(r#"{"nullValue": null}"#));
+    }
+
+    #[test]
+    fn round_trips() {
+        let fixtures = vec![
+            r#"{"booleanValue": false}"#,
+            r#"{"stringValue": "x", "meaning": 22, "excludeFromIndexes": true}"#,
+            r#"{"timestampValue": "2020-01-02T03:04:05.678901Z"}"#,
+            r#"{"blobValue": "AJ//", "excludeFromIndexes": true}"#,
+            r#"{"geoPointValue": {"latitude": 1.5, "longitude": -2.5}}"#,
+            r#"{"keyValue": {
+                "partitionId": {"projectId": "p", "namespaceId": "ns"},
+                "path": [{"kind": "Task", "id": "-1"}, {"kind": "Note", "name": "a"}]
+            }}"#,
+            r#"{"entityValue": {"properties": {"a": {"integerValue": "1"}}}, "meaning": 9}"#,
+        ];
+        for fixture in fixtures {
+            assert_eq!(to_json(&parse(fixture)), json(fixture), "{}", fixture);
+        }
+
+        let value = parse(r#"{"stringValue": "x", "meaning": 22, "excludeFromIndexes": true}"#);
+        assert_eq!(value.meaning, Some(22));
+        assert_eq!(value.exclude_from_indexes, Some(true));
+    }
+}